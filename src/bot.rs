@@ -0,0 +1,484 @@
+use crate::model;
+use crate::session;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::thread;
+use std::time;
+
+static FLEE_LIFE_RATIO: f64 = 0.3;
+static MAX_CONNECT_FAILURES: u32 = 5;
+
+/// What a strategy sees before choosing its next action.
+#[derive(Clone, Debug, Default)]
+pub struct Observation {
+    pub step: u64,
+    pub status: Option<model::Status>,
+    pub room: Option<model::Room>,
+    /// See [`session::Session::position`].
+    pub position: session::Position,
+    /// See [`session::Session::rooms_entered`].
+    pub rooms_entered: u64,
+    /// Entities of the current room examined so far, by guid.
+    pub examined: HashMap<String, model::Entity>,
+    pub fight: Option<model::Fight>,
+    pub error: Option<model::Error>,
+}
+
+impl Observation {
+    pub fn from_session(session: &session::Session, step: u64) -> Observation {
+        Observation {
+            step,
+            status: session.status.clone(),
+            room: session.status.as_ref().map(|status| status.room.clone()),
+            position: session.position,
+            rooms_entered: session.rooms_entered,
+            examined: session.examined.clone(),
+            fight: session.fight_info.clone(),
+            error: session.error.clone(),
+        }
+    }
+
+    pub fn life_ratio(&self) -> f64 {
+        match &self.status {
+            Some(status) if status.total_life > 0 => {
+                status.life.unwrap_or(status.total_life) as f64 / status.total_life as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Entities in the room other than the player.
+    pub fn others(&self) -> Vec<String> {
+        match (&self.status, &self.room) {
+            (Some(status), Some(room)) => room
+                .entities
+                .iter()
+                .filter(|entity| **entity != status.guid)
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Move(model::Direction),
+    Attack(String),
+    Examine(String),
+    Wait,
+    Disconnect,
+}
+
+impl Action {
+    pub fn to_command(&self) -> Option<session::Command> {
+        match self {
+            Action::Move(direction) => Some(session::Command::Move {
+                direction: direction.clone(),
+            }),
+            Action::Attack(target) => Some(session::Command::Attack {
+                target: target.clone(),
+            }),
+            Action::Examine(target) => Some(session::Command::Examine {
+                target: target.clone(),
+            }),
+            Action::Wait => None,
+            Action::Disconnect => Some(session::Command::Disconnect),
+        }
+    }
+}
+
+pub trait Strategy: Send {
+    fn name(&self) -> &str;
+    fn decide(&mut self, observation: &Observation) -> Action;
+}
+
+/// Explores by favouring the passages it has taken least, examines everything
+/// it meets and only attacks monsters with less life than itself.
+#[derive(Default)]
+pub struct Explorer {
+    taken: HashMap<(session::Position, String), u32>,
+    unexaminable: HashSet<String>,
+    last_action: Option<Action>,
+}
+
+impl Explorer {
+    fn explore(&mut self, observation: &Observation) -> Action {
+        let room = match &observation.room {
+            Some(room) if !room.paths.is_empty() => room,
+            _ => return Action::Wait,
+        };
+
+        let mut paths = room.paths.clone();
+        // Rotate so ties do not always break towards the same passage
+        let rotation = observation.step as usize % paths.len();
+        paths.rotate_left(rotation);
+        let direction = paths
+            .into_iter()
+            .min_by_key(|direction| {
+                self.taken
+                    .get(&(observation.position, format!("{:?}", direction)))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(model::Direction::N);
+
+        *self
+            .taken
+            .entry((observation.position, format!("{:?}", direction)))
+            .or_insert(0) += 1;
+
+        Action::Move(direction)
+    }
+}
+
+impl Strategy for Explorer {
+    fn name(&self) -> &str {
+        "explorer"
+    }
+
+    fn decide(&mut self, observation: &Observation) -> Action {
+        let action = self.choose(observation);
+        self.last_action = Some(action.clone());
+
+        action
+    }
+}
+
+impl Explorer {
+    fn choose(&mut self, observation: &Observation) -> Action {
+        if let (Some(Action::Examine(guid)), Some(_)) = (&self.last_action, &observation.error) {
+            self.unexaminable.insert(guid.clone());
+        }
+        if observation.status.is_none() {
+            return Action::Disconnect;
+        }
+        if observation.life_ratio() < FLEE_LIFE_RATIO {
+            return self.explore(observation);
+        }
+
+        let others = observation.others();
+        if let Some(unknown) = others.iter().find(|guid| {
+            !observation.examined.contains_key(*guid) && !self.unexaminable.contains(*guid)
+        }) {
+            return Action::Examine(unknown.clone());
+        }
+
+        let life = observation
+            .status
+            .as_ref()
+            .and_then(|status| status.life)
+            .unwrap_or(0);
+        let weakest = others
+            .iter()
+            .filter_map(|guid| observation.examined.get(guid).map(|entity| (guid, entity)))
+            .filter(|(_, entity)| {
+                entity.r#type == model::EntityType::Monster && entity.life > 0 && entity.life < life
+            })
+            .min_by_key(|(_, entity)| entity.life);
+
+        match weakest {
+            Some((guid, _)) => Action::Attack(guid.clone()),
+            None => self.explore(observation),
+        }
+    }
+}
+
+/// Reacts to a session driven by someone else, e.g. a human in the TUI.
+pub trait Hooks: Send {
+    fn react(&mut self, observation: &Observation) -> Vec<Action>;
+}
+
+pub fn strategy(name: &str) -> Option<Box<dyn Strategy>> {
+    match name {
+        "explorer" => Some(Box::new(Explorer::default())),
+        _ => None,
+    }
+}
+
+pub struct BotOptions {
+    pub step_delay: time::Duration,
+    pub max_steps: Option<u64>,
+}
+
+/// Drives a [`session::Session`] with a [`Strategy`], one action per step.
+pub struct BotRunner {
+    strategy: Box<dyn Strategy>,
+    options: BotOptions,
+    pub steps: u64,
+    pub last_action: Option<Action>,
+    pub finished: bool,
+    last_step: Option<time::Instant>,
+    connect_failures: u32,
+}
+
+impl BotRunner {
+    pub fn new(strategy: Box<dyn Strategy>, options: BotOptions) -> BotRunner {
+        BotRunner {
+            strategy,
+            options,
+            steps: 0,
+            last_action: None,
+            finished: false,
+            last_step: None,
+            connect_failures: 0,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.strategy.name()
+    }
+
+    // Doubles with each failed connect in a row
+    fn delay(&self) -> time::Duration {
+        self.options.step_delay * 2u32.pow(self.connect_failures)
+    }
+
+    pub fn is_due(&self) -> bool {
+        !self.finished
+            && self
+                .last_step
+                .map_or(true, |last_step| last_step.elapsed() >= self.delay())
+    }
+
+    fn finish(&mut self, reason: &str) {
+        log::info!(
+            target: "bot",
+            "finished strategy={} steps={} reason={}",
+            self.strategy.name(),
+            self.steps,
+            reason
+        );
+        self.finished = true;
+    }
+
+    /// Runs one step and returns whether the bot wants to keep going.
+    pub fn step(&mut self, session: &mut session::Session) -> bool {
+        self.last_step = Some(time::Instant::now());
+        if self.finished {
+            return false;
+        }
+        if !session.is_connected() {
+            session.clear_infos();
+            session.connect();
+            if session.is_connected() {
+                self.connect_failures = 0;
+                session.update();
+            } else {
+                self.connect_failures += 1;
+                log::warn!(
+                    target: "bot",
+                    "connect_failed attempt={}/{}",
+                    self.connect_failures,
+                    MAX_CONNECT_FAILURES
+                );
+                if self.connect_failures >= MAX_CONNECT_FAILURES {
+                    self.finish("connect_failed");
+                }
+            }
+            return !self.finished;
+        }
+
+        let observation = Observation::from_session(session, self.steps);
+        let action = self.strategy.decide(&observation);
+        log::info!(target: "bot", "step={} action={:?}", self.steps, action);
+
+        session.clear_infos();
+        match action.clone() {
+            Action::Move(direction) => {
+                session.r#move(direction);
+                session.update();
+            }
+            Action::Attack(guid) => {
+                session.attack(guid);
+                session.update();
+            }
+            Action::Examine(guid) => {
+                session.look_entity(guid);
+                session.update();
+            }
+            Action::Wait => session.update(),
+            Action::Disconnect => session.disconnect(),
+        }
+        self.steps += 1;
+        self.last_action = Some(action.clone());
+
+        let dead = matches!(
+            &session.error,
+            Some(model::Error {
+                detail: model::ErrorDetail {
+                    r#type: Some(model::ErrorType::Dead),
+                    ..
+                },
+                ..
+            })
+        );
+        if dead {
+            self.finish("death");
+        } else if action == Action::Disconnect {
+            self.finish("disconnect");
+        } else if self
+            .options
+            .max_steps
+            .is_some_and(|max_steps| self.steps >= max_steps)
+        {
+            self.finish("max_steps");
+        }
+
+        !self.finished
+    }
+
+    pub fn run(&mut self, session: &mut session::Session) {
+        while self.step(session) {
+            thread::sleep(self.delay());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net;
+    use crate::sim;
+
+    static BASE_URL: &str = "http://offline";
+
+    fn session() -> session::Session {
+        let path = format!("{}/dungeons/dead_end.toml", env!("CARGO_MANIFEST_DIR"));
+        let world = sim::World::load(path.as_str(), 7).unwrap();
+
+        session::Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(sim::SimTransport::new(BASE_URL.to_string(), world)),
+        ))
+    }
+
+    // Connected and standing next to the troll
+    fn next_to_troll() -> (session::Session, String) {
+        let mut session = session();
+        session.connect();
+        session.r#move(model::Direction::E);
+        session.update();
+        let troll = session.get_entity_guid(1).unwrap();
+
+        (session, troll)
+    }
+
+    fn options(max_steps: Option<u64>) -> BotOptions {
+        BotOptions {
+            step_delay: time::Duration::ZERO,
+            max_steps,
+        }
+    }
+
+    struct Berserker;
+
+    impl Strategy for Berserker {
+        fn name(&self) -> &str {
+            "berserker"
+        }
+
+        fn decide(&mut self, observation: &Observation) -> Action {
+            match observation.others().first() {
+                Some(guid) => Action::Attack(guid.clone()),
+                None => Action::Move(model::Direction::E),
+            }
+        }
+    }
+
+    struct Unreachable;
+
+    impl net::Transport for Unreachable {
+        fn send(&self, _request: &net::MunRequest) -> Result<net::RawResponse, String> {
+            Err(String::from("connection refused"))
+        }
+    }
+
+    #[test]
+    fn explorer_examines_before_attacking() {
+        let (mut session, troll) = next_to_troll();
+        let mut explorer = Explorer::default();
+
+        let action = explorer.decide(&Observation::from_session(&session, 0));
+        assert_eq!(action, Action::Examine(troll.clone()));
+
+        session.look_entity(troll.clone());
+        let mut observation = Observation::from_session(&session, 1);
+        observation.examined.get_mut(&troll).unwrap().life = 5;
+        assert_eq!(explorer.decide(&observation), Action::Attack(troll));
+    }
+
+    #[test]
+    fn explorer_leaves_stronger_monsters_alone() {
+        let (mut session, troll) = next_to_troll();
+        session.look_entity(troll.clone());
+        let mut explorer = Explorer::default();
+
+        // The troll has 60 life against 30
+        for step in 0..5 {
+            let action = explorer.decide(&Observation::from_session(&session, step));
+            assert_eq!(action, Action::Move(model::Direction::W));
+        }
+    }
+
+    #[test]
+    fn explorer_flees_when_low_on_life() {
+        let (mut session, troll) = next_to_troll();
+        session.look_entity(troll.clone());
+        let mut observation = Observation::from_session(&session, 0);
+        observation.examined.get_mut(&troll).unwrap().life = 1;
+        // Just under the ratio, the weak troll is left alone
+        let status = observation.status.as_mut().unwrap();
+        status.life = Some((status.total_life as f64 * FLEE_LIFE_RATIO) as u32 - 1);
+
+        let action = Explorer::default().decide(&observation);
+        assert_eq!(action, Action::Move(model::Direction::W));
+    }
+
+    #[test]
+    fn runner_finishes_on_death() {
+        let mut session = session();
+        let mut runner = BotRunner::new(Box::new(Berserker), options(Some(100)));
+
+        runner.run(&mut session);
+        assert!(runner.finished);
+        assert!(runner.steps < 100);
+        assert!(matches!(
+            session
+                .error
+                .as_ref()
+                .and_then(|error| error.detail.r#type.clone()),
+            Some(model::ErrorType::Dead)
+        ));
+        assert!(!runner.step(&mut session));
+    }
+
+    #[test]
+    fn runner_finishes_after_max_steps() {
+        let mut session = session();
+        let mut runner = BotRunner::new(Box::new(Explorer::default()), options(Some(3)));
+
+        // The first step only connects
+        runner.run(&mut session);
+        assert!(runner.finished);
+        assert_eq!(runner.steps, 3);
+        assert!(session.is_connected());
+    }
+
+    #[test]
+    fn runner_gives_up_after_repeated_connect_failures() {
+        let mut session = session::Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(Unreachable),
+        ));
+        let mut runner = BotRunner::new(Box::new(Explorer::default()), options(None));
+
+        for attempt in 1..MAX_CONNECT_FAILURES {
+            assert!(runner.step(&mut session), "attempt {}", attempt);
+        }
+        assert!(!runner.step(&mut session));
+        assert!(runner.finished);
+        assert_eq!(runner.steps, 0);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::logging;
+use crate::net;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time;
+
+static ERROR_CASSETTE_OPEN: &str = "Could not open cassette";
+static ERROR_CASSETTE_PARSE: &str = "Could not parse cassette line";
+static ERROR_CASSETTE_EXHAUSTED: &str = "Cassette has no more recorded responses";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CassetteEntry {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+    pub status: Option<u16>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+    pub timestamp_ms: u64,
+}
+
+impl CassetteEntry {
+    fn to_result(&self) -> Result<net::RawResponse, String> {
+        match (self.status, &self.error) {
+            (Some(status), _) => Ok(net::RawResponse {
+                status,
+                body: self.response.clone().unwrap_or_default(),
+            }),
+            (None, Some(error)) => Err(error.clone()),
+            (None, None) => Err(String::new()),
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn load(path: &str) -> Result<Vec<CassetteEntry>, String> {
+    let file =
+        fs::File::open(path).map_err(|e| format!("{} {}: {}", ERROR_CASSETTE_OPEN, path, e))?;
+    let mut entries: Vec<CassetteEntry> = Vec::new();
+
+    for (number, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{} {}: {}", ERROR_CASSETTE_OPEN, path, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str::<CassetteEntry>(line.as_str())
+            .map_err(|e| format!("{} {}:{}: {}", ERROR_CASSETTE_PARSE, path, number + 1, e))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+pub struct RecordingTransport<T: net::Transport> {
+    inner: T,
+    base_url: String,
+    file: Mutex<fs::File>,
+}
+
+impl<T: net::Transport> RecordingTransport<T> {
+    pub fn create(inner: T, base_url: String, path: &str) -> Result<RecordingTransport<T>, String> {
+        let file = fs::File::create(path)
+            .map_err(|e| format!("{} {}: {}", ERROR_CASSETTE_OPEN, path, e))?;
+
+        Ok(RecordingTransport {
+            inner,
+            base_url,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl<T: net::Transport> net::Transport for RecordingTransport<T> {
+    fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+        let timestamp_ms = unix_millis();
+        let started = time::Instant::now();
+        let result = self.inner.send(request);
+
+        let entry = CassetteEntry {
+            method: request.method().to_string(),
+            path: net::relative_path(self.base_url.as_str(), request.url()),
+            body: request.body().to_string(),
+            status: result.as_ref().ok().map(|response| response.status),
+            response: result.as_ref().ok().map(|response| response.body.clone()),
+            error: result.as_ref().err().cloned(),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            timestamp_ms,
+        };
+
+        match serde_json::to_string(&entry) {
+            Ok(line) => {
+                let written = match self.file.lock() {
+                    Ok(mut file) => writeln!(file, "{}", line),
+                    Err(_) => Err(io::Error::new(io::ErrorKind::Other, "cassette poisoned")),
+                };
+                if let Err(error) = written {
+                    log::warn!(target: "cassette", "write_failed error=\"{}\"", error);
+                }
+            }
+            Err(error) => log::warn!(target: "cassette", "encode_failed error=\"{}\"", error),
+        }
+
+        result
+    }
+}
+
+pub type PendingEntries = Arc<Mutex<VecDeque<CassetteEntry>>>;
+
+pub struct ReplayTransport {
+    base_url: String,
+    entries: PendingEntries,
+}
+
+impl ReplayTransport {
+    pub fn new(base_url: String, entries: Vec<CassetteEntry>) -> ReplayTransport {
+        ReplayTransport {
+            base_url,
+            entries: Arc::new(Mutex::new(entries.into_iter().collect())),
+        }
+    }
+
+    pub fn pending(&self) -> PendingEntries {
+        self.entries.clone()
+    }
+}
+
+impl net::Transport for ReplayTransport {
+    // Auto-update ticks do not land at the same moments on every run, so a request is answered
+    // by the next recorded exchange for the same method and path. Exchanges it skips over were
+    // not asked for in time and are dropped, so they cannot answer a later request stale.
+    fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+        let path = net::relative_path(self.base_url.as_str(), request.url());
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return Err(ERROR_CASSETTE_EXHAUSTED.to_string()),
+        };
+        let position = entries
+            .iter()
+            .position(|entry| entry.method == request.method() && entry.path == path);
+
+        let entry = match position {
+            Some(position) => {
+                for skipped in entries.drain(..position) {
+                    log::warn!(
+                        target: "cassette",
+                        "replay_skipped method={} path={} for_method={} for_path={}",
+                        skipped.method,
+                        logging::url("", skipped.path.as_str()),
+                        request.method(),
+                        logging::url("", path.as_str())
+                    );
+                }
+                entries.pop_front()
+            }
+            None => None,
+        };
+
+        match entry {
+            Some(entry) => entry.to_result(),
+            None => {
+                log::warn!(
+                    target: "cassette",
+                    "replay_exhausted method={} path={} remaining={}",
+                    request.method(),
+                    logging::url("", path.as_str()),
+                    entries.len()
+                );
+                Err(ERROR_CASSETTE_EXHAUSTED.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history;
+    use crate::model;
+    use crate::session;
+    use crate::sim;
+
+    static BASE_URL: &str = "http://cassette";
+
+    type Seen = (
+        Option<String>,
+        Option<(u32, u32)>,
+        Option<u16>,
+        Option<model::ErrorType>,
+    );
+
+    fn seen(session: &mut session::Session) -> Seen {
+        let seen = (
+            session
+                .status
+                .as_ref()
+                .map(|status| status.room.description.clone()),
+            session
+                .fight_info
+                .as_ref()
+                .map(|fight| (fight.attacker.life, fight.defender.life)),
+            session.error.as_ref().and_then(|error| error.code),
+            session
+                .error
+                .as_ref()
+                .and_then(|error| error.detail.r#type.clone()),
+        );
+        session.clear_infos();
+        session.error = None;
+
+        seen
+    }
+
+    // Walks into the dead end, bumps the wall and fights the troll to the death
+    fn play(session: &mut session::Session) -> Vec<Seen> {
+        let mut log = Vec::new();
+        session.connect();
+        session.update();
+        log.push(seen(session));
+        session.r#move(model::Direction::E);
+        log.push(seen(session));
+        session.r#move(model::Direction::E);
+        log.push(seen(session));
+
+        let troll = session.get_entity_guid(1).unwrap();
+        for _ in 0..10 {
+            session.attack(troll.clone());
+            let dead = session.error.is_some();
+            log.push(seen(session));
+            if dead {
+                break;
+            }
+        }
+
+        log
+    }
+
+    #[test]
+    fn replayed_session_sees_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!(
+            "mungeon-cassette-{}-{}.jsonl",
+            std::process::id(),
+            history::now()
+        ));
+        let path = path.to_str().unwrap();
+        let dungeon = format!("{}/dungeons/dead_end.toml", env!("CARGO_MANIFEST_DIR"));
+        let world = sim::World::load(dungeon.as_str(), 7).unwrap();
+        let recording = RecordingTransport::create(
+            sim::SimTransport::new(BASE_URL.to_string(), world),
+            BASE_URL.to_string(),
+            path,
+        )
+        .unwrap();
+        let recorded = play(&mut session::Session::with_client(
+            net::MunHttpClient::with_transport(BASE_URL.to_string(), Box::new(recording)),
+        ));
+
+        let entries = load(path).unwrap();
+        let _ = fs::remove_file(path);
+        let replay = ReplayTransport::new(BASE_URL.to_string(), entries);
+        let pending = replay.pending();
+        let replayed = play(&mut session::Session::with_client(
+            net::MunHttpClient::with_transport(BASE_URL.to_string(), Box::new(replay)),
+        ));
+
+        assert_eq!(recorded, replayed);
+        assert_eq!(recorded[2].2, Some(409));
+        assert_eq!(recorded[2].3, Some(model::ErrorType::Wall));
+        assert_eq!(recorded.last().unwrap().3, Some(model::ErrorType::Dead));
+        assert!(recorded.iter().any(|seen| seen.1.is_some()));
+        assert!(pending.lock().unwrap().is_empty());
+    }
+
+    fn entry(path: &str, response: &str) -> CassetteEntry {
+        CassetteEntry {
+            method: String::from("GET"),
+            path: path.to_string(),
+            body: String::new(),
+            status: Some(200),
+            response: Some(response.to_string()),
+            error: None,
+            elapsed_ms: 0,
+            timestamp_ms: 0,
+        }
+    }
+
+    #[test]
+    fn skipped_exchanges_are_dropped() {
+        let replay = ReplayTransport::new(
+            BASE_URL.to_string(),
+            vec![
+                entry("/a", "first a"),
+                entry("/b", "b"),
+                entry("/a", "second a"),
+            ],
+        );
+        let get = |path: &str| {
+            net::Transport::send(
+                &replay,
+                &net::MunRequest::Get(format!("{}{}", BASE_URL, path)),
+            )
+            .map(|response| response.body)
+        };
+
+        assert_eq!(get("/b"), Ok(String::from("b")));
+        assert_eq!(get("/a"), Ok(String::from("second a")));
+        assert_eq!(get("/a"), Err(ERROR_CASSETTE_EXHAUSTED.to_string()));
+    }
+}
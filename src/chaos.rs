@@ -0,0 +1,313 @@
+use crate::config;
+use crate::model;
+use crate::net;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+static ERROR_CHAOS_PROBABILITY: &str = "Chaos probabilities must be between 0 and 1";
+static ERROR_CHAOS_DROP: &str = "Connection dropped by chaos transport";
+
+static SERVER_ERROR_STATUSES: [u16; 3] = [500, 502, 503];
+
+pub struct ChaosTransport<T: net::Transport> {
+    inner: T,
+    chaos_config: config::ChaosConfig,
+    rng: Mutex<StdRng>,
+}
+
+impl<T: net::Transport> ChaosTransport<T> {
+    pub fn new(inner: T, chaos_config: config::ChaosConfig) -> Result<ChaosTransport<T>, String> {
+        let probabilities = [
+            ("latency_probability", chaos_config.latency_probability),
+            ("drop_probability", chaos_config.drop_probability),
+            (
+                "server_error_probability",
+                chaos_config.server_error_probability,
+            ),
+            ("truncate_probability", chaos_config.truncate_probability),
+            ("corrupt_probability", chaos_config.corrupt_probability),
+            ("diff_room_probability", chaos_config.diff_room_probability),
+            ("wall_probability", chaos_config.wall_probability),
+        ];
+        for (name, probability) in probabilities.iter() {
+            if !(0.0..=1.0).contains(probability) {
+                return Err(format!(
+                    "{}: got \"{} = {}\"",
+                    ERROR_CHAOS_PROBABILITY, name, probability
+                ));
+            }
+        }
+
+        let seed = chaos_config
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen());
+        log::info!(target: "chaos", "enabled seed={}", seed);
+
+        Ok(ChaosTransport {
+            inner,
+            chaos_config,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        })
+    }
+
+    // Only the rng is locked, so requests of other clients are not held up
+    fn with_rng<R>(&self, f: impl FnOnce(&mut StdRng) -> R) -> R {
+        let mut rng = match self.rng.lock() {
+            Ok(rng) => rng,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut rng)
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        probability > 0.0 && self.with_rng(|rng| rng.gen_bool(probability))
+    }
+
+    fn truncate(&self, body: String) -> String {
+        let cut = self.with_rng(|rng| rng.gen_range(0..=body.len()));
+        let cut = (0..=cut)
+            .rev()
+            .find(|i| body.is_char_boundary(*i))
+            .unwrap_or(0);
+
+        body[..cut].to_string()
+    }
+
+    fn corrupt(&self, body: String) -> String {
+        let mut bytes = body.into_bytes();
+        if !bytes.is_empty() {
+            let (index, byte) =
+                self.with_rng(|rng| (rng.gen_range(0..bytes.len()), rng.gen_range(0..7)));
+            bytes[index] = b"{}[]\",:"[byte];
+        }
+
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+fn game_error(error_type: model::ErrorType) -> net::RawResponse {
+    let detail = model::ErrorDetail {
+        message: format!("{} (injected)", error_type),
+        r#type: Some(error_type),
+    };
+
+    net::RawResponse {
+        status: 409,
+        body: serde_json::to_string(&detail).unwrap_or_default(),
+    }
+}
+
+impl<T: net::Transport> net::Transport for ChaosTransport<T> {
+    fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+        if self.roll(self.chaos_config.latency_probability) {
+            let delay = self.with_rng(|rng| rng.gen_range(0..=self.chaos_config.latency_ms));
+            log::debug!(target: "chaos", "latency ms={}", delay);
+            thread::sleep(time::Duration::from_millis(delay));
+        }
+        if self.roll(self.chaos_config.drop_probability) {
+            log::debug!(target: "chaos", "drop");
+            return Err(ERROR_CHAOS_DROP.to_string());
+        }
+        if self.roll(self.chaos_config.server_error_probability) {
+            let status = SERVER_ERROR_STATUSES
+                [self.with_rng(|rng| rng.gen_range(0..SERVER_ERROR_STATUSES.len()))];
+            log::debug!(target: "chaos", "server_error status={}", status);
+            return Ok(net::RawResponse {
+                status,
+                body: String::new(),
+            });
+        }
+        if self.roll(self.chaos_config.diff_room_probability) {
+            log::debug!(target: "chaos", "error type=DIFFSALLE");
+            return Ok(game_error(model::ErrorType::DiffRoom));
+        }
+        if self.roll(self.chaos_config.wall_probability) {
+            log::debug!(target: "chaos", "error type=MUR");
+            return Ok(game_error(model::ErrorType::Wall));
+        }
+
+        let mut response = self.inner.send(request)?;
+        if self.roll(self.chaos_config.truncate_probability) {
+            log::debug!(target: "chaos", "truncate");
+            response.body = self.truncate(response.body);
+        } else if self.roll(self.chaos_config.corrupt_probability) {
+            log::debug!(target: "chaos", "corrupt");
+            response.body = self.corrupt(response.body);
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::MunModel;
+    use crate::session;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    static BASE_URL: &str = "http://chaos";
+    static ROOM: &str = r#"{"description":"Hall","passages":["N","E"],"entites":["a","b"]}"#;
+
+    // Answers every request with the same room
+    struct Stub;
+
+    impl net::Transport for Stub {
+        fn send(&self, _request: &net::MunRequest) -> Result<net::RawResponse, String> {
+            Ok(net::RawResponse {
+                status: 200,
+                body: ROOM.to_string(),
+            })
+        }
+    }
+
+    // Counts the requests the client sends, retries included
+    struct Counted<T: net::Transport> {
+        inner: T,
+        sent: Arc<AtomicUsize>,
+    }
+
+    impl<T: net::Transport> net::Transport for Counted<T> {
+        fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            self.inner.send(request)
+        }
+    }
+
+    fn chaos_config(seed: u64) -> config::ChaosConfig {
+        config::ChaosConfig {
+            enabled: true,
+            seed: Some(seed),
+            ..config::ChaosConfig::default()
+        }
+    }
+
+    fn client(chaos_config: config::ChaosConfig) -> (net::MunHttpClient, Arc<AtomicUsize>) {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let transport = Counted {
+            inner: ChaosTransport::new(Stub, chaos_config).unwrap(),
+            sent: sent.clone(),
+        };
+
+        (
+            net::MunHttpClient::with_transport(BASE_URL.to_string(), Box::new(transport)),
+            sent,
+        )
+    }
+
+    #[test]
+    fn server_errors_are_retried_once_on_get_only() {
+        let (mut client, sent) = client(config::ChaosConfig {
+            server_error_probability: 1.0,
+            ..chaos_config(1)
+        });
+
+        let error = client.look_room(String::from("me")).unwrap_err();
+        assert!(error.code.is_some_and(|code| code >= 500));
+        assert_eq!(sent.swap(0, Ordering::SeqCst), 2);
+
+        let error = client
+            .r#move(String::from("me"), model::Direction::N)
+            .unwrap_err();
+        assert!(error.code.is_some_and(|code| code >= 500));
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn damaged_bodies_become_errors() {
+        for seed in 0..50 {
+            for chaos_config in [
+                config::ChaosConfig {
+                    truncate_probability: 1.0,
+                    ..chaos_config(seed)
+                },
+                config::ChaosConfig {
+                    corrupt_probability: 1.0,
+                    ..chaos_config(seed)
+                },
+            ] {
+                let (mut client, _) = client(chaos_config);
+                // A cut or a swapped byte may still leave valid JSON, but never a panic
+                if let Err(error) = client.look_room(String::from("me")) {
+                    assert!(!error.detail.message.is_empty());
+                }
+            }
+        }
+
+        let (mut client, _) = client(config::ChaosConfig {
+            truncate_probability: 1.0,
+            ..chaos_config(3)
+        });
+        let errors = (0..20)
+            .filter(|_| client.look_room(String::from("me")).is_err())
+            .count();
+        assert!(errors > 0);
+    }
+
+    #[test]
+    fn injected_game_errors_reach_the_session() {
+        for (chaos_config, expected) in [
+            (
+                config::ChaosConfig {
+                    diff_room_probability: 1.0,
+                    ..chaos_config(5)
+                },
+                model::ErrorType::DiffRoom,
+            ),
+            (
+                config::ChaosConfig {
+                    wall_probability: 1.0,
+                    ..chaos_config(5)
+                },
+                model::ErrorType::Wall,
+            ),
+        ] {
+            let (client, _) = client(chaos_config);
+            let mut session = session::Session::with_client(client);
+            session.status = Some(model::Status {
+                guid: String::from("me"),
+                total_life: 10,
+                life: Some(10),
+                room: model::Room::from_str(ROOM).unwrap(),
+            });
+
+            session.r#move(model::Direction::N);
+            let error = session.error.as_ref().unwrap();
+            assert_eq!(error.code, Some(409));
+            assert_eq!(error.detail.r#type, Some(expected));
+            assert_eq!(session.position, (0, 0));
+        }
+    }
+
+    #[test]
+    fn probabilities_outside_zero_to_one_are_rejected() {
+        for probability in [-0.1, 1.5, f64::NAN] {
+            let chaos_config = config::ChaosConfig {
+                wall_probability: probability,
+                ..chaos_config(1)
+            };
+            let error = ChaosTransport::new(Stub, chaos_config).err().unwrap();
+            assert!(error.starts_with(ERROR_CHAOS_PROBABILITY), "{}", error);
+            assert!(error.contains("wall_probability"), "{}", error);
+        }
+
+        assert!(ChaosTransport::new(
+            Stub,
+            config::ChaosConfig {
+                wall_probability: 1.0,
+                drop_probability: 0.0,
+                ..chaos_config(1)
+            }
+        )
+        .is_ok());
+    }
+}
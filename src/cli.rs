@@ -0,0 +1,678 @@
+//! Command line front end of the binary: parses `key=value` arguments and a command,
+//! then wires the library together for it.
+
+use crate::bot;
+use crate::cassette;
+use crate::chaos;
+use crate::config;
+use crate::conformance;
+use crate::control;
+use crate::env;
+use crate::history;
+use crate::httpd;
+use crate::keymap;
+use crate::load;
+use crate::logging;
+use crate::net;
+use crate::relay;
+use crate::replay;
+use crate::rpc;
+use crate::runner;
+use crate::session;
+use crate::sim;
+use crate::theme;
+
+use std::error;
+use std::time;
+
+static DEFAULT_SERVE_PORT: u16 = 8000;
+static OFFLINE_URL: &str = "offline:";
+
+static ERROR_ARGUMENT_PARSE: &str = "Could not parse argument";
+static ERROR_NO_URL: &str = "No URL was specified";
+static ERROR_CHECKS_FAILED: &str = "Server failed conformance checks";
+static ERROR_UNKNOWN_COMMAND: &str = "Unknown command";
+static ERROR_NO_CASSETTE: &str =
+    "No cassette was specified, use replay <cassette> with a record= file (session logs cannot be replayed)";
+static ERROR_UNKNOWN_BOT: &str = "Unknown bot strategy";
+#[cfg(not(feature = "scripting"))]
+static ERROR_NO_SCRIPTING: &str = "Scripts need a build with the scripting feature";
+static ERROR_EMPTY_CASSETTE: &str = "Cassette contains no replayable steps";
+static ERROR_NO_RELAY_TOKEN: &str = "relay-join= needs the relay-token= printed by the relay host";
+static ERROR_NO_HISTORY_PATH: &str = "No history file could be determined, set path in [history]";
+
+enum Command {
+    Play,
+    Replay(String),
+    CheckServer,
+    Load,
+    StdioRpc,
+    Env,
+    Serve,
+    History,
+}
+
+/// What the binary was asked to do, from its command line.
+pub struct Args {
+    command: Command,
+    viewer_path: Option<String>,
+    url: String,
+    config_path: Option<String>,
+    theme_name: Option<String>,
+    log_level: Option<String>,
+    log_file: Option<String>,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+    chaos_seed: Option<u64>,
+    players: Option<String>,
+    duration: Option<String>,
+    iterations: Option<String>,
+    mix: Option<String>,
+    rate: Option<f64>,
+    allow_remote: bool,
+    control_port: Option<u16>,
+    control_token: Option<String>,
+    bot_name: Option<String>,
+    bot_steps: Option<u64>,
+    bot_delay_millis: u64,
+    headless: bool,
+    offline: bool,
+    script_path: Option<String>,
+    episodes: u64,
+    transitions_path: Option<String>,
+    seed: Option<u64>,
+    dungeon_path: Option<String>,
+    serve_port: u16,
+    history_query: history::Query,
+    csv_path: Option<String>,
+    tab_urls: Vec<String>,
+    relay_host: Option<std::net::SocketAddr>,
+    relay_join: Option<String>,
+    relay_token: Option<String>,
+}
+
+impl Args {
+    /// Parses the binary's arguments, program name first; panics on an unknown or bad one.
+    pub fn parse(args: &[String]) -> Args {
+        let mut url = String::new();
+        let mut config_path: Option<String> = None;
+        let mut theme_name: Option<String> = None;
+        let mut log_level: Option<String> = None;
+        let mut log_file: Option<String> = None;
+        let mut record_path: Option<String> = None;
+        let mut replay_path: Option<String> = None;
+        let mut chaos_seed: Option<u64> = None;
+        let mut players: Option<String> = None;
+        let mut duration: Option<String> = None;
+        let mut iterations: Option<String> = None;
+        let mut mix: Option<String> = None;
+        let mut rate: Option<f64> = None;
+        let mut allow_remote = false;
+        let mut control_port: Option<u16> = None;
+        let mut control_token: Option<String> = None;
+        let mut bot_name: Option<String> = None;
+        let mut bot_steps: Option<u64> = None;
+        let mut bot_delay_millis: u64 = 500;
+        let mut headless = false;
+        let mut offline = false;
+        let mut script_path: Option<String> = None;
+        let mut episodes: u64 = 1;
+        let mut transitions_path: Option<String> = None;
+        let mut seed: Option<u64> = None;
+        let mut dungeon_path: Option<String> = None;
+        let mut serve_port: u16 = DEFAULT_SERVE_PORT;
+        let mut history_query = history::Query::default();
+        let mut csv_path: Option<String> = None;
+        let mut tab_urls: Vec<String> = Vec::new();
+        let mut relay_host: Option<std::net::SocketAddr> = None;
+        let mut relay_join: Option<String> = None;
+        let mut relay_token: Option<String> = None;
+        let mut positionals: Vec<String> = Vec::new();
+
+        for arg in args.iter().skip(1) {
+            let arg = arg.strip_prefix("--").unwrap_or(arg);
+            if let Some(value) = arg.strip_prefix("url=") {
+                url = value.to_string();
+            } else if let Some(value) = arg.strip_prefix("config=") {
+                config_path = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("theme=") {
+                theme_name = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("log=") {
+                log_level = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("log-file=") {
+                log_file = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("record=") {
+                record_path = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("replay=") {
+                replay_path = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("chaos=") {
+                match value.parse::<u64>() {
+                    Ok(seed) => chaos_seed = Some(seed),
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("players=") {
+                players = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("duration=") {
+                duration = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("iterations=") {
+                iterations = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("mix=") {
+                mix = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("rate=") {
+                match value.parse::<f64>() {
+                    Ok(value) if value.is_finite() && value > 0.0 => rate = Some(value),
+                    _ => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("control=") {
+                match value.parse::<u16>() {
+                    Ok(port) => control_port = Some(port),
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("control-token=") {
+                control_token = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("bot=") {
+                bot_name = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("steps=") {
+                match value.parse::<u64>() {
+                    Ok(steps) => bot_steps = Some(steps),
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("delay=") {
+                match value.parse::<u64>() {
+                    Ok(delay) => bot_delay_millis = delay,
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("script=") {
+                script_path = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("episodes=") {
+                match value.parse::<u64>() {
+                    Ok(value) => episodes = value,
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("transitions=") {
+                transitions_path = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("seed=") {
+                match value.parse::<u64>() {
+                    Ok(value) => seed = Some(value),
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("dungeon=") {
+                dungeon_path = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("port=") {
+                match value.parse::<u16>() {
+                    Ok(port) => serve_port = port,
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("sort=") {
+                match history::SortKey::parse(value) {
+                    Ok(sort) => history_query.sort = sort,
+                    Err(error) => panic!("{}", error),
+                }
+            } else if let Some(value) = arg.strip_prefix("cause=") {
+                match history::EndCause::parse(value) {
+                    Ok(cause) => history_query.cause = Some(cause),
+                    Err(error) => panic!("{}", error),
+                }
+            } else if let Some(value) = arg.strip_prefix("server=") {
+                history_query.server = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("limit=") {
+                match value.parse::<usize>() {
+                    Ok(limit) => history_query.limit = Some(limit),
+                    Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                }
+            } else if let Some(value) = arg.strip_prefix("tab=") {
+                tab_urls.push(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("relay-host=") {
+                // A bare port stays on this machine, an address can open the relay to the LAN
+                relay_host = match value.parse::<u16>() {
+                    Ok(port) => Some((std::net::Ipv4Addr::LOCALHOST, port).into()),
+                    Err(_) => match value.parse::<std::net::SocketAddr>() {
+                        Ok(address) => Some(address),
+                        Err(_) => panic!("{} {}", ERROR_ARGUMENT_PARSE, arg),
+                    },
+                }
+            } else if let Some(value) = arg.strip_prefix("relay-join=") {
+                relay_join = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("relay-token=") {
+                relay_token = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("csv=") {
+                csv_path = Some(value.to_string());
+            } else if arg == "headless" {
+                headless = true;
+            } else if arg == "offline" {
+                offline = true;
+            } else if arg == "allow-remote" {
+                allow_remote = true;
+            } else if !arg.contains('=') {
+                positionals.push(arg.to_string());
+            } else {
+                panic!("{} {}", ERROR_ARGUMENT_PARSE, arg);
+            }
+        }
+
+        let command = match positionals.split_first() {
+            Some((command, rest)) if command == "replay" => match rest {
+                [path] => Command::Replay(path.clone()),
+                _ => panic!("{}", ERROR_NO_CASSETTE),
+            },
+            Some((command, [])) if command == "check-server" => Command::CheckServer,
+            Some((command, [])) if command == "load" => Command::Load,
+            Some((command, [])) if command == "stdio-rpc" => Command::StdioRpc,
+            Some((command, [])) if command == "env" => Command::Env,
+            Some((command, [])) if command == "serve" => Command::Serve,
+            Some((command, [])) if command == "history" => Command::History,
+            Some((command, _)) => panic!("{}: got \"{}\"", ERROR_UNKNOWN_COMMAND, command),
+            None => Command::Play,
+        };
+        let viewer_path = match &command {
+            Command::Replay(path) => Some(path.clone()),
+            _ => None,
+        };
+
+        if url.is_empty() {
+            match viewer_path.as_ref().or(replay_path.as_ref()) {
+                Some(replay_path) => url = format!("replay:{}", replay_path),
+                None if offline || matches!(command, Command::Serve) => {
+                    url = OFFLINE_URL.to_string()
+                }
+                None if matches!(command, Command::History) => (),
+                None => panic!("{}", ERROR_NO_URL),
+            }
+        }
+
+        Args {
+            command,
+            viewer_path,
+            url,
+            config_path,
+            theme_name,
+            log_level,
+            log_file,
+            record_path,
+            replay_path,
+            chaos_seed,
+            players,
+            duration,
+            iterations,
+            mix,
+            rate,
+            allow_remote,
+            control_port,
+            control_token,
+            bot_name,
+            bot_steps,
+            bot_delay_millis,
+            headless,
+            offline,
+            script_path,
+            episodes,
+            transitions_path,
+            seed,
+            dungeon_path,
+            serve_port,
+            history_query,
+            csv_path,
+            tab_urls,
+            relay_host,
+            relay_join,
+            relay_token,
+        }
+    }
+}
+
+/// Runs the command `args` asks for.
+pub fn run(args: Args) -> Result<(), Box<dyn error::Error>> {
+    let Args {
+        command,
+        viewer_path,
+        url,
+        config_path,
+        theme_name,
+        log_level,
+        log_file,
+        record_path,
+        replay_path,
+        chaos_seed,
+        players,
+        duration,
+        iterations,
+        mix,
+        rate,
+        allow_remote,
+        control_port,
+        control_token,
+        bot_name,
+        bot_steps,
+        bot_delay_millis,
+        headless,
+        offline,
+        script_path,
+        episodes,
+        transitions_path,
+        seed,
+        dungeon_path,
+        serve_port,
+        history_query,
+        csv_path,
+        tab_urls,
+        relay_host,
+        relay_join,
+        relay_token,
+    } = args;
+
+    let mut config = config::Config::load(config_path.as_deref())?;
+    if chaos_seed.is_some() {
+        config.chaos.enabled = true;
+        config.chaos.seed = chaos_seed;
+    }
+    logging::FileLogger::init(logging::LogOptions::from_config(
+        &config.log,
+        log_level.as_deref(),
+        log_file.as_deref(),
+    )?)?;
+    log::info!(target: "main", "start url={}", url);
+
+    if let Command::History = command {
+        let history_path = history::resolve_path(config.history.path.as_deref())
+            .ok_or_else(|| ERROR_NO_HISTORY_PATH.to_string())?;
+        let history = history::History::load(&history_path)?;
+        let records = history.query(&history_query);
+        match csv_path.as_deref() {
+            Some("-") => print!("{}", history::to_csv(&records)),
+            Some(csv_path) => std::fs::write(csv_path, history::to_csv(&records))?,
+            None => {
+                for line in history::table(&records, true) {
+                    println!("{}", line);
+                }
+            }
+        }
+        log::logger().flush();
+
+        return Ok(());
+    }
+
+    // Offline games are seeded from seed=, or from the clock
+    let world = || -> Result<sim::World, String> {
+        let seed = seed.unwrap_or_else(|| {
+            time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default()
+        });
+        match dungeon_path.as_ref().or(config.offline.dungeon.as_ref()) {
+            Some(dungeon_path) => sim::World::load(dungeon_path, seed),
+            None => Ok(sim::World::generate(seed, &config.offline)),
+        }
+    };
+    let base_transport = || -> Result<Box<dyn net::Transport>, String> {
+        if offline {
+            Ok(Box::new(sim::SimTransport::new(url.clone(), world()?)))
+        } else {
+            Ok(Box::new(net::HttpTransport::new()))
+        }
+    };
+
+    if let Command::Serve = command {
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, serve_port))?;
+        println!("serving on http://{}", listener.local_addr()?);
+        sim::serve(listener, world()?);
+        loop {
+            std::thread::park();
+        }
+    }
+
+    if let Command::CheckServer = command {
+        let report = conformance::check_server(url.clone(), base_transport()?);
+        println!("{}", report);
+        log::logger().flush();
+
+        return match report.failures() {
+            0 => Ok(()),
+            failures => Err(format!("{}: got {}", ERROR_CHECKS_FAILED, failures).into()),
+        };
+    }
+
+    if let Command::Load = command {
+        let mut options = load::LoadOptions::parse(
+            players.as_deref(),
+            duration.as_deref(),
+            iterations.as_deref(),
+            mix.as_deref(),
+        )?;
+        options.rate = rate;
+        options.allow_remote = allow_remote;
+        // Offline, the players share a simulator served on a free local port
+        let url = match offline {
+            true => {
+                let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))?;
+                let url = format!("http://{}", listener.local_addr()?);
+                sim::serve(listener, world()?);
+                url
+            }
+            false => url,
+        };
+        println!("{}", load::run(url, options)?);
+        log::logger().flush();
+
+        return Ok(());
+    }
+
+    let keymap = keymap::Keymap::from_config(&config.keymap)?;
+    let theme = theme::Theme::from_config(&config, theme_name.as_deref())?;
+
+    let replay_state = match &viewer_path {
+        Some(viewer_path) => {
+            let frames = replay::build_frames(cassette::load(viewer_path)?);
+            if frames.is_empty() {
+                return Err(format!("{}: got \"{}\"", ERROR_EMPTY_CASSETTE, viewer_path).into());
+            }
+            log::info!(target: "main", "replay frames={}", frames.len());
+            Some(replay::ReplayState::new(frames))
+        }
+        None => None,
+    };
+
+    // The env command steps as fast as the server answers, like load does
+    if let (Command::Env, false, None) = (&command, offline, &replay_path) {
+        load::check_target(url.as_str(), allow_remote, rate)?;
+    }
+
+    let session = match (&replay_path, &record_path, config.chaos.enabled) {
+        (Some(replay_path), _, _) => {
+            session::Session::with_client(net::MunHttpClient::with_transport(
+                url.clone(),
+                Box::new(cassette::ReplayTransport::new(
+                    url.clone(),
+                    cassette::load(replay_path)?,
+                )),
+            ))
+        }
+        (None, None, false) if !offline && rate.is_none() => session::Session::new(url),
+        (None, record_path, chaos_enabled) => {
+            let mut transport = base_transport()?;
+            if chaos_enabled {
+                transport = Box::new(chaos::ChaosTransport::new(transport, config.chaos.clone())?);
+            }
+            if let Some(rate) = rate {
+                transport = Box::new(load::ThrottledTransport::new(transport, rate));
+            }
+            if let Some(record_path) = record_path {
+                transport = Box::new(cassette::RecordingTransport::create(
+                    transport,
+                    url.clone(),
+                    record_path,
+                )?);
+            }
+            session::Session::with_client(net::MunHttpClient::with_transport(
+                url.clone(),
+                transport,
+            ))
+        }
+    };
+
+    let bot_runner = match &bot_name {
+        Some(bot_name) => {
+            let strategy = bot::strategy(bot_name)
+                .ok_or_else(|| format!("{}: got \"{}\"", ERROR_UNKNOWN_BOT, bot_name))?;
+            Some(bot::BotRunner::new(
+                strategy,
+                bot::BotOptions {
+                    step_delay: time::Duration::from_millis(bot_delay_millis),
+                    max_steps: bot_steps,
+                },
+            ))
+        }
+        None => None,
+    };
+
+    let bot_runner = match (bot_runner, headless) {
+        (Some(mut bot_runner), true) => {
+            let mut session = session;
+            bot_runner.run(&mut session);
+            println!(
+                "{} stopped after {} steps, last action {:?}",
+                bot_runner.name(),
+                bot_runner.steps,
+                bot_runner.last_action
+            );
+            log::logger().flush();
+            // The bot gave up before its first step: report why
+            if let (0, Some(error)) = (bot_runner.steps, &session.error) {
+                return Err(error.detail.message.clone().into());
+            }
+
+            return Ok(());
+        }
+        (bot_runner, _) => bot_runner,
+    };
+
+    if let Command::Env = command {
+        let mut environment = env::Env::new(session);
+        environment.max_steps = bot_steps.unwrap_or(env::DEFAULT_MAX_STEPS);
+        if let Some(transitions_path) = &transitions_path {
+            environment.record_to(transitions_path)?;
+        }
+        let result = env::run_random(&mut environment, episodes, seed);
+        log::logger().flush();
+
+        return match result {
+            Ok((steps, reward)) => {
+                println!(
+                    "{} episodes, {} steps, total reward {:.2}",
+                    episodes, steps, reward
+                );
+                Ok(())
+            }
+            Err(error) => Err(error.detail.message.into()),
+        };
+    }
+
+    if let Command::StdioRpc = command {
+        let result = rpc::RpcServer::new(session).run();
+        log::logger().flush();
+
+        return result.map_err(|e| e.into());
+    }
+
+    let hooks: Option<Box<dyn bot::Hooks>> = match script_path.or(config.script.path.clone()) {
+        #[cfg(feature = "scripting")]
+        Some(script_path) => Some(Box::new(crate::script::ScriptHooks::load(
+            script_path.as_str(),
+            &config.script,
+        )?)),
+        #[cfg(not(feature = "scripting"))]
+        Some(_) => return Err(ERROR_NO_SCRIPTING.into()),
+        None => None,
+    };
+
+    let run = || -> Result<(), Box<dyn error::Error>> {
+        // Tabs on the main server share its client, and so its transport
+        let tabs: Vec<session::Session> = tab_urls
+            .iter()
+            .map(|tab_url| {
+                if tab_url == &session.client.base_url {
+                    session::Session::with_client(session.client.clone())
+                } else {
+                    session::Session::new(tab_url.clone())
+                }
+            })
+            .collect();
+        // Printed before the terminal is taken over, so it is left in the scrollback
+        let control = match control_port {
+            Some(port) => {
+                let listener = control::bind(port)?;
+                let address = listener.local_addr()?;
+                let token = match &control_token {
+                    Some(token) => {
+                        eprintln!("Control API on {}", address);
+                        token.clone()
+                    }
+                    None => {
+                        let token = httpd::token();
+                        eprintln!("Control API on {} with token {}", address, token);
+                        token
+                    }
+                };
+                Some((listener, token))
+            }
+            None => None,
+        };
+        let relay_token = match (&relay_token, relay_host) {
+            (Some(token), _) => Some(token.clone()),
+            (None, Some(address)) => {
+                let token = httpd::token();
+                eprintln!("Relay on {} with token {}", address, token);
+                Some(token)
+            }
+            (None, None) if relay_join.is_some() => return Err(ERROR_NO_RELAY_TOKEN.into()),
+            (None, None) => None,
+        };
+        let mut runner = runner::Runner::try_new(session, keymap, theme)?;
+        for tab in tabs {
+            runner.add_session(tab);
+        }
+        runner.set_alerts(config.alerts.clone());
+        runner.set_defense(config.defense.clone());
+        runner.set_history(config.history.clone());
+        if let Some(replay_state) = replay_state {
+            runner.set_replay(replay_state);
+        }
+        if let Some(bot_runner) = bot_runner {
+            runner.set_bot(bot_runner);
+        }
+        if let Some(hooks) = hooks {
+            runner.set_hooks(hooks);
+        }
+        if let Some((listener, token)) = control {
+            runner.enable_control(listener, token)?;
+        }
+        // The host joins its own relay unless told to join another one
+        let relay_address = match relay_host {
+            Some(address) => {
+                let listener = std::net::TcpListener::bind(address)?;
+                let mut local_address = listener.local_addr()?;
+                if local_address.ip().is_unspecified() {
+                    local_address.set_ip(std::net::Ipv4Addr::LOCALHOST.into());
+                }
+                log::info!(target: "main", "relay_host address={}", local_address);
+                relay::host(listener, relay_token.clone().unwrap_or_default())?;
+                relay_join
+                    .clone()
+                    .or_else(|| Some(local_address.to_string()))
+            }
+            None => relay_join.clone(),
+        };
+        if let (Some(relay_address), Some(relay_token)) = (relay_address, relay_token) {
+            runner.set_relay(relay::RelayClient::new(relay_address.as_str(), relay_token));
+        }
+        runner.run()
+    };
+
+    let result = run();
+    if let Err(error) = &result {
+        log::error!(target: "main", "error=\"{}\"", error);
+    }
+    log::logger().flush();
+
+    result
+}
@@ -0,0 +1,205 @@
+use crate::sim::OfflineConfig;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fs;
+use std::path;
+
+static CONFIG_DIR: &str = "mungeon";
+static CONFIG_FILE: &str = "config.toml";
+
+static ERROR_CONFIG_READ: &str = "Could not read configuration file";
+static ERROR_CONFIG_PARSE: &str = "Could not parse configuration file";
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub keymap: HashMap<String, Vec<String>>,
+    pub theme: Option<String>,
+    pub themes: HashMap<String, ThemeConfig>,
+    pub log: LogConfig,
+    pub chaos: ChaosConfig,
+    pub script: ScriptConfig,
+    pub offline: OfflineConfig,
+    pub alerts: AlertConfig,
+    pub defense: DefenseConfig,
+    pub history: HistoryConfig,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: String,
+    pub file: Option<String>,
+    pub max_size: u64,
+    pub max_files: u32,
+    pub redact_guids: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: String::from("info"),
+            file: None,
+            max_size: 1024 * 1024,
+            max_files: 3,
+            redact_guids: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    pub seed: Option<u64>,
+    pub latency_probability: f64,
+    pub latency_ms: u64,
+    pub drop_probability: f64,
+    pub server_error_probability: f64,
+    pub truncate_probability: f64,
+    pub corrupt_probability: f64,
+    pub diff_room_probability: f64,
+    pub wall_probability: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ScriptConfig {
+    pub path: Option<String>,
+    pub call_timeout_ms: u64,
+    pub max_operations: u64,
+    pub low_hp_ratio: f64,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> ScriptConfig {
+        ScriptConfig {
+            path: None,
+            call_timeout_ms: 50,
+            max_operations: 100_000,
+            low_hp_ratio: 0.3,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AlertConfig {
+    pub enabled: bool,
+    pub bell: bool,
+    pub min_interval_ms: u64,
+    pub duration_ms: u64,
+}
+
+impl Default for AlertConfig {
+    fn default() -> AlertConfig {
+        AlertConfig {
+            enabled: true,
+            bell: false,
+            min_interval_ms: 2000,
+            duration_ms: 4000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DefenseResponse {
+    None,
+    Alert,
+    Flee,
+    Counterattack,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DefenseConfig {
+    pub response: DefenseResponse,
+}
+
+impl Default for DefenseConfig {
+    fn default() -> DefenseConfig {
+        DefenseConfig {
+            response: DefenseResponse::None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub path: Option<String>,
+    pub report_dir: Option<String>,
+    pub report_format: ReportFormat,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            enabled: true,
+            path: None,
+            report_dir: None,
+            report_format: ReportFormat::Markdown,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct StyleConfig {
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    pub modifiers: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub inherits: Option<String>,
+    pub base: Option<StyleConfig>,
+    pub popup: Option<StyleConfig>,
+    pub good: Option<StyleConfig>,
+    pub bad: Option<StyleConfig>,
+    pub title: Option<StyleConfig>,
+    pub dungeon: Option<StyleConfig>,
+    pub highlight: Option<StyleConfig>,
+}
+
+impl Config {
+    pub fn default_path() -> Option<path::PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+            _ => path::PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(config_home.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    pub fn load(explicit_path: Option<&str>) -> Result<Config, Box<dyn error::Error>> {
+        let config_path = match explicit_path {
+            Some(explicit_path) => path::PathBuf::from(explicit_path),
+            None => match Config::default_path() {
+                Some(default_path) if default_path.exists() => default_path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let text = fs::read_to_string(&config_path)
+            .map_err(|e| format!("{} {}: {}", ERROR_CONFIG_READ, config_path.display(), e))?;
+
+        toml::from_str(text.as_str())
+            .map_err(|e| format!("{} {}: {}", ERROR_CONFIG_PARSE, config_path.display(), e).into())
+    }
+}
@@ -0,0 +1,336 @@
+use crate::model;
+use crate::net;
+
+use serde::de::DeserializeOwned;
+
+use std::fmt;
+
+static DIRECTIONS: [model::Direction; 4] = [
+    model::Direction::N,
+    model::Direction::E,
+    model::Direction::S,
+    model::Direction::W,
+];
+
+pub enum Outcome {
+    Pass,
+    Fail(String, Option<String>),
+    Skip(String),
+}
+
+pub struct Check {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Pass => write!(f, "PASS  {}", self.name),
+            Outcome::Skip(reason) => write!(f, "SKIP  {}: {}", self.name, reason),
+            Outcome::Fail(reason, body) => {
+                write!(f, "FAIL  {}: {}", self.name, reason)?;
+                match body {
+                    Some(body) => write!(f, "\n      body: {}", body),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn failures(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Fail(_, _)))
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in self.checks.iter() {
+            writeln!(f, "{}", check)?;
+        }
+        let skipped = self
+            .checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Skip(_)))
+            .count();
+        write!(
+            f,
+            "\n{} passed, {} failed, {} skipped",
+            self.checks.len() - self.failures() - skipped,
+            self.failures(),
+            skipped
+        )
+    }
+}
+
+struct Checker {
+    base_url: String,
+    transport: Box<dyn net::Transport>,
+    checks: Vec<Check>,
+}
+
+impl Checker {
+    fn record(&mut self, name: &str, outcome: Outcome) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            outcome,
+        });
+    }
+
+    fn send(&mut self, name: &str, request: net::MunRequest) -> Option<net::RawResponse> {
+        match self.transport.send(&request) {
+            Ok(response) => Some(response),
+            Err(error) => {
+                self.record(name, Outcome::Fail(error, None));
+                None
+            }
+        }
+    }
+
+    fn expect_model<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        request: net::MunRequest,
+    ) -> Option<T> {
+        let response = self.send(name, request)?;
+        if response.status != 200 {
+            self.record(
+                name,
+                Outcome::Fail(
+                    format!("expected status 200, got {}", response.status),
+                    Some(response.body),
+                ),
+            );
+            return None;
+        }
+
+        match serde_json::from_str::<T>(response.body.as_str()) {
+            Ok(object) => {
+                self.record(name, Outcome::Pass);
+                Some(object)
+            }
+            Err(error) => {
+                self.record(name, Outcome::Fail(error.to_string(), Some(response.body)));
+                None
+            }
+        }
+    }
+
+    fn expect_error(&mut self, name: &str, request: net::MunRequest, error_type: model::ErrorType) {
+        let response = match self.send(name, request) {
+            Some(response) => response,
+            None => return,
+        };
+        if response.status != 409 {
+            self.record(
+                name,
+                Outcome::Fail(
+                    format!("expected status 409, got {}", response.status),
+                    Some(response.body),
+                ),
+            );
+            return;
+        }
+
+        let outcome = match serde_json::from_str::<model::ErrorDetail>(response.body.as_str()) {
+            Ok(detail) if detail.r#type.as_ref() == Some(&error_type) => Outcome::Pass,
+            Ok(detail) => Outcome::Fail(
+                format!("expected type {:?}, got {:?}", error_type, detail.r#type),
+                Some(response.body),
+            ),
+            Err(error) => Outcome::Fail(error.to_string(), Some(response.body)),
+        };
+        self.record(name, outcome);
+    }
+
+    fn look_room(&mut self, name: &str, guid: &str) -> Option<model::Room> {
+        self.expect_model::<model::Room>(
+            name,
+            net::MunRequest::Get(format!("{}/{}/regarder", self.base_url, guid)),
+        )
+    }
+
+    fn r#move(
+        &mut self,
+        name: &str,
+        guid: &str,
+        direction: &model::Direction,
+    ) -> Option<model::Room> {
+        self.expect_model::<model::Room>(
+            name,
+            net::MunRequest::Post(
+                format!("{}/{}/deplacement", self.base_url, guid),
+                direction.to_movement_json(),
+            ),
+        )
+    }
+
+    fn examine(&mut self, name: &str, guid: &str, guid_dest: &str) -> Option<model::Entity> {
+        self.expect_model::<model::Entity>(
+            name,
+            net::MunRequest::Get(format!("{}/{}/examiner/{}", self.base_url, guid, guid_dest)),
+        )
+    }
+
+    fn attack(&mut self, name: &str, guid: &str, guid_dest: &str) -> Option<model::Fight> {
+        self.expect_model::<model::Fight>(
+            name,
+            net::MunRequest::Post(
+                format!("{}/{}/taper/{}", self.base_url, guid, guid_dest),
+                String::new(),
+            ),
+        )
+    }
+
+    fn run(&mut self) {
+        let status = match self.expect_model::<model::Status>(
+            "connect",
+            net::MunRequest::Post(format!("{}/connect", self.base_url), String::new()),
+        ) {
+            Some(status) => status,
+            None => return,
+        };
+        let guid = status.guid;
+
+        let room = match self.look_room("look room", &guid) {
+            Some(room) => room,
+            None => return,
+        };
+
+        let mut neighbour_entities: Vec<(model::Direction, Vec<String>)> = Vec::new();
+        for direction in DIRECTIONS.iter() {
+            let name = format!("move {:?}", direction);
+            if room.paths.contains(direction) {
+                if let Some(next_room) = self.r#move(name.as_str(), &guid, direction) {
+                    neighbour_entities.push((direction.clone(), next_room.entities));
+                    self.r#move(
+                        format!("move back {:?}", opposite(direction)).as_str(),
+                        &guid,
+                        &opposite(direction),
+                    );
+                }
+            } else {
+                self.expect_error(
+                    format!("{} into a wall", name).as_str(),
+                    net::MunRequest::Post(
+                        format!("{}/{}/deplacement", self.base_url, guid),
+                        direction.to_movement_json(),
+                    ),
+                    model::ErrorType::Wall,
+                );
+            }
+        }
+        if room.paths.len() == DIRECTIONS.len() {
+            self.record(
+                "move into a wall",
+                Outcome::Skip(String::from("the starting room has no wall")),
+            );
+        }
+
+        if let Some(entity) = self.examine("examine self", &guid, &guid) {
+            if entity.r#type != model::EntityType::Player {
+                self.record(
+                    "examine self type",
+                    Outcome::Fail(
+                        format!(
+                            "expected {:?}, got {:?}",
+                            model::EntityType::Player,
+                            entity.r#type
+                        ),
+                        None,
+                    ),
+                );
+            }
+        }
+
+        let others: Vec<String> = room
+            .entities
+            .iter()
+            .filter(|entity| **entity != guid)
+            .cloned()
+            .collect();
+        for other in others.iter() {
+            self.examine(format!("examine {}", other).as_str(), &guid, other);
+        }
+
+        let elsewhere = neighbour_entities
+            .iter()
+            .flat_map(|(_, entities)| entities.iter())
+            .find(|entity| **entity != guid && !room.entities.contains(entity))
+            .cloned();
+        match elsewhere {
+            Some(elsewhere) => self.expect_error(
+                "examine entity in another room",
+                net::MunRequest::Get(format!("{}/{}/examiner/{}", self.base_url, guid, elsewhere)),
+                model::ErrorType::DiffRoom,
+            ),
+            None => self.record(
+                "examine entity in another room",
+                Outcome::Skip(String::from("no entity seen in a neighbouring room")),
+            ),
+        }
+
+        match others.first() {
+            Some(target) => {
+                self.attack("attack", &guid, target);
+            }
+            None => match neighbour_entities
+                .iter()
+                .find(|(_, entities)| entities.iter().any(|entity| *entity != guid))
+            {
+                Some((direction, entities)) => {
+                    let direction = direction.clone();
+                    let target = entities.iter().find(|entity| **entity != guid).cloned();
+                    if let (Some(_), Some(target)) =
+                        (self.r#move("move to a target", &guid, &direction), target)
+                    {
+                        self.attack("attack", &guid, &target);
+                    }
+                }
+                None => self.record(
+                    "attack",
+                    Outcome::Skip(String::from("no entity to attack near the starting room")),
+                ),
+            },
+        }
+    }
+}
+
+fn opposite(direction: &model::Direction) -> model::Direction {
+    match direction {
+        model::Direction::N => model::Direction::S,
+        model::Direction::S => model::Direction::N,
+        model::Direction::E => model::Direction::W,
+        model::Direction::W => model::Direction::E,
+    }
+}
+
+pub fn check_server(base_url: String, transport: Box<dyn net::Transport>) -> Report {
+    let mut checker = Checker {
+        base_url,
+        transport,
+        checks: Vec::new(),
+    };
+    checker.run();
+
+    let report = Report {
+        checks: checker.checks,
+    };
+    log::info!(
+        target: "conformance",
+        "checks={} failures={}",
+        report.checks.len(),
+        report.failures()
+    );
+
+    report
+}
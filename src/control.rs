@@ -0,0 +1,225 @@
+use crate::httpd;
+use crate::runner;
+use crate::session;
+
+use crossterm::event;
+
+use std::io;
+use std::net;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// What the runner last published for the control API.
+pub struct Published {
+    pub session: String,
+    /// Commands are refused while a bot or a replay drives the session.
+    pub spectating: bool,
+}
+
+pub type SharedSnapshot = Arc<Mutex<Published>>;
+
+/// Binds the control API on localhost; port 0 picks a free one.
+pub fn bind(port: u16) -> Result<net::TcpListener, io::Error> {
+    net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, port))
+}
+
+/// Serves `GET /session` and `POST /command` on `listener`; every request needs
+/// `token` as a bearer token and a `127.0.0.1:<port>` or `localhost:<port>` Host.
+/// Commands get a 409 while the runner is spectating.
+pub fn spawn(
+    listener: net::TcpListener,
+    token: String,
+    sender: mpsc::Sender<runner::ChannelEvent<event::KeyEvent>>,
+) -> Result<SharedSnapshot, io::Error> {
+    let port = listener.local_addr()?.port();
+    log::info!(target: "control", "listening port={}", port);
+    let hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+
+    let snapshot: SharedSnapshot = Arc::new(Mutex::new(Published {
+        session: String::from("{}"),
+        spectating: false,
+    }));
+    let shared = snapshot.clone();
+    let sender = Mutex::new(sender);
+
+    httpd::serve(listener, move |request| {
+        if let Err(response) = httpd::authorize(
+            &request,
+            |host| hosts.iter().any(|allowed| allowed == host),
+            token.as_str(),
+        ) {
+            return response;
+        }
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/session") => match shared.lock() {
+                Ok(published) => httpd::Response::json(200, published.session.clone()),
+                Err(_) => httpd::Response::error(500, "snapshot unavailable"),
+            },
+            ("POST", "/command") => {
+                match serde_json::from_str::<session::Command>(request.body.as_str()) {
+                    Ok(command) => {
+                        match shared.lock() {
+                            Ok(published) if published.spectating => {
+                                log::warn!(
+                                    target: "control",
+                                    "command_rejected reason=spectating command={:?}",
+                                    command
+                                );
+                                return httpd::Response::error(
+                                    409,
+                                    "session is driven by a bot or a replay",
+                                );
+                            }
+                            Ok(_) => (),
+                            Err(_) => return httpd::Response::error(500, "snapshot unavailable"),
+                        }
+                        let sent = match sender.lock() {
+                            Ok(sender) => sender.send(runner::ChannelEvent::Command(command)),
+                            Err(_) => return httpd::Response::error(500, "runner unavailable"),
+                        };
+                        match sent {
+                            Ok(()) => httpd::Response::json(202, String::from("{\"queued\":true}")),
+                            Err(_) => httpd::Response::error(500, "runner has stopped"),
+                        }
+                    }
+                    Err(error) => httpd::Response::error(400, error.to_string().as_str()),
+                }
+            }
+            (_, "/session") | (_, "/command") => httpd::Response::error(405, "method not allowed"),
+            _ => httpd::Response::error(404, "not found"),
+        }
+    });
+
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::io::Write;
+
+    type Events = mpsc::Receiver<runner::ChannelEvent<event::KeyEvent>>;
+
+    fn spawned() -> (String, SharedSnapshot, Events) {
+        let listener = bind(0).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        let snapshot = spawn(listener, String::from("secret"), sender).unwrap();
+
+        (address, snapshot, receiver)
+    }
+
+    fn request(address: &str, head: &str, body: &str) -> String {
+        let mut stream = net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            head,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    fn command(address: &str) -> String {
+        request(
+            address,
+            format!(
+                "POST /command HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer secret\r\nContent-Type: application/json",
+                address
+            )
+            .as_str(),
+            r#"{"command":"disconnect"}"#,
+        )
+    }
+
+    #[test]
+    fn commands_are_queued_for_the_runner() {
+        let (address, snapshot, events) = spawned();
+        snapshot.lock().unwrap().session = String::from(r#"{"connected":true}"#);
+
+        let response = request(
+            address.as_str(),
+            format!(
+                "GET /session HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer secret",
+                address
+            )
+            .as_str(),
+            "",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(r#"{"connected":true}"#), "{}", response);
+
+        let response = command(address.as_str());
+        assert!(response.starts_with("HTTP/1.1 202"), "{}", response);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(runner::ChannelEvent::Command(session::Command::Disconnect))
+        ));
+    }
+
+    #[test]
+    fn requests_need_the_token_a_local_host_and_json() {
+        let (address, _snapshot, events) = spawned();
+        let port = address.rsplit(':').next().unwrap().to_string();
+
+        for (head, status) in [
+            (
+                format!("GET /session HTTP/1.1\r\nHost: {}", address),
+                "401",
+            ),
+            (
+                format!(
+                    "GET /session HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer guess",
+                    address
+                ),
+                "401",
+            ),
+            (
+                format!(
+                    "GET /session HTTP/1.1\r\nHost: evil.example:{}\r\nAuthorization: Bearer secret",
+                    port
+                ),
+                "403",
+            ),
+            (
+                format!(
+                    "POST /command HTTP/1.1\r\nHost: localhost:{}\r\nAuthorization: Bearer secret\r\nContent-Type: text/plain",
+                    port
+                ),
+                "415",
+            ),
+        ] {
+            let response = request(address.as_str(), head.as_str(), r#"{"command":"disconnect"}"#);
+            assert!(
+                response.starts_with(format!("HTTP/1.1 {}", status).as_str()),
+                "{}: {}",
+                head,
+                response
+            );
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn commands_are_refused_while_spectating() {
+        let (address, snapshot, events) = spawned();
+        snapshot.lock().unwrap().spectating = true;
+
+        let response = command(address.as_str());
+        assert!(response.starts_with("HTTP/1.1 409"), "{}", response);
+        assert!(events.try_recv().is_err());
+
+        snapshot.lock().unwrap().spectating = false;
+        let response = command(address.as_str());
+        assert!(response.starts_with("HTTP/1.1 202"), "{}", response);
+    }
+}
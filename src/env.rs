@@ -0,0 +1,466 @@
+use crate::bot;
+use crate::model;
+use crate::session;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+
+static ERROR_TRANSITIONS_OPEN: &str = "Could not open transitions file";
+static ERROR_TRANSITIONS_WRITE: &str = "Could not write transition";
+
+/// Number of entity slots addressable by attack and examine actions.
+pub static ENTITY_SLOTS: usize = 4;
+/// Size of the discrete action space: 4 moves, attack and examine per slot, wait.
+pub static ACTION_COUNT: usize = 4 + 2 * ENTITY_SLOTS + 1;
+/// Length of an observation: exits bitmask, monsters, players, unknown entities, HP ratio.
+pub static OBSERVATION_SIZE: usize = 5;
+/// Steps after which an episode is truncated unless [`Env::max_steps`] is changed.
+pub static DEFAULT_MAX_STEPS: u64 = 200;
+
+static DIRECTIONS: [model::Direction; 4] = [
+    model::Direction::N,
+    model::Direction::E,
+    model::Direction::S,
+    model::Direction::W,
+];
+
+#[derive(Clone, Debug)]
+pub struct RewardWeights {
+    pub damage_dealt: f64,
+    pub damage_taken: f64,
+    pub room_discovered: f64,
+    pub kill: f64,
+    pub death: f64,
+    pub step: f64,
+}
+
+impl Default for RewardWeights {
+    fn default() -> RewardWeights {
+        RewardWeights {
+            damage_dealt: 0.1,
+            damage_taken: -0.1,
+            room_discovered: 1.0,
+            kill: 5.0,
+            death: -10.0,
+            step: -0.01,
+        }
+    }
+}
+
+pub struct StepResult {
+    pub observation: Vec<f32>,
+    pub reward: f64,
+    pub done: bool,
+    pub info: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct Transition<'a> {
+    episode: u64,
+    step: u64,
+    observation: &'a [f32],
+    action: usize,
+    reward: f64,
+    next_observation: &'a [f32],
+    done: bool,
+    info: &'a serde_json::Map<String, serde_json::Value>,
+}
+
+/// Gym-style environment over a [`session::Session`].
+pub struct Env {
+    session: session::Session,
+    pub weights: RewardWeights,
+    pub max_steps: u64,
+    entity_types: HashMap<String, model::EntityType>,
+    visited: HashSet<session::Position>,
+    observation: Vec<f32>,
+    episode: u64,
+    steps: u64,
+    kills: u64,
+    transitions: Option<fs::File>,
+}
+
+impl Env {
+    pub fn new(session: session::Session) -> Env {
+        Env {
+            session,
+            weights: RewardWeights::default(),
+            max_steps: DEFAULT_MAX_STEPS,
+            entity_types: HashMap::new(),
+            visited: HashSet::new(),
+            observation: vec![0.0; OBSERVATION_SIZE],
+            episode: 0,
+            steps: 0,
+            kills: 0,
+            transitions: None,
+        }
+    }
+
+    /// Appends every transition taken with [`Env::step_index`] to a JSONL file.
+    pub fn record_to(&mut self, path: &str) -> Result<(), String> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{} {}: {}", ERROR_TRANSITIONS_OPEN, path, e))?;
+        self.transitions = Some(file);
+
+        Ok(())
+    }
+
+    /// Maps a discrete action index to an action on the current room.
+    pub fn action(index: usize) -> bot::Action {
+        match index {
+            0..=3 => bot::Action::Move(DIRECTIONS[index].clone()),
+            i if i < 4 + ENTITY_SLOTS => bot::Action::Attack((i - 3).to_string()),
+            i if i < 4 + 2 * ENTITY_SLOTS => {
+                bot::Action::Examine((i - 3 - ENTITY_SLOTS).to_string())
+            }
+            _ => bot::Action::Wait,
+        }
+    }
+
+    /// Connects a fresh player and returns the first observation.
+    pub fn reset(&mut self) -> Result<Vec<f32>, model::Error> {
+        self.session.disconnect();
+        self.session.connect();
+        if let Some(error) = self.session.error.take() {
+            return Err(error);
+        }
+        self.session.update();
+
+        self.episode += 1;
+        self.steps = 0;
+        self.kills = 0;
+        self.visited.clear();
+        self.visited.insert(self.session.position);
+        self.observation = self.observe();
+        log::info!(target: "env", "reset episode={}", self.episode);
+
+        Ok(self.observation.clone())
+    }
+
+    fn life(&self) -> u32 {
+        self.session
+            .status
+            .as_ref()
+            .and_then(|status| status.life)
+            .unwrap_or(0)
+    }
+
+    fn observe(&mut self) -> Vec<f32> {
+        let (guid, room) = match &self.session.status {
+            Some(status) => (status.guid.clone(), status.room.clone()),
+            None => return vec![0.0; OBSERVATION_SIZE],
+        };
+
+        // Types are only known once an examine action revealed them
+        for (entity, examined) in &self.session.examined {
+            self.entity_types
+                .insert(entity.clone(), examined.r#type.clone());
+        }
+
+        let exits = room.paths.iter().fold(0u8, |mask, direction| {
+            mask | match direction {
+                model::Direction::N => 1,
+                model::Direction::E => 2,
+                model::Direction::S => 4,
+                model::Direction::W => 8,
+            }
+        });
+        let (mut monsters, mut players, mut unknown) = (0, 0, 0);
+        for entity in room.entities.iter().filter(|entity| **entity != guid) {
+            match self.entity_types.get(entity) {
+                Some(model::EntityType::Monster) => monsters += 1,
+                Some(model::EntityType::Player) => players += 1,
+                None => unknown += 1,
+            }
+        }
+        let life_ratio = match &self.session.status {
+            Some(status) if status.total_life > 0 => self.life() as f32 / status.total_life as f32,
+            _ => 0.0,
+        };
+
+        vec![
+            exits as f32,
+            monsters as f32,
+            players as f32,
+            unknown as f32,
+            life_ratio,
+        ]
+    }
+
+    pub fn step(&mut self, action: bot::Action) -> StepResult {
+        let life_before = self.life();
+        let mut reward = self.weights.step;
+        let mut info = serde_json::Map::new();
+
+        self.session.clear_infos();
+        if let Some(command) = action.to_command() {
+            self.session.apply(command);
+        } else {
+            self.session.update();
+        }
+        self.steps += 1;
+
+        if let Some(fight) = &self.session.fight_info {
+            reward += self.weights.damage_dealt * fight.attacker.damage as f64;
+            if fight.defender.life == 0 {
+                self.kills += 1;
+                reward += self.weights.kill;
+            }
+        }
+        let life_after = self.life();
+        if life_after < life_before {
+            reward += self.weights.damage_taken * (life_before - life_after) as f64;
+        }
+        if let Some(status) = &self.session.status {
+            if self.visited.insert(self.session.position) {
+                reward += self.weights.room_discovered;
+            }
+            info.insert(String::from("room"), status.room.description.clone().into());
+        }
+
+        let dead = matches!(
+            &self.session.error,
+            Some(model::Error {
+                detail: model::ErrorDetail {
+                    r#type: Some(model::ErrorType::Dead),
+                    ..
+                },
+                ..
+            })
+        );
+        if dead {
+            reward += self.weights.death;
+            self.session.disconnect();
+        }
+        let truncated = self.steps >= self.max_steps;
+
+        if let Some(error) = &self.session.error {
+            info.insert(String::from("error"), error.detail.message.clone().into());
+        }
+        info.insert(String::from("action"), format!("{:?}", action).into());
+        info.insert(String::from("life"), life_after.into());
+        info.insert(String::from("kills"), self.kills.into());
+        info.insert(String::from("dead"), dead.into());
+        info.insert(String::from("truncated"), truncated.into());
+
+        self.observation = self.observe();
+
+        StepResult {
+            observation: self.observation.clone(),
+            reward,
+            done: dead || truncated,
+            info,
+        }
+    }
+
+    /// Steps with a discrete action index and records the transition if enabled.
+    pub fn step_index(&mut self, index: usize) -> StepResult {
+        let observation = self.observation.clone();
+        let result = self.step(Env::action(index));
+
+        if let Some(file) = &mut self.transitions {
+            let transition = Transition {
+                episode: self.episode,
+                step: self.steps,
+                observation: &observation,
+                action: index,
+                reward: result.reward,
+                next_observation: &result.observation,
+                done: result.done,
+                info: &result.info,
+            };
+            let written = serde_json::to_string(&transition)
+                .map_err(|e| e.to_string())
+                .and_then(|line| writeln!(file, "{}", line).map_err(|e| e.to_string()));
+            if let Err(error) = written {
+                log::warn!(target: "env", "{}: {}", ERROR_TRANSITIONS_WRITE, error);
+            }
+        }
+
+        result
+    }
+}
+
+/// Runs `episodes` episodes of a uniformly random policy, e.g. to produce transitions.
+///
+/// Each episode ends on death or after [`Env::max_steps`] steps.
+pub fn run_random(
+    env: &mut Env,
+    episodes: u64,
+    seed: Option<u64>,
+) -> Result<(u64, f64), model::Error> {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut total_steps = 0;
+    let mut total_reward = 0.0;
+
+    for _ in 0..episodes {
+        env.reset()?;
+        loop {
+            let result = env.step_index(rng.gen_range(0..ACTION_COUNT));
+            total_steps += 1;
+            total_reward += result.reward;
+            if result.done {
+                break;
+            }
+        }
+    }
+
+    Ok((total_steps, total_reward))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history;
+    use crate::net;
+    use crate::sim;
+
+    static BASE_URL: &str = "http://offline";
+    static MOVE_EAST: usize = 1;
+    static ATTACK_FIRST: usize = 4;
+    static EXAMINE_FIRST: usize = 4 + ENTITY_SLOTS;
+    static WAIT: usize = ACTION_COUNT - 1;
+
+    fn env(dungeon: &str) -> Env {
+        let path = format!("{}/dungeons/{}", env!("CARGO_MANIFEST_DIR"), dungeon);
+        let world = sim::World::load(path.as_str(), 7).unwrap();
+
+        Env::new(session::Session::with_client(
+            net::MunHttpClient::with_transport(
+                BASE_URL.to_string(),
+                Box::new(sim::SimTransport::new(BASE_URL.to_string(), world)),
+            ),
+        ))
+    }
+
+    #[test]
+    fn observations_count_exits_and_entities() {
+        let mut dead_end = env("dead_end.toml");
+        assert_eq!(dead_end.reset().unwrap(), vec![2.0, 0.0, 0.0, 0.0, 1.0]);
+
+        let result = dead_end.step_index(MOVE_EAST);
+        assert_eq!(result.observation, vec![8.0, 0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(result.info["room"], "Dead end");
+        // The troll is known to be a monster once examined
+        let result = dead_end.step_index(EXAMINE_FIRST);
+        assert_eq!(result.observation, vec![8.0, 1.0, 0.0, 0.0, 1.0]);
+
+        let mut hall = env("two_players.toml");
+        assert_eq!(hall.reset().unwrap(), vec![0.0, 0.0, 0.0, 2.0, 1.0]);
+        let result = hall.step_index(EXAMINE_FIRST);
+        assert_eq!(result.observation, vec![0.0, 0.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn discovering_a_room_is_rewarded_once() {
+        let mut env = env("dead_end.toml");
+        env.reset().unwrap();
+        let step = env.weights.step;
+
+        let result = env.step_index(MOVE_EAST);
+        assert_eq!(result.reward, step + env.weights.room_discovered);
+        let result = env.step_index(0);
+        assert_eq!(result.reward, step);
+        assert!(!result.done);
+    }
+
+    #[test]
+    fn death_is_punished_and_ends_the_episode() {
+        let mut env = env("dead_end.toml");
+        env.weights = RewardWeights {
+            damage_dealt: 0.0,
+            damage_taken: 0.0,
+            room_discovered: 0.0,
+            kill: 0.0,
+            death: -10.0,
+            step: 0.0,
+        };
+        env.reset().unwrap();
+        env.step_index(MOVE_EAST);
+
+        let mut last = None;
+        for _ in 0..10 {
+            let result = env.step_index(ATTACK_FIRST);
+            let done = result.done;
+            last = Some(result);
+            if done {
+                break;
+            }
+            assert_eq!(last.as_ref().unwrap().reward, 0.0);
+        }
+        let last = last.unwrap();
+        assert!(last.done);
+        assert_eq!(last.reward, -10.0);
+        assert_eq!(last.info["dead"], true);
+        assert_eq!(last.info["truncated"], false);
+        assert_eq!(last.observation, vec![0.0; OBSERVATION_SIZE]);
+    }
+
+    #[test]
+    fn episodes_are_truncated_at_max_steps() {
+        let mut env = env("dead_end.toml");
+        env.max_steps = 3;
+        env.reset().unwrap();
+
+        assert!(!env.step_index(WAIT).done);
+        assert!(!env.step_index(WAIT).done);
+        let result = env.step_index(WAIT);
+        assert!(result.done);
+        assert_eq!(result.info["truncated"], true);
+        assert_eq!(result.info["dead"], false);
+
+        // A reset starts counting again
+        env.reset().unwrap();
+        assert!(!env.step_index(WAIT).done);
+    }
+
+    #[test]
+    fn every_indexed_step_is_recorded() {
+        let path = std::env::temp_dir().join(format!(
+            "mungeon-transitions-{}-{}.jsonl",
+            std::process::id(),
+            history::now()
+        ));
+        let mut env = env("dead_end.toml");
+        env.record_to(path.to_str().unwrap()).unwrap();
+        env.reset().unwrap();
+
+        env.step_index(MOVE_EAST);
+        env.step(bot::Action::Wait);
+        env.step_index(EXAMINE_FIRST);
+        env.step_index(WAIT);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let transitions: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(transitions.len(), 3);
+        let actions: Vec<u64> = transitions
+            .iter()
+            .map(|transition| transition["action"].as_u64().unwrap())
+            .collect();
+        assert_eq!(
+            actions,
+            vec![MOVE_EAST as u64, EXAMINE_FIRST as u64, WAIT as u64]
+        );
+        assert_eq!(transitions[0]["episode"], 1);
+        assert_eq!(transitions[0]["observation"][0], 2.0);
+        assert_eq!(transitions[0]["next_observation"][0], 8.0);
+        assert_eq!(transitions[1]["step"], 3);
+    }
+}
@@ -0,0 +1,537 @@
+//! Statistics of a character's run, and the local file finished runs are appended to.
+
+use crate::logging;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::time;
+
+static HISTORY_DIR: &str = "mungeon";
+static HISTORY_FILE: &str = "history.jsonl";
+
+static ERROR_HISTORY_WRITE: &str = "Could not write run history";
+static ERROR_REPORT_WRITE: &str = "Could not write run report";
+static ERROR_HISTORY_READ: &str = "Could not read run history";
+static ERROR_UNKNOWN_SORT: &str =
+    "Unknown sort key, expected survived, kills, damage, rooms or recent";
+static ERROR_UNKNOWN_CAUSE: &str = "Unknown end cause, expected death, disconnect or quit";
+
+static CSV_HEADER: &str = "guid,server,started_at,ended_at,cause,survived_seconds,rooms_explored,kills,damage_dealt,damage_taken,last_room,last_opponent";
+
+/// Why a run ended.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndCause {
+    Death,
+    Disconnect,
+    Quit,
+}
+
+impl EndCause {
+    pub fn parse(data: &str) -> Result<EndCause, String> {
+        match data {
+            "death" => Ok(EndCause::Death),
+            "disconnect" => Ok(EndCause::Disconnect),
+            "quit" => Ok(EndCause::Quit),
+            _ => Err(format!("{}: got \"{}\"", ERROR_UNKNOWN_CAUSE, data)),
+        }
+    }
+}
+
+impl fmt::Display for EndCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EndCause::Death => write!(f, "death"),
+            EndCause::Disconnect => write!(f, "disconnect"),
+            EndCause::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+/// Counters kept by a [`Session`](crate::session::Session) while a character is alive.
+#[derive(Clone, Debug, Default)]
+pub struct RunStats {
+    pub started_at: u64,
+    /// Rooms visited, by [`Position`](crate::session::Position).
+    pub rooms: BTreeSet<(i64, i64)>,
+    pub kills: u32,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    /// Last entity we fought, or the main suspect of the last unprovoked hit.
+    pub last_opponent: Option<String>,
+    /// Descriptions of the entities examined so far, by guid.
+    pub descriptions: HashMap<String, String>,
+}
+
+impl RunStats {
+    pub fn new() -> RunStats {
+        RunStats {
+            started_at: now(),
+            ..RunStats::default()
+        }
+    }
+}
+
+/// One finished run, as stored in the history file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunRecord {
+    pub guid: String,
+    pub base_url: String,
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub cause: EndCause,
+    pub rooms_explored: usize,
+    pub kills: u32,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    pub last_room: Option<String>,
+    pub last_opponent: Option<String>,
+}
+
+impl RunRecord {
+    pub fn survived(&self) -> u64 {
+        self.ended_at.saturating_sub(self.started_at)
+    }
+
+    pub fn to_markdown(&self, log_lines: &[String]) -> String {
+        let mut markdown = format!(
+            "# Run {}\n\n\
+             | | |\n\
+             |---|---|\n\
+             | Server | {} |\n\
+             | End | {:?} |\n\
+             | Survived | {} |\n\
+             | Rooms explored | {} |\n\
+             | Kills | {} |\n\
+             | Damage dealt | {} |\n\
+             | Damage taken | {} |\n\
+             | Last room | {} |\n\
+             | Last opponent | {} |\n",
+            self.guid,
+            self.base_url,
+            self.cause,
+            format_duration(self.survived()),
+            self.rooms_explored,
+            self.kills,
+            self.damage_dealt,
+            self.damage_taken,
+            self.last_room.as_deref().unwrap_or("-"),
+            self.last_opponent.as_deref().unwrap_or("-")
+        );
+
+        if !log_lines.is_empty() {
+            markdown += "\n## Last log entries\n\n```\n";
+            for line in log_lines {
+                markdown += line;
+                markdown += "\n";
+            }
+            markdown += "```\n";
+        }
+
+        markdown
+    }
+}
+
+/// Leaderboard order, best first.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SortKey {
+    Survived,
+    Kills,
+    Damage,
+    Rooms,
+    Recent,
+}
+
+impl SortKey {
+    pub fn parse(data: &str) -> Result<SortKey, String> {
+        match data {
+            "survived" => Ok(SortKey::Survived),
+            "kills" => Ok(SortKey::Kills),
+            "damage" => Ok(SortKey::Damage),
+            "rooms" => Ok(SortKey::Rooms),
+            "recent" => Ok(SortKey::Recent),
+            _ => Err(format!("{}: got \"{}\"", ERROR_UNKNOWN_SORT, data)),
+        }
+    }
+
+    pub fn next(&self) -> SortKey {
+        match self {
+            SortKey::Survived => SortKey::Kills,
+            SortKey::Kills => SortKey::Damage,
+            SortKey::Damage => SortKey::Rooms,
+            SortKey::Rooms => SortKey::Recent,
+            SortKey::Recent => SortKey::Survived,
+        }
+    }
+
+    fn value(&self, record: &RunRecord) -> u64 {
+        match self {
+            SortKey::Survived => record.survived(),
+            SortKey::Kills => record.kills as u64,
+            SortKey::Damage => record.damage_dealt as u64,
+            SortKey::Rooms => record.rooms_explored as u64,
+            SortKey::Recent => record.ended_at,
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortKey::Survived => write!(f, "survived"),
+            SortKey::Kills => write!(f, "kills"),
+            SortKey::Damage => write!(f, "damage"),
+            SortKey::Rooms => write!(f, "rooms"),
+            SortKey::Recent => write!(f, "recent"),
+        }
+    }
+}
+
+/// Which records to list, and in what order.
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub sort: SortKey,
+    pub cause: Option<EndCause>,
+    /// Keeps records whose server URL contains this text.
+    pub server: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl Default for Query {
+    fn default() -> Query {
+        Query {
+            sort: SortKey::Survived,
+            cause: None,
+            server: None,
+            limit: None,
+        }
+    }
+}
+
+/// All recorded runs, one per guid.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    pub records: Vec<RunRecord>,
+}
+
+impl History {
+    /// Reads a history file, keeping the last record of each guid. A missing file is an empty history.
+    pub fn load(path: &path::Path) -> Result<History, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(History::default()),
+            Err(e) => return Err(format!("{} {}: {}", ERROR_HISTORY_READ, path.display(), e)),
+        };
+
+        let mut records: Vec<RunRecord> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<RunRecord>(line) {
+                Ok(record) => {
+                    records.retain(|other| other.guid != record.guid);
+                    records.push(record);
+                }
+                Err(error) => log::warn!(
+                    target: "history",
+                    "skipped line={} error=\"{}\"",
+                    number + 1,
+                    error
+                ),
+            }
+        }
+
+        Ok(History { records })
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<&RunRecord> {
+        let mut records: Vec<&RunRecord> = self
+            .records
+            .iter()
+            .filter(|record| query.cause.map_or(true, |cause| record.cause == cause))
+            .filter(|record| {
+                query
+                    .server
+                    .as_ref()
+                    .map_or(true, |server| record.base_url.contains(server.as_str()))
+            })
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(query.sort.value(record)));
+        if let Some(limit) = query.limit {
+            records.truncate(limit);
+        }
+
+        records
+    }
+}
+
+/// One line per record, for terminals and popups.
+pub fn table(records: &[&RunRecord], with_date: bool) -> Vec<String> {
+    let row = |columns: [String; 7], date: String| -> String {
+        let mut line = format!(
+            "{:>3}  {:<8}  {:<10}  {:>9}  {:>5}  {:>6}  {:>5}",
+            columns[0], columns[1], columns[2], columns[3], columns[4], columns[5], columns[6]
+        );
+        if with_date {
+            line += format!("  {}", date).as_str();
+        }
+        line
+    };
+
+    let mut lines = vec![row(
+        [
+            String::from("#"),
+            String::from("guid"),
+            String::from("end"),
+            String::from("survived"),
+            String::from("kills"),
+            String::from("damage"),
+            String::from("rooms"),
+        ],
+        String::from("ended at"),
+    )];
+    for (rank, record) in records.iter().enumerate() {
+        lines.push(row(
+            [
+                (rank + 1).to_string(),
+                record.guid.chars().take(8).collect(),
+                record.cause.to_string(),
+                format_duration(record.survived()),
+                record.kills.to_string(),
+                record.damage_dealt.to_string(),
+                record.rooms_explored.to_string(),
+            ],
+            date(record.ended_at),
+        ));
+    }
+
+    lines
+}
+
+fn date(seconds: u64) -> String {
+    logging::timestamp(time::UNIX_EPOCH + time::Duration::from_secs(seconds))
+        .chars()
+        .take(16)
+        .collect::<String>()
+        .replace('T', " ")
+}
+
+fn csv_field(field: &str) -> String {
+    // Server text starting like a formula would be evaluated by spreadsheets
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+pub fn to_csv(records: &[&RunRecord]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for record in records {
+        let fields = [
+            record.guid.clone(),
+            record.base_url.clone(),
+            logging::timestamp(time::UNIX_EPOCH + time::Duration::from_secs(record.started_at)),
+            logging::timestamp(time::UNIX_EPOCH + time::Duration::from_secs(record.ended_at)),
+            record.cause.to_string(),
+            record.survived().to_string(),
+            record.rooms_explored.to_string(),
+            record.kills.to_string(),
+            record.damage_dealt.to_string(),
+            record.damage_taken.to_string(),
+            record.last_room.clone().unwrap_or_default(),
+            record.last_opponent.clone().unwrap_or_default(),
+        ];
+        csv += fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<String>>()
+            .join(",")
+            .as_str();
+        csv += "\n";
+    }
+
+    csv
+}
+
+pub fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    match (seconds / 3_600, (seconds % 3_600) / 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+/// The configured history file, or the default one.
+pub fn resolve_path(configured: Option<&str>) -> Option<path::PathBuf> {
+    match configured {
+        Some(configured) => Some(path::PathBuf::from(configured)),
+        None => default_path(),
+    }
+}
+
+pub fn default_path() -> Option<path::PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+        _ => path::PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+
+    Some(state_home.join(HISTORY_DIR).join(HISTORY_FILE))
+}
+
+/// Appends `record` as one JSON line.
+pub fn append(path: &path::Path, record: &RunRecord) -> Result<(), String> {
+    let error = |e: String| format!("{} {}: {}", ERROR_HISTORY_WRITE, path.display(), e);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| error(e.to_string()))?;
+    }
+    let line = serde_json::to_string(record).map_err(|e| error(e.to_string()))?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| error(e.to_string()))?;
+
+    writeln!(file, "{}", line).map_err(|e| error(e.to_string()))
+}
+
+/// Writes a report of `record` to `directory`, as markdown or JSON, and returns its path.
+pub fn save_report(
+    directory: &path::Path,
+    record: &RunRecord,
+    log_lines: &[String],
+    json: bool,
+) -> Result<path::PathBuf, String> {
+    let short_guid: String = record.guid.chars().take(8).collect();
+    let path = directory.join(format!(
+        "run-{}.{}",
+        short_guid,
+        if json { "json" } else { "md" }
+    ));
+    let contents = if json {
+        let mut value = serde_json::json!(record);
+        value["log"] = serde_json::json!(log_lines);
+        serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+    } else {
+        Ok(record.to_markdown(log_lines))
+    };
+
+    contents
+        .and_then(|contents| fs::write(&path, contents).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{} {}: {}", ERROR_REPORT_WRITE, path.display(), e))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(guid: &str, base_url: &str, cause: EndCause, survived: u64, kills: u32) -> RunRecord {
+        RunRecord {
+            guid: guid.to_string(),
+            base_url: base_url.to_string(),
+            started_at: 1_000,
+            ended_at: 1_000 + survived,
+            cause,
+            rooms_explored: 1,
+            kills,
+            damage_dealt: 0,
+            damage_taken: 0,
+            last_room: None,
+            last_opponent: None,
+        }
+    }
+
+    fn guids(records: Vec<&RunRecord>) -> Vec<&str> {
+        records.iter().map(|record| record.guid.as_str()).collect()
+    }
+
+    #[test]
+    fn queries_sort_filter_and_limit() {
+        let history = History {
+            records: vec![
+                record("a", "http://localhost:8000", EndCause::Death, 30, 2),
+                record("b", "http://example.com", EndCause::Quit, 90, 0),
+                record("c", "http://localhost:8000", EndCause::Death, 60, 5),
+                record("d", "http://localhost:9000", EndCause::Disconnect, 10, 1),
+            ],
+        };
+
+        assert_eq!(
+            guids(history.query(&Query::default())),
+            ["b", "c", "a", "d"]
+        );
+        let by_kills = Query {
+            sort: SortKey::Kills,
+            ..Query::default()
+        };
+        assert_eq!(guids(history.query(&by_kills)), ["c", "a", "d", "b"]);
+        let deaths = Query {
+            cause: Some(EndCause::Death),
+            ..Query::default()
+        };
+        assert_eq!(guids(history.query(&deaths)), ["c", "a"]);
+        let local = Query {
+            server: Some(String::from("localhost")),
+            limit: Some(2),
+            ..Query::default()
+        };
+        assert_eq!(guids(history.query(&local)), ["c", "a"]);
+        let none = Query {
+            limit: Some(0),
+            ..Query::default()
+        };
+        assert!(history.query(&none).is_empty());
+    }
+
+    #[test]
+    fn csv_has_a_header_and_one_row_per_record() {
+        let mut troll = record("a", "http://localhost:8000", EndCause::Death, 90, 2);
+        troll.last_opponent = Some(String::from("A troll, angry"));
+        let csv = to_csv(&[&troll]);
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "a,http://localhost:8000,1970-01-01T00:16:40.000Z,1970-01-01T00:18:10.000Z,death,90,1,2,0,0,,\"A troll, angry\""
+        );
+        assert_eq!(to_csv(&[]), format!("{}\n", CSV_HEADER));
+    }
+
+    #[test]
+    fn csv_fields_cannot_start_formulas() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("A troll, angry"), "\"A troll, angry\"");
+        assert_eq!(csv_field("Dead end"), "Dead end");
+    }
+}
@@ -0,0 +1,320 @@
+#[cfg(feature = "tui")]
+use rand::Rng;
+
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::net;
+use std::sync::atomic;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+static MAX_BODY_SIZE: usize = 64 * 1024;
+static MAX_HEADER_SIZE: u64 = 8 * 1024;
+static MAX_CONNECTIONS: usize = 16;
+static READ_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    /// First value of header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, body: String) -> Response {
+        Response { status, body }
+    }
+
+    pub fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+// Reads one line of the request head, which may not grow past MAX_HEADER_SIZE in total
+fn read_head_line<R: BufRead>(head: &mut io::Take<R>) -> Result<String, Response> {
+    let mut line = String::new();
+    head.read_line(&mut line)
+        .map_err(|e| Response::error(400, e.to_string().as_str()))?;
+    if !line.ends_with('\n') {
+        return Err(match head.limit() {
+            0 => Response::error(431, "request head too large"),
+            _ => Response::error(400, "incomplete request head"),
+        });
+    }
+
+    Ok(line)
+}
+
+fn read_request(stream: &net::TcpStream) -> Result<Request, Response> {
+    let mut reader = io::BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEADER_SIZE);
+    let line = read_head_line(&mut head)?;
+
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(Response::error(400, "malformed request line")),
+    };
+
+    let mut request = Request {
+        method,
+        path,
+        headers: Vec::new(),
+        body: String::new(),
+    };
+    loop {
+        let header = read_head_line(&mut head)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            request
+                .headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let content_length = request
+        .header("content-length")
+        .map_or(Ok(0), str::parse::<usize>)
+        .map_err(|_| Response::error(400, "invalid content-length"))?;
+    if content_length > MAX_BODY_SIZE {
+        return Err(Response::error(413, "body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| Response::error(400, e.to_string().as_str()))?;
+    request.body = String::from_utf8_lossy(&body).into_owned();
+
+    Ok(request)
+}
+
+fn write_response(mut stream: &net::TcpStream, response: Response) -> Result<(), io::Error> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+#[cfg(feature = "tui")]
+/// A random bearer token for one run of a local API.
+pub fn token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..16)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+#[cfg(feature = "tui")]
+// Takes the same time wherever the first difference is
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(feature = "tui")]
+/// Checks what a browser page could otherwise forge: the Host header (against DNS
+/// rebinding), the `Authorization: Bearer` token, and a JSON body on POST (a
+/// `text/plain` POST needs no CORS preflight).
+pub fn authorize<F>(request: &Request, host_allowed: F, token: &str) -> Result<(), Response>
+where
+    F: Fn(&str) -> bool,
+{
+    if !request.header("host").is_some_and(host_allowed) {
+        return Err(Response::error(403, "host not allowed"));
+    }
+
+    let given = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !same_token(given.trim(), token) {
+        return Err(Response::error(401, "missing or wrong token"));
+    }
+
+    let json = request
+        .header("content-type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"));
+    if request.method == "POST" && !json {
+        return Err(Response::error(415, "body must be application/json"));
+    }
+
+    Ok(())
+}
+
+// Counts a connection as open until dropped, even if its handler panics
+struct Open(Arc<atomic::AtomicUsize>);
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+/// Answers each connection on its own thread, at most `MAX_CONNECTIONS` at a time;
+/// connections beyond that get a 503 straight away.
+pub fn serve<H>(listener: net::TcpListener, handler: H)
+where
+    H: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let open = Arc::new(atomic::AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    log::warn!(target: "httpd", "accept_failed error=\"{}\"", error);
+                    continue;
+                }
+            };
+            if open.fetch_add(1, atomic::Ordering::SeqCst) >= MAX_CONNECTIONS {
+                open.fetch_sub(1, atomic::Ordering::SeqCst);
+                log::warn!(target: "httpd", "connection_refused open={}", MAX_CONNECTIONS);
+                let _ = stream.set_write_timeout(Some(READ_TIMEOUT));
+                let _ = write_response(&stream, Response::error(503, "too many connections"));
+                continue;
+            }
+            let handler = handler.clone();
+            let connection = Open(open.clone());
+
+            thread::spawn(move || {
+                let _connection = connection;
+                let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                let response = match read_request(&stream) {
+                    Ok(request) => {
+                        log::debug!(target: "httpd", "method={} path={}", request.method, request.path);
+                        handler(request)
+                    }
+                    Err(response) => response,
+                };
+                if let Err(error) = write_response(&stream, response) {
+                    log::warn!(target: "httpd", "write_failed error=\"{}\"", error);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn served() -> String {
+        let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        serve(listener, |request| Response::json(200, request.path));
+
+        address
+    }
+
+    fn exchange(address: &str, data: &[u8]) -> String {
+        let mut stream = net::TcpStream::connect(address).unwrap();
+        // The server may answer and close before reading everything
+        let _ = stream.write_all(data);
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+
+        response
+    }
+
+    #[test]
+    fn requests_are_answered() {
+        let address = served();
+        let response = exchange(address.as_str(), b"GET /hello HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("/hello"), "{}", response);
+    }
+
+    #[test]
+    fn oversized_heads_are_rejected() {
+        let address = served();
+
+        // Exactly the cap, so nothing is left unread to reset the connection
+        let mut line = format!("GET /{} HTTP/1.1", "a".repeat(MAX_HEADER_SIZE as usize));
+        line.truncate(MAX_HEADER_SIZE as usize);
+        let response = exchange(address.as_str(), line.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+        let mut head = String::from("GET / HTTP/1.1\r\n");
+        while head.len() <= MAX_HEADER_SIZE as usize {
+            head.push_str("X-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n");
+        }
+        head.truncate(MAX_HEADER_SIZE as usize);
+        let response = exchange(address.as_str(), head.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+    }
+
+    #[test]
+    fn connections_beyond_the_cap_are_refused() {
+        let address = served();
+        // Idle connections hold their thread until the read timeout
+        let idle: Vec<net::TcpStream> = (0..MAX_CONNECTIONS)
+            .map(|_| net::TcpStream::connect(address.as_str()).unwrap())
+            .collect();
+
+        let response = exchange(address.as_str(), b"");
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+
+        drop(idle);
+        // Closed connections free their slot once their thread notices
+        let answered = (0..50).any(|_| {
+            thread::sleep(time::Duration::from_millis(20));
+            exchange(address.as_str(), b"GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200")
+        });
+        assert!(answered);
+    }
+}
@@ -0,0 +1,623 @@
+use crossterm::event;
+
+use std::collections::HashMap;
+use std::fmt;
+
+static ERROR_UNKNOWN_ACTION: &str = "Unknown action in keymap";
+static ERROR_KEY_PARSE: &str = "Could not parse key binding";
+static ERROR_KEY_CONFLICT: &str = "Conflicting key bindings";
+
+static QUICK_TARGET_KEYS: [&str; 9] = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Context {
+    Global,
+    Normal,
+    Popup,
+    Replay,
+    Death,
+    // Switching and closing tabs, also possible from the death screen
+    Tabs,
+}
+
+impl Context {
+    fn overlaps(&self, other: &Context) -> bool {
+        match (self, other) {
+            (Context::Global, _) | (_, Context::Global) => true,
+            (Context::Tabs, Context::Normal | Context::Death)
+            | (Context::Normal | Context::Death, Context::Tabs) => true,
+            _ => self == other,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Action {
+    Quit,
+    Suspend,
+    Connect,
+    Disconnect,
+    Look,
+    Examine,
+    Attack,
+    Help,
+    MoveNorth,
+    MoveSouth,
+    MoveEast,
+    MoveWest,
+    Confirm,
+    SelectPrevious,
+    SelectNext,
+    QuickTarget(u8),
+    ReplayNext,
+    ReplayPrevious,
+    ReplayPlayPause,
+    ReplayFaster,
+    ReplaySlower,
+    ReplayNextEvent,
+    ReplayPreviousEvent,
+    ReplayDeath,
+    ReplayFirst,
+    ReplayLast,
+    NewCharacter,
+    SaveReport,
+    Leaderboard,
+    NextTab,
+    PreviousTab,
+    NewTab,
+    CloseTab,
+    PartyMode,
+}
+
+impl Action {
+    pub fn all() -> Vec<Action> {
+        let mut actions = vec![
+            Action::Quit,
+            Action::Suspend,
+            Action::Connect,
+            Action::Disconnect,
+            Action::Look,
+            Action::Examine,
+            Action::Attack,
+            Action::Help,
+            Action::MoveNorth,
+            Action::MoveSouth,
+            Action::MoveEast,
+            Action::MoveWest,
+            Action::Confirm,
+            Action::SelectPrevious,
+            Action::SelectNext,
+        ];
+
+        for slot in 1..=9 {
+            actions.push(Action::QuickTarget(slot));
+        }
+
+        actions.extend(vec![
+            Action::ReplayNext,
+            Action::ReplayPrevious,
+            Action::ReplayPlayPause,
+            Action::ReplayFaster,
+            Action::ReplaySlower,
+            Action::ReplayNextEvent,
+            Action::ReplayPreviousEvent,
+            Action::ReplayDeath,
+            Action::ReplayFirst,
+            Action::ReplayLast,
+            Action::NewCharacter,
+            Action::SaveReport,
+            Action::Leaderboard,
+            Action::NextTab,
+            Action::PreviousTab,
+            Action::NewTab,
+            Action::CloseTab,
+            Action::PartyMode,
+        ]);
+
+        actions
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Action::Quit => String::from("quit"),
+            Action::Suspend => String::from("suspend"),
+            Action::Connect => String::from("connect"),
+            Action::Disconnect => String::from("disconnect"),
+            Action::Look => String::from("look"),
+            Action::Examine => String::from("examine"),
+            Action::Attack => String::from("attack"),
+            Action::Help => String::from("help"),
+            Action::MoveNorth => String::from("move_north"),
+            Action::MoveSouth => String::from("move_south"),
+            Action::MoveEast => String::from("move_east"),
+            Action::MoveWest => String::from("move_west"),
+            Action::Confirm => String::from("confirm"),
+            Action::SelectPrevious => String::from("select_previous"),
+            Action::SelectNext => String::from("select_next"),
+            Action::QuickTarget(slot) => format!("quick_target_{}", slot),
+            Action::ReplayNext => String::from("replay_next"),
+            Action::ReplayPrevious => String::from("replay_previous"),
+            Action::ReplayPlayPause => String::from("replay_play_pause"),
+            Action::ReplayFaster => String::from("replay_faster"),
+            Action::ReplaySlower => String::from("replay_slower"),
+            Action::ReplayNextEvent => String::from("replay_next_event"),
+            Action::ReplayPreviousEvent => String::from("replay_previous_event"),
+            Action::ReplayDeath => String::from("replay_death"),
+            Action::ReplayFirst => String::from("replay_first"),
+            Action::ReplayLast => String::from("replay_last"),
+            Action::NewCharacter => String::from("new_character"),
+            Action::SaveReport => String::from("save_report"),
+            Action::Leaderboard => String::from("leaderboard"),
+            Action::NextTab => String::from("next_tab"),
+            Action::PreviousTab => String::from("previous_tab"),
+            Action::NewTab => String::from("new_tab"),
+            Action::CloseTab => String::from("close_tab"),
+            Action::PartyMode => String::from("party_mode"),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::all()
+            .into_iter()
+            .find(|action| action.name() == name)
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Action::Quit => String::from("quit"),
+            Action::Suspend => String::from("suspend to shell"),
+            Action::Connect => String::from("(re)connect"),
+            Action::Disconnect => String::from("disconnect"),
+            Action::Look => String::from("look around"),
+            Action::Examine => String::from("look entity"),
+            Action::Attack => String::from("attack"),
+            Action::Help => String::from("show keybinds"),
+            Action::MoveNorth => String::from("move north"),
+            Action::MoveSouth => String::from("move south"),
+            Action::MoveEast => String::from("move east"),
+            Action::MoveWest => String::from("move west"),
+            Action::Confirm => String::from("confirm / close popup"),
+            Action::SelectPrevious => String::from("previous entity / scroll up"),
+            Action::SelectNext => String::from("next entity / scroll down"),
+            Action::QuickTarget(slot) => format!("pick entity {}", slot),
+            Action::ReplayNext => String::from("next step"),
+            Action::ReplayPrevious => String::from("previous step"),
+            Action::ReplayPlayPause => String::from("play / pause"),
+            Action::ReplayFaster => String::from("faster"),
+            Action::ReplaySlower => String::from("slower"),
+            Action::ReplayNextEvent => String::from("next event"),
+            Action::ReplayPreviousEvent => String::from("previous event"),
+            Action::ReplayDeath => String::from("jump to death"),
+            Action::ReplayFirst => String::from("first step"),
+            Action::ReplayLast => String::from("last step"),
+            Action::NewCharacter => String::from("start a new character"),
+            Action::SaveReport => String::from("save run report"),
+            Action::Leaderboard => String::from("leaderboard / change order"),
+            Action::NextTab => String::from("next character"),
+            Action::PreviousTab => String::from("previous character"),
+            Action::NewTab => String::from("new character in a tab"),
+            Action::CloseTab => String::from("close character tab"),
+            Action::PartyMode => String::from("move and attack as a party"),
+        }
+    }
+
+    pub fn context(&self) -> Context {
+        match self {
+            Action::Quit | Action::Suspend | Action::Help | Action::Leaderboard => Context::Global,
+            Action::Confirm
+            | Action::SelectPrevious
+            | Action::SelectNext
+            | Action::QuickTarget(_) => Context::Popup,
+            Action::ReplayNext
+            | Action::ReplayPrevious
+            | Action::ReplayPlayPause
+            | Action::ReplayFaster
+            | Action::ReplaySlower
+            | Action::ReplayNextEvent
+            | Action::ReplayPreviousEvent
+            | Action::ReplayDeath
+            | Action::ReplayFirst
+            | Action::ReplayLast => Context::Replay,
+            Action::NewCharacter | Action::SaveReport => Context::Death,
+            Action::NextTab | Action::PreviousTab | Action::CloseTab => Context::Tabs,
+            _ => Context::Normal,
+        }
+    }
+
+    fn default_keys(&self) -> Vec<&'static str> {
+        match self {
+            Action::Quit => vec!["q", "Ctrl+c"],
+            Action::Suspend => vec!["Ctrl+z"],
+            Action::Connect => vec!["c"],
+            Action::Disconnect => vec!["d"],
+            Action::Look => vec!["l"],
+            Action::Examine => vec!["e"],
+            Action::Attack => vec!["a"],
+            Action::Help => vec!["h"],
+            Action::MoveNorth => vec!["Up"],
+            Action::MoveSouth => vec!["Down"],
+            Action::MoveEast => vec!["Right"],
+            Action::MoveWest => vec!["Left"],
+            Action::Confirm => vec!["Enter"],
+            Action::SelectPrevious => vec!["Up"],
+            Action::SelectNext => vec!["Down"],
+            Action::QuickTarget(slot) => QUICK_TARGET_KEYS
+                .iter()
+                .skip(*slot as usize - 1)
+                .take(1)
+                .copied()
+                .collect(),
+            Action::ReplayNext => vec!["Right"],
+            Action::ReplayPrevious => vec!["Left"],
+            Action::ReplayPlayPause => vec!["Space"],
+            Action::ReplayFaster => vec!["+", "Up"],
+            Action::ReplaySlower => vec!["-", "Down"],
+            Action::ReplayNextEvent => vec!["n"],
+            Action::ReplayPreviousEvent => vec!["p"],
+            Action::ReplayDeath => vec!["x"],
+            Action::ReplayFirst => vec!["Home"],
+            Action::ReplayLast => vec!["End"],
+            Action::NewCharacter => vec!["n"],
+            Action::SaveReport => vec!["s"],
+            Action::Leaderboard => vec!["b"],
+            Action::NextTab => vec!["Tab"],
+            Action::PreviousTab => vec!["BackTab"],
+            Action::NewTab => vec!["t"],
+            Action::CloseTab => vec!["w"],
+            Action::PartyMode => vec!["p"],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct KeyBinding {
+    pub code: event::KeyCode,
+    pub modifiers: event::KeyModifiers,
+}
+
+impl KeyBinding {
+    pub fn parse(data: &str) -> Result<KeyBinding, String> {
+        let error = || format!("{}: got \"{}\"", ERROR_KEY_PARSE, data);
+
+        let (prefix, key) = if data == "+" || data.ends_with("++") {
+            (&data[..data.len() - 1], "+")
+        } else {
+            match data.rfind('+') {
+                Some(i) => (&data[..i], &data[i + 1..]),
+                None => ("", data),
+            }
+        };
+        if key.is_empty() {
+            return Err(error());
+        }
+
+        let mut modifiers = event::KeyModifiers::empty();
+        for modifier in prefix.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.insert(event::KeyModifiers::CONTROL),
+                "alt" => modifiers.insert(event::KeyModifiers::ALT),
+                "shift" => modifiers.insert(event::KeyModifiers::SHIFT),
+                _ => return Err(error()),
+            }
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => event::KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "up" => event::KeyCode::Up,
+                "down" => event::KeyCode::Down,
+                "left" => event::KeyCode::Left,
+                "right" => event::KeyCode::Right,
+                "enter" | "return" => event::KeyCode::Enter,
+                "esc" | "escape" => event::KeyCode::Esc,
+                "tab" => event::KeyCode::Tab,
+                "backtab" => event::KeyCode::BackTab,
+                "backspace" => event::KeyCode::Backspace,
+                "delete" | "del" => event::KeyCode::Delete,
+                "insert" | "ins" => event::KeyCode::Insert,
+                "home" => event::KeyCode::Home,
+                "end" => event::KeyCode::End,
+                "pageup" => event::KeyCode::PageUp,
+                "pagedown" => event::KeyCode::PageDown,
+                "space" => event::KeyCode::Char(' '),
+                function if function.starts_with('f') => match function[1..].parse::<u8>() {
+                    Ok(n) if (1..=12).contains(&n) => event::KeyCode::F(n),
+                    _ => return Err(error()),
+                },
+                _ => return Err(error()),
+            },
+        };
+
+        Ok(KeyBinding::new(code, modifiers))
+    }
+
+    pub fn new(code: event::KeyCode, modifiers: event::KeyModifiers) -> KeyBinding {
+        // Terminals report shifted characters as the uppercase char, sometimes with SHIFT set
+        // and sometimes without, so SHIFT is folded into the character itself. BackTab is
+        // Shift+Tab already, and crossterm sends it with SHIFT set on unix.
+        match code {
+            event::KeyCode::Char(c) if modifiers.contains(event::KeyModifiers::SHIFT) => {
+                KeyBinding {
+                    code: event::KeyCode::Char(c.to_ascii_uppercase()),
+                    modifiers: modifiers - event::KeyModifiers::SHIFT,
+                }
+            }
+            event::KeyCode::BackTab => KeyBinding {
+                code,
+                modifiers: modifiers - event::KeyModifiers::SHIFT,
+            },
+            _ => KeyBinding { code, modifiers },
+        }
+    }
+
+    pub fn from_event(key: &event::KeyEvent) -> KeyBinding {
+        KeyBinding::new(key.code, key.modifiers)
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(event::KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(event::KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(event::KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+
+        match self.code {
+            event::KeyCode::Char(' ') => write!(f, "Space"),
+            event::KeyCode::Char(c) => write!(f, "{}", c),
+            event::KeyCode::F(n) => write!(f, "F{}", n),
+            event::KeyCode::Up => write!(f, "Up"),
+            event::KeyCode::Down => write!(f, "Down"),
+            event::KeyCode::Left => write!(f, "Left"),
+            event::KeyCode::Right => write!(f, "Right"),
+            event::KeyCode::Enter => write!(f, "Enter"),
+            event::KeyCode::Esc => write!(f, "Esc"),
+            event::KeyCode::Tab => write!(f, "Tab"),
+            event::KeyCode::BackTab => write!(f, "BackTab"),
+            event::KeyCode::Backspace => write!(f, "Backspace"),
+            event::KeyCode::Delete => write!(f, "Delete"),
+            event::KeyCode::Insert => write!(f, "Insert"),
+            event::KeyCode::Home => write!(f, "Home"),
+            event::KeyCode::End => write!(f, "End"),
+            event::KeyCode::PageUp => write!(f, "PageUp"),
+            event::KeyCode::PageDown => write!(f, "PageDown"),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: Vec<(Action, Vec<KeyBinding>)>,
+}
+
+impl Keymap {
+    pub fn from_config(overrides: &HashMap<String, Vec<String>>) -> Result<Keymap, String> {
+        for name in overrides.keys() {
+            if Action::from_name(name).is_none() {
+                return Err(format!("{}: got \"{}\"", ERROR_UNKNOWN_ACTION, name));
+            }
+        }
+
+        let mut bindings: Vec<(Action, Vec<KeyBinding>)> = Vec::new();
+
+        for action in Action::all() {
+            let keys = match overrides.get(&action.name()) {
+                Some(keys) => keys
+                    .iter()
+                    .map(|key| KeyBinding::parse(key.as_str()))
+                    .collect::<Result<Vec<KeyBinding>, String>>()?,
+                None => action
+                    .default_keys()
+                    .iter()
+                    .map(|key| KeyBinding::parse(key))
+                    .collect::<Result<Vec<KeyBinding>, String>>()?,
+            };
+            bindings.push((action, keys));
+        }
+
+        let keymap = Keymap { bindings };
+        let conflicts = keymap.conflicts();
+
+        if conflicts.is_empty() {
+            Ok(keymap)
+        } else {
+            Err(format!("{}: {}", ERROR_KEY_CONFLICT, conflicts.join(", ")))
+        }
+    }
+
+    fn conflicts(&self) -> Vec<String> {
+        let mut conflicts: Vec<String> = Vec::new();
+
+        for (i, (action, keys)) in self.bindings.iter().enumerate() {
+            for (other_action, other_keys) in self.bindings.iter().skip(i + 1) {
+                if !action.context().overlaps(&other_action.context()) {
+                    continue;
+                }
+                for key in keys.iter().filter(|key| other_keys.contains(key)) {
+                    conflicts.push(format!(
+                        "[{}] is bound to both {} and {}",
+                        key,
+                        action.name(),
+                        other_action.name()
+                    ));
+                }
+            }
+        }
+
+        conflicts
+    }
+
+    pub fn action_for(&self, key: &event::KeyEvent, context: Context) -> Option<Action> {
+        let binding = KeyBinding::from_event(key);
+
+        self.bindings
+            .iter()
+            .filter(|(action, _)| action.context().overlaps(&context))
+            .find(|(_, keys)| keys.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    /// One line per action, except quick targets which share a single line.
+    pub fn help_lines(&self, contexts: &[Context]) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+
+        for context in contexts.iter() {
+            if *context == Context::Popup {
+                lines.push(String::from("In popups:"));
+            }
+            for (action, keys) in self.bindings.iter() {
+                if action.context() != *context {
+                    continue;
+                }
+                let (keys_string, description) = match action {
+                    Action::QuickTarget(1) => {
+                        let slots = self
+                            .bindings
+                            .iter()
+                            .filter(|(action, _)| matches!(action, Action::QuickTarget(_)))
+                            .filter_map(|(_, keys)| keys.first())
+                            .map(|key| key.to_string())
+                            .collect::<Vec<String>>();
+                        if slots.is_empty() {
+                            continue;
+                        }
+                        (
+                            format!("[{}]", slots.join(" ")),
+                            String::from("pick entity by number"),
+                        )
+                    }
+                    Action::QuickTarget(_) => continue,
+                    _ if keys.is_empty() => continue,
+                    _ => (
+                        keys.iter()
+                            .map(|key| format!("[{}]", key))
+                            .collect::<Vec<String>>()
+                            .join(" "),
+                        action.description(),
+                    ),
+                };
+                lines.push(format!("{:<16} {}", keys_string, description));
+            }
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(pairs: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        pairs
+            .iter()
+            .map(|(action, keys)| {
+                (
+                    action.to_string(),
+                    keys.iter().map(|key| key.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn binding(code: event::KeyCode, modifiers: event::KeyModifiers) -> KeyBinding {
+        KeyBinding { code, modifiers }
+    }
+
+    #[test]
+    fn valid_bindings_parse() {
+        let none = event::KeyModifiers::NONE;
+        let ctrl = event::KeyModifiers::CONTROL;
+        let cases = vec![
+            ("q", binding(event::KeyCode::Char('q'), none)),
+            ("Ctrl+c", binding(event::KeyCode::Char('c'), ctrl)),
+            (
+                "control+alt+x",
+                binding(event::KeyCode::Char('x'), ctrl | event::KeyModifiers::ALT),
+            ),
+            ("Shift+a", binding(event::KeyCode::Char('A'), none)),
+            ("+", binding(event::KeyCode::Char('+'), none)),
+            ("Ctrl++", binding(event::KeyCode::Char('+'), ctrl)),
+            ("Space", binding(event::KeyCode::Char(' '), none)),
+            ("pagedown", binding(event::KeyCode::PageDown, none)),
+            ("Esc", binding(event::KeyCode::Esc, none)),
+            ("F12", binding(event::KeyCode::F(12), none)),
+            ("Shift+BackTab", binding(event::KeyCode::BackTab, none)),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(KeyBinding::parse(data), Ok(expected), "{}", data);
+        }
+    }
+
+    #[test]
+    fn invalid_bindings_are_rejected() {
+        for data in ["", "Ctrl+", "Hyper+x", "F0", "F13", "Fx", "Enterr", "ab"] {
+            let error = KeyBinding::parse(data).unwrap_err();
+            assert!(error.starts_with(ERROR_KEY_PARSE), "{}: {}", data, error);
+        }
+    }
+
+    #[test]
+    fn bindings_display_as_they_parse() {
+        for data in ["Ctrl+c", "Alt+Left", "Space", "F5", "BackTab"] {
+            assert_eq!(KeyBinding::parse(data).unwrap().to_string(), data);
+        }
+    }
+
+    #[test]
+    fn defaults_and_disjoint_contexts_do_not_conflict() {
+        assert!(Keymap::from_config(&HashMap::new()).is_ok());
+        // Replay and normal moves share the arrows without overlapping
+        let keymap = Keymap::from_config(&overrides(&[("replay_next", &["Right"])])).unwrap();
+        let right = event::KeyEvent::new(event::KeyCode::Right, event::KeyModifiers::NONE);
+        assert_eq!(
+            keymap.action_for(&right, Context::Normal),
+            Some(Action::MoveEast)
+        );
+        assert_eq!(
+            keymap.action_for(&right, Context::Replay),
+            Some(Action::ReplayNext)
+        );
+    }
+
+    #[test]
+    fn same_context_conflicts_are_rejected() {
+        let error = Keymap::from_config(&overrides(&[("look", &["c"])])).unwrap_err();
+        assert!(error.starts_with(ERROR_KEY_CONFLICT), "{}", error);
+        assert!(
+            error.contains("[c] is bound to both connect and look"),
+            "{}",
+            error
+        );
+
+        // Tab keys also work on the normal screen
+        let error = Keymap::from_config(&overrides(&[("new_tab", &["Tab"])])).unwrap_err();
+        assert!(error.contains("next_tab"), "{}", error);
+    }
+
+    #[test]
+    fn global_overlap_conflicts_are_rejected() {
+        // Global keys work everywhere, so they clash with popup and replay keys too
+        for (action, key, other) in [
+            ("help", "Enter", "confirm"),
+            ("quit", "Space", "replay_play_pause"),
+        ] {
+            let error = Keymap::from_config(&overrides(&[(action, &[key])])).unwrap_err();
+            assert!(error.contains(other), "{}", error);
+        }
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        let error = Keymap::from_config(&overrides(&[("fly", &["f"])])).unwrap_err();
+        assert!(error.starts_with(ERROR_UNKNOWN_ACTION), "{}", error);
+    }
+}
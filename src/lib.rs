@@ -0,0 +1,58 @@
+//! Client library for mungeon dungeon servers.
+//!
+//! [`model`], [`net`] and [`session`] form the stable API for bots and other
+//! headless consumers, with [`history`] for the statistics a session keeps.
+//! [`bot`] runs pluggable strategies, [`env`](mod@env) wraps a session as a
+//! Gym-style environment and [`sim`] is an in-process server to run either
+//! offline.
+//! None of these need a terminal.
+//!
+//! The terminal interface lives behind the default-on `tui` feature; build
+//! with `default-features = false` to leave out `crossterm` and `tui`. Rhai
+//! scripting hooks need the `scripting` feature, which implies `tui`.
+//!
+//! The other modules serve the binary, a thin wrapper around the hidden `cli`
+//! module, and may change at any time.
+
+pub mod bot;
+#[cfg(feature = "tui")]
+pub(crate) mod cassette;
+#[cfg(feature = "tui")]
+pub(crate) mod chaos;
+#[cfg(feature = "tui")]
+#[doc(hidden)]
+pub mod cli;
+#[cfg(feature = "tui")]
+pub(crate) mod config;
+#[cfg(feature = "tui")]
+pub(crate) mod conformance;
+#[cfg(feature = "tui")]
+pub(crate) mod control;
+pub mod env;
+pub mod history;
+pub(crate) mod httpd;
+#[cfg(feature = "tui")]
+pub(crate) mod keymap;
+#[cfg(feature = "tui")]
+pub(crate) mod load;
+pub(crate) mod logging;
+pub mod model;
+pub mod net;
+#[cfg(feature = "tui")]
+pub(crate) mod party;
+#[cfg(feature = "tui")]
+pub(crate) mod relay;
+#[cfg(feature = "tui")]
+pub(crate) mod replay;
+#[cfg(feature = "tui")]
+pub(crate) mod rpc;
+#[cfg(feature = "tui")]
+pub(crate) mod runner;
+#[cfg(feature = "tui")]
+pub(crate) mod screen;
+#[cfg(feature = "scripting")]
+pub(crate) mod script;
+pub mod session;
+pub mod sim;
+#[cfg(feature = "tui")]
+pub(crate) mod theme;
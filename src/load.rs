@@ -0,0 +1,568 @@
+use crate::model;
+use crate::net;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand::SeedableRng;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+static ERROR_LOAD_PLAYERS: &str = "Player count must be a number from 1 to 256";
+static ERROR_LOAD_DURATION: &str = "Could not parse duration in seconds";
+static ERROR_LOAD_ITERATIONS: &str = "Could not parse iteration count";
+static ERROR_LOAD_MIX: &str =
+    "Could not parse action mix, expected e.g. move:4,look:3,examine:2,attack:1";
+static ERROR_LOAD_RATE: &str = "Request rate must be a positive number of requests per second";
+static ERROR_REMOTE_TARGET: &str =
+    "Refusing to drive a server that is not on this machine, pass allow-remote and rate=";
+
+static DEFAULT_PLAYERS: usize = 10;
+// Each player is a thread of its own
+static MAX_PLAYERS: usize = 256;
+static DEFAULT_DURATION_SECS: u64 = 30;
+static DEFAULT_MIX: [(LoadAction, u32); 4] = [
+    (LoadAction::Move, 4),
+    (LoadAction::Look, 3),
+    (LoadAction::Examine, 2),
+    (LoadAction::Attack, 1),
+];
+
+static DIRECTIONS: [model::Direction; 4] = [
+    model::Direction::N,
+    model::Direction::E,
+    model::Direction::S,
+    model::Direction::W,
+];
+
+#[derive(Clone, Copy, Debug)]
+enum LoadAction {
+    Move,
+    Look,
+    Examine,
+    Attack,
+}
+
+impl LoadAction {
+    fn from_name(name: &str) -> Option<LoadAction> {
+        match name {
+            "move" => Some(LoadAction::Move),
+            "look" => Some(LoadAction::Look),
+            "examine" => Some(LoadAction::Examine),
+            "attack" => Some(LoadAction::Attack),
+            _ => None,
+        }
+    }
+}
+
+pub struct LoadOptions {
+    pub players: usize,
+    pub duration: Option<time::Duration>,
+    pub iterations: Option<u64>,
+    /// Requests per second across all players, unlimited if `None`.
+    pub rate: Option<f64>,
+    /// Whether the target may be another machine, see [`check_target`].
+    pub allow_remote: bool,
+    mix: Vec<(LoadAction, u32)>,
+}
+
+impl LoadOptions {
+    pub fn parse(
+        players: Option<&str>,
+        duration: Option<&str>,
+        iterations: Option<&str>,
+        mix: Option<&str>,
+    ) -> Result<LoadOptions, String> {
+        let players = match players {
+            Some(players) => match players.parse::<usize>() {
+                Ok(players) if (1..=MAX_PLAYERS).contains(&players) => players,
+                _ => return Err(format!("{}: got \"{}\"", ERROR_LOAD_PLAYERS, players)),
+            },
+            None => DEFAULT_PLAYERS,
+        };
+        let duration = match duration {
+            Some(duration) => {
+                Some(time::Duration::from_secs(duration.parse::<u64>().map_err(
+                    |_| format!("{}: got \"{}\"", ERROR_LOAD_DURATION, duration),
+                )?))
+            }
+            None => None,
+        };
+        let iterations = match iterations {
+            Some(iterations) => Some(
+                iterations
+                    .parse::<u64>()
+                    .map_err(|_| format!("{}: got \"{}\"", ERROR_LOAD_ITERATIONS, iterations))?,
+            ),
+            None => None,
+        };
+        let mix = match mix {
+            Some(mix) => LoadOptions::parse_mix(mix)
+                .ok_or_else(|| format!("{}: got \"{}\"", ERROR_LOAD_MIX, mix))?,
+            None => DEFAULT_MIX.to_vec(),
+        };
+
+        Ok(LoadOptions {
+            players,
+            duration: match (duration, iterations) {
+                (None, None) => Some(time::Duration::from_secs(DEFAULT_DURATION_SECS)),
+                _ => duration,
+            },
+            iterations,
+            rate: None,
+            allow_remote: false,
+            mix,
+        })
+    }
+
+    fn parse_mix(mix: &str) -> Option<Vec<(LoadAction, u32)>> {
+        let mut weights: Vec<(LoadAction, u32)> = Vec::new();
+        for part in mix.split(',') {
+            let (name, weight) = part.split_once(':')?;
+            weights.push((
+                LoadAction::from_name(name.trim())?,
+                weight.trim().parse::<u32>().ok()?,
+            ));
+        }
+
+        if weights.iter().any(|(_, weight)| *weight > 0) {
+            Some(weights)
+        } else {
+            None
+        }
+    }
+}
+
+/// Only servers on this machine can be driven flat out. Another machine needs
+/// both an explicit `allow_remote` and a request rate, so a typo in a URL is
+/// not enough to hammer someone's server.
+pub fn check_target(base_url: &str, allow_remote: bool, rate: Option<f64>) -> Result<(), String> {
+    if let Some(rate) = rate {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(format!("{}: got \"{}\"", ERROR_LOAD_RATE, rate));
+        }
+    }
+
+    match (net::is_loopback(base_url), allow_remote, rate) {
+        (true, _, _) | (false, true, Some(_)) => Ok(()),
+        _ => Err(format!("{}: got \"{}\"", ERROR_REMOTE_TARGET, base_url)),
+    }
+}
+
+/// Spaces the requests of `inner` at least `interval` apart.
+pub struct ThrottledTransport<T: net::Transport> {
+    inner: T,
+    interval: time::Duration,
+    next_slot: Mutex<Option<time::Instant>>,
+}
+
+impl<T: net::Transport> ThrottledTransport<T> {
+    /// Sends at most `rate` requests per second.
+    pub fn new(inner: T, rate: f64) -> ThrottledTransport<T> {
+        ThrottledTransport {
+            inner,
+            interval: time::Duration::from_secs_f64(1.0 / rate),
+            next_slot: Mutex::new(None),
+        }
+    }
+}
+
+impl<T: net::Transport> net::Transport for ThrottledTransport<T> {
+    fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+        // Each request books the next free slot, then waits for it without the lock
+        let now = time::Instant::now();
+        let slot = match self.next_slot.lock() {
+            Ok(mut next_slot) => {
+                let slot = next_slot.map_or(now, |next| next.max(now));
+                *next_slot = Some(slot + self.interval);
+                slot
+            }
+            Err(_) => now,
+        };
+        if slot > now {
+            thread::sleep(slot - now);
+        }
+
+        self.inner.send(request)
+    }
+}
+
+#[derive(Default)]
+struct LoadStats {
+    latencies: BTreeMap<&'static str, Vec<time::Duration>>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl LoadStats {
+    fn record<T>(
+        &mut self,
+        endpoint: &'static str,
+        started: time::Instant,
+        result: Result<T, model::Error>,
+    ) -> Option<T> {
+        self.latencies
+            .entry(endpoint)
+            .or_default()
+            .push(started.elapsed());
+
+        match result {
+            Ok(object) => Some(object),
+            Err(error) => {
+                let key = match (error.code, &error.detail.r#type) {
+                    (Some(code), Some(r#type)) => format!("{} {}", code, r#type),
+                    (Some(code), None) => code.to_string(),
+                    (None, _) => error
+                        .detail
+                        .message
+                        .split(':')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                *self.errors.entry(key).or_insert(0) += 1;
+                None
+            }
+        }
+    }
+
+    fn merge(&mut self, other: LoadStats) {
+        for (endpoint, latencies) in other.latencies {
+            self.latencies
+                .entry(endpoint)
+                .or_default()
+                .extend(latencies);
+        }
+        for (key, count) in other.errors {
+            *self.errors.entry(key).or_insert(0) += count;
+        }
+    }
+}
+
+struct LoadPlayer {
+    client: net::MunHttpClient,
+    guid: Option<String>,
+    room: Option<model::Room>,
+    rng: StdRng,
+    stats: LoadStats,
+}
+
+impl LoadPlayer {
+    fn connect(&mut self) {
+        let started = time::Instant::now();
+        let result = self.client.connect();
+        if let Some(status) = self.stats.record("connect", started, result) {
+            self.guid = Some(status.guid);
+            self.room = Some(status.room);
+        }
+    }
+
+    fn random_entity(&mut self, guid: &str, include_self: bool) -> Option<String> {
+        let entities: Vec<&String> = self
+            .room
+            .iter()
+            .flat_map(|room| room.entities.iter())
+            .filter(|entity| include_self || *entity != guid)
+            .collect();
+
+        entities
+            .choose(&mut self.rng)
+            .map(|entity| entity.to_string())
+    }
+
+    fn step(&mut self, action: LoadAction) {
+        let guid = match self.guid.clone() {
+            Some(guid) => guid,
+            None => return self.connect(),
+        };
+        let started = time::Instant::now();
+
+        let died = match action {
+            LoadAction::Move => {
+                let direction = match &self.room {
+                    Some(room) if !room.paths.is_empty() => {
+                        room.paths.choose(&mut self.rng).cloned()
+                    }
+                    _ => DIRECTIONS.choose(&mut self.rng).cloned(),
+                }
+                .unwrap_or(model::Direction::N);
+                let result = self.client.r#move(guid, direction);
+                let died = is_dead(&result);
+                if let Some(room) = self.stats.record("deplacement", started, result) {
+                    self.room = Some(room);
+                }
+                died
+            }
+            LoadAction::Look => {
+                let result = self.client.look_room(guid);
+                let died = is_dead(&result);
+                if let Some(room) = self.stats.record("regarder", started, result) {
+                    self.room = Some(room);
+                }
+                died
+            }
+            LoadAction::Examine => {
+                let target = self
+                    .random_entity(&guid, true)
+                    .unwrap_or_else(|| guid.clone());
+                let result = self.client.look_entity(guid, target);
+                let died = is_dead(&result);
+                self.stats.record("examiner", started, result);
+                died
+            }
+            LoadAction::Attack => match self.random_entity(&guid, false) {
+                Some(target) => {
+                    let result = self.client.attack(guid, target);
+                    let died = is_dead(&result);
+                    self.stats.record("taper", started, result);
+                    died
+                }
+                None => return self.step(LoadAction::Look),
+            },
+        };
+
+        if died {
+            self.guid = None;
+            self.room = None;
+        }
+    }
+}
+
+fn is_dead<T>(result: &Result<T, model::Error>) -> bool {
+    matches!(
+        result,
+        Err(model::Error {
+            detail: model::ErrorDetail {
+                r#type: Some(model::ErrorType::Dead),
+                ..
+            },
+            ..
+        })
+    )
+}
+
+fn pick_action(rng: &mut StdRng, mix: &[(LoadAction, u32)]) -> LoadAction {
+    let total: u32 = mix.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rng.gen_range(0..total);
+    for (action, weight) in mix.iter() {
+        if roll < *weight {
+            return *action;
+        }
+        roll -= weight;
+    }
+
+    mix[0].0
+}
+
+pub struct LoadReport {
+    players: usize,
+    elapsed: time::Duration,
+    stats: LoadStats,
+}
+
+fn percentile(sorted: &[time::Duration], percent: usize) -> f64 {
+    let index = (sorted.len() * percent / 100).min(sorted.len() - 1);
+    sorted[index].as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: usize = self
+            .stats
+            .latencies
+            .values()
+            .map(|latencies| latencies.len())
+            .sum();
+        let elapsed_secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+
+        writeln!(
+            f,
+            "{} players, {} requests in {:.1}s ({:.1} req/s)\n",
+            self.players,
+            total,
+            elapsed_secs,
+            total as f64 / elapsed_secs
+        )?;
+        writeln!(
+            f,
+            "{:<12} {:>8} {:>9} {:>9} {:>9} {:>9}",
+            "endpoint", "count", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        )?;
+        for (endpoint, latencies) in self.stats.latencies.iter() {
+            let mut sorted = latencies.clone();
+            sorted.sort();
+            writeln!(
+                f,
+                "{:<12} {:>8} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+                endpoint,
+                sorted.len(),
+                percentile(&sorted, 50),
+                percentile(&sorted, 90),
+                percentile(&sorted, 99),
+                percentile(&sorted, 100)
+            )?;
+        }
+
+        if self.stats.errors.is_empty() {
+            write!(f, "\nno errors")
+        } else {
+            write!(f, "\nerrors")?;
+            for (key, count) in self.stats.errors.iter() {
+                write!(f, "\n  {:<34} {}", key, count)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// When player `index` connects, and how far apart its steps are after that: the
+// connects share the rate like the steps do, so they never come in a burst
+fn pacing(
+    rate: Option<f64>,
+    players: usize,
+    index: usize,
+) -> (time::Duration, Option<time::Duration>) {
+    match rate {
+        Some(rate) => (
+            time::Duration::from_secs_f64(index as f64 / rate),
+            Some(time::Duration::from_secs_f64(players as f64 / rate)),
+        ),
+        None => (time::Duration::ZERO, None),
+    }
+}
+
+/// Runs the players against `base_url`, after [`check_target`] allowed it.
+pub fn run(base_url: String, options: LoadOptions) -> Result<LoadReport, String> {
+    check_target(base_url.as_str(), options.allow_remote, options.rate)?;
+
+    let started = time::Instant::now();
+    let deadline = options.duration.map(|duration| started + duration);
+    log::info!(
+        target: "load",
+        "start players={} duration={:?} iterations={:?} rate={:?}",
+        options.players,
+        options.duration,
+        options.iterations,
+        options.rate
+    );
+
+    let handles: Vec<thread::JoinHandle<LoadStats>> = (0..options.players)
+        .map(|index| {
+            let client = net::MunHttpClient::new(base_url.clone());
+            let mix = options.mix.clone();
+            let iterations = options.iterations;
+            let (connect_delay, interval) = pacing(options.rate, options.players, index);
+
+            thread::spawn(move || {
+                let mut player = LoadPlayer {
+                    client,
+                    guid: None,
+                    room: None,
+                    rng: StdRng::from_entropy(),
+                    stats: LoadStats::default(),
+                };
+                let mut next_step = started + connect_delay;
+                let now = time::Instant::now();
+                if next_step > now {
+                    thread::sleep(next_step - now);
+                }
+                player.connect();
+
+                let mut iteration = 0;
+                while iterations.map_or(true, |iterations| iteration < iterations)
+                    && deadline.map_or(true, |deadline| time::Instant::now() < deadline)
+                {
+                    if let Some(interval) = interval {
+                        next_step += interval;
+                        let now = time::Instant::now();
+                        if next_step > now {
+                            thread::sleep(next_step - now);
+                        }
+                    }
+                    let action = pick_action(&mut player.rng, &mix);
+                    player.step(action);
+                    iteration += 1;
+                }
+
+                player.stats
+            })
+        })
+        .collect();
+
+    let mut stats = LoadStats::default();
+    for handle in handles {
+        if let Ok(player_stats) = handle.join() {
+            stats.merge(player_stats);
+        }
+    }
+
+    let report = LoadReport {
+        players: options.players,
+        elapsed: started.elapsed(),
+        stats,
+    };
+    log::info!(target: "load", "done elapsed_ms={}", report.elapsed.as_millis());
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_targets_need_no_opt_in() {
+        for base_url in [
+            "http://localhost:8000",
+            "http://127.0.0.1:8000/",
+            "http://127.3.2.1",
+            "http://[::1]:8000",
+        ] {
+            assert!(check_target(base_url, false, None).is_ok(), "{}", base_url);
+        }
+    }
+
+    #[test]
+    fn remote_targets_need_opt_in_and_rate() {
+        for base_url in [
+            "http://example.com",
+            "http://10.0.0.1:8000",
+            "http://localhost.example.com",
+            "offline:",
+        ] {
+            assert!(check_target(base_url, false, None).is_err(), "{}", base_url);
+            assert!(check_target(base_url, true, None).is_err(), "{}", base_url);
+            assert!(
+                check_target(base_url, false, Some(5.0)).is_err(),
+                "{}",
+                base_url
+            );
+        }
+        assert!(check_target("http://example.com", true, Some(5.0)).is_ok());
+        assert!(check_target("http://example.com", true, Some(0.0)).is_err());
+        assert!(check_target("http://localhost", false, Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn player_count_is_bounded() {
+        assert!(LoadOptions::parse(Some("256"), None, None, None).is_ok());
+        for players in ["0", "257", "100000", "many"] {
+            assert!(
+                LoadOptions::parse(Some(players), None, None, None).is_err(),
+                "{}",
+                players
+            );
+        }
+    }
+
+    #[test]
+    fn connects_are_paced_like_steps() {
+        let ms = time::Duration::from_millis;
+        assert_eq!(pacing(Some(10.0), 4, 0), (ms(0), Some(ms(400))));
+        assert_eq!(pacing(Some(10.0), 4, 3), (ms(300), Some(ms(400))));
+        assert_eq!(pacing(None, 4, 3), (ms(0), None));
+    }
+}
@@ -0,0 +1,299 @@
+#[cfg(feature = "tui")]
+use crate::config;
+
+#[cfg(feature = "tui")]
+use std::collections::VecDeque;
+#[cfg(feature = "tui")]
+use std::env;
+#[cfg(feature = "tui")]
+use std::fs;
+#[cfg(feature = "tui")]
+use std::io;
+#[cfg(feature = "tui")]
+use std::io::Write;
+#[cfg(feature = "tui")]
+use std::path;
+use std::sync::atomic;
+#[cfg(feature = "tui")]
+use std::sync::Mutex;
+use std::time;
+
+#[cfg(feature = "tui")]
+static LOG_DIR: &str = "mungeon";
+#[cfg(feature = "tui")]
+static LOG_FILE: &str = "client.log";
+
+#[cfg(feature = "tui")]
+static ERROR_LOG_LEVEL: &str = "Could not parse log level";
+#[cfg(feature = "tui")]
+static ERROR_LOG_FILE: &str = "Could not open log file";
+#[cfg(feature = "tui")]
+static ERROR_NO_LOG_PATH: &str = "No log file could be determined, set log-file=<path>";
+
+static ACTION_SEGMENTS: [&str; 5] = ["connect", "regarder", "deplacement", "examiner", "taper"];
+
+static REDACT_GUIDS: atomic::AtomicBool = atomic::AtomicBool::new(true);
+
+#[cfg(feature = "tui")]
+static RECENT_LINES: usize = 50;
+#[cfg(feature = "tui")]
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+#[cfg(feature = "tui")]
+pub struct LogOptions {
+    pub level: log::LevelFilter,
+    pub path: path::PathBuf,
+    pub max_size: u64,
+    pub max_files: u32,
+    pub redact_guids: bool,
+}
+
+#[cfg(feature = "tui")]
+impl LogOptions {
+    pub fn from_config(
+        log_config: &config::LogConfig,
+        level: Option<&str>,
+        file: Option<&str>,
+    ) -> Result<LogOptions, String> {
+        let level_name = level.unwrap_or(log_config.level.as_str());
+        let level = level_name
+            .parse::<log::LevelFilter>()
+            .map_err(|_| format!("{}: got \"{}\"", ERROR_LOG_LEVEL, level_name))?;
+
+        let path = match file.or(log_config.file.as_deref()) {
+            Some(file) => path::PathBuf::from(file),
+            None => LogOptions::default_path().ok_or_else(|| ERROR_NO_LOG_PATH.to_string())?,
+        };
+
+        Ok(LogOptions {
+            level,
+            path,
+            max_size: log_config.max_size,
+            max_files: log_config.max_files,
+            redact_guids: log_config.redact_guids,
+        })
+    }
+
+    pub fn default_path() -> Option<path::PathBuf> {
+        let state_home = match env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+            _ => path::PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("state"),
+        };
+
+        Some(state_home.join(LOG_DIR).join(LOG_FILE))
+    }
+}
+
+#[cfg(feature = "tui")]
+struct LogFile {
+    path: path::PathBuf,
+    file: fs::File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+#[cfg(feature = "tui")]
+impl LogFile {
+    fn open(path: &path::Path, max_size: u64, max_files: u32) -> Result<LogFile, io::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> path::PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        path::PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> Result<(), io::Error> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), io::Error> {
+        if self.max_size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tui")]
+pub struct FileLogger {
+    level: log::LevelFilter,
+    file: Mutex<LogFile>,
+}
+
+#[cfg(feature = "tui")]
+impl FileLogger {
+    pub fn init(options: LogOptions) -> Result<(), String> {
+        REDACT_GUIDS.store(options.redact_guids, atomic::Ordering::SeqCst);
+
+        if options.level == log::LevelFilter::Off {
+            return Ok(());
+        }
+
+        let file = LogFile::open(&options.path, options.max_size, options.max_files)
+            .map_err(|e| format!("{} {}: {}", ERROR_LOG_FILE, options.path.display(), e))?;
+        let logger = FileLogger {
+            level: options.level,
+            file: Mutex::new(file),
+        };
+
+        log::set_boxed_logger(Box::new(logger)).map_err(|e| e.to_string())?;
+        log::set_max_level(options.level);
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tui")]
+impl log::Log for FileLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{} {:<5} {} {}\n",
+            timestamp(time::SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.write_line(line.as_str());
+        }
+        if let Ok(mut recent) = RECENT.lock() {
+            if recent.len() >= RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line.trim_end().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+/// The last `count` lines written to the log, oldest first.
+pub fn recent(count: usize) -> Vec<String> {
+    match RECENT.lock() {
+        Ok(recent) => recent
+            .iter()
+            .skip(recent.len().saturating_sub(count))
+            .cloned()
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn guid(guid: &str) -> String {
+    if !REDACT_GUIDS.load(atomic::Ordering::SeqCst) {
+        return guid.to_string();
+    }
+
+    // FNV-1a, so the same guid always maps to the same token within and across logs
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in guid.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+
+    format!("<guid:{:08x}>", hash)
+}
+
+pub fn url(base_url: &str, url: &str) -> String {
+    match url.strip_prefix(base_url) {
+        Some(path) => {
+            let redacted_path = path
+                .split('/')
+                .map(|segment| {
+                    if segment.is_empty() || ACTION_SEGMENTS.contains(&segment) {
+                        segment.to_string()
+                    } else {
+                        guid(segment)
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("/");
+            format!("{}{}", base_url, redacted_path)
+        }
+        None => url.to_string(),
+    }
+}
+
+pub fn timestamp(now: time::SystemTime) -> String {
+    let since_epoch = now
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_else(|_| time::Duration::from_secs(0));
+    let seconds = since_epoch.as_secs();
+    let (days, day_seconds) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_seconds / 3_600,
+        (day_seconds % 3_600) / 60,
+        day_seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
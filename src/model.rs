@@ -1,13 +1,20 @@
+//! Payloads exchanged with a mungeon server, with their French wire names.
+
 use serde::{Deserialize, Serialize};
 
-static ERROR_DESERIALIZATION: &'static str = "Error while deserializing object";
+use std::fmt;
+
+static ERROR_DESERIALIZATION: &str = "Error while deserializing object";
 
+/// Parsing of server payloads into model types.
 pub trait MunModel {
+    /// Parses `data` as JSON, reporting failures as an [`Error`] without a status code.
     fn from_str(data: &str) -> Result<Self, Error>
     where
         Self: Sized;
 }
 
+/// A cardinal direction, as used for room passages and moves.
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub enum Direction {
     #[serde(rename = "N")]
@@ -21,6 +28,7 @@ pub enum Direction {
 }
 
 impl Direction {
+    /// Body of a `deplacement` request towards this direction.
     pub fn to_movement_json(&self) -> String {
         format!(
             "{{ \"direction\": \"{}\" }}",
@@ -40,14 +48,14 @@ impl MunModel for Direction {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Kind of an examined entity (`MONSTRE` or `JOUEUR`).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub enum EntityType {
     #[serde(rename = "MONSTRE")]
     Monster,
@@ -61,23 +69,23 @@ impl MunModel for EntityType {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
-impl EntityType {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for EntityType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            EntityType::Monster => String::from("Monster"),
-            EntityType::Player => String::from("Player"),
+            EntityType::Monster => write!(f, "Monster"),
+            EntityType::Player => write!(f, "Player"),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Game error carried by a 409 response (`MORT`, `MUR` or `DIFFSALLE`).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub enum ErrorType {
     #[serde(rename = "MORT")]
     Dead,
@@ -93,23 +101,23 @@ impl MunModel for ErrorType {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
-impl ErrorType {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ErrorType::Dead => String::from("Death"),
-            ErrorType::Wall => String::from("Wall"),
-            ErrorType::DiffRoom => String::from("Different room"),
+            ErrorType::Dead => write!(f, "Death"),
+            ErrorType::Wall => write!(f, "Wall"),
+            ErrorType::DiffRoom => write!(f, "Different room"),
         }
     }
 }
 
+/// A room: its description, open passages and the guids of the entities in it.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Room {
     pub description: String,
@@ -125,13 +133,16 @@ impl MunModel for Room {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
+/// Player state returned by `connect`.
+///
+/// `life` is not sent by the server; it starts at `total_life` and is kept up to
+/// date by [`Session`](crate::session::Session).
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Status {
     pub guid: String,
@@ -153,13 +164,13 @@ impl MunModel for Status {
             }),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
+/// An examined entity.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Entity {
     pub description: String,
@@ -176,13 +187,13 @@ impl MunModel for Entity {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
+/// One side of a fight: damage dealt and life left afterwards.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Fighter {
     pub guid: String,
@@ -198,13 +209,13 @@ impl MunModel for Fighter {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
+/// Result of an attack.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Fight {
     #[serde(rename = "attaquant")]
@@ -219,13 +230,13 @@ impl MunModel for Fight {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
+/// Body of a 409 response.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ErrorDetail {
     pub r#type: Option<ErrorType>,
@@ -238,20 +249,21 @@ impl MunModel for ErrorDetail {
             Ok(object) => Ok(object),
             Err(_) => Err(Error::from_error_string(format!(
                 "{}: got \"{}\"",
-                ERROR_DESERIALIZATION.to_string(),
-                data.to_string()
+                ERROR_DESERIALIZATION, data
             ))),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// Any failure of a request: `code` is the HTTP status when the server answered.
+#[derive(Serialize, Clone, Debug)]
 pub struct Error {
     pub code: Option<u16>,
     pub detail: ErrorDetail,
 }
 
 impl Error {
+    /// An error that did not come from an HTTP status.
     pub fn from_error_string(error_string: String) -> Error {
         Error {
             code: None,
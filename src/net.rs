@@ -1,42 +1,127 @@
+//! HTTP access to a mungeon server.
+
+use crate::logging;
 use crate::model;
 use crate::model::MunModel;
 
-static ERROR_NETWORK: &'static str = "A network error has occured";
-static ERROR_400: &'static str = "Bad request";
-static ERROR_404: &'static str = "URL not found";
-static ERROR_SERDE: &'static str = "Error while parsing JSON response";
+static ERROR_NETWORK: &str = "A network error has occured";
+static ERROR_400: &str = "Bad request";
+static ERROR_404: &str = "URL not found";
+static ERROR_5XX: &str = "Server error";
+static ERROR_SERDE: &str = "Error while parsing JSON response";
 
+use std::fmt;
+use std::sync::Arc;
 use std::time;
 
+/// A request to the server: a GET of a URL, or a POST of a URL with a JSON body.
 #[derive(Clone, Debug)]
 pub enum MunRequest {
     Get(String),
     Post(String, String),
 }
 
+impl MunRequest {
+    pub fn method(&self) -> &'static str {
+        match self {
+            MunRequest::Get(_) => "GET",
+            MunRequest::Post(_, _) => "POST",
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            MunRequest::Get(url) => url.as_str(),
+            MunRequest::Post(url, _) => url.as_str(),
+        }
+    }
+
+    /// Only these are retried: a POST may already have moved or attacked server-side.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self, MunRequest::Get(_))
+    }
+
+    pub fn body(&self) -> &str {
+        match self {
+            MunRequest::Get(_) => "",
+            MunRequest::Post(_, body) => body.as_str(),
+        }
+    }
+}
+
+/// A server answer before it is interpreted.
 #[derive(Clone, Debug)]
-pub struct MunHttpClient {
-    pub base_url: String,
+pub struct RawResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Whether `base_url` points at this machine: `localhost` or a loopback address.
+pub fn is_loopback(base_url: &str) -> bool {
+    let host = match reqwest::Url::parse(base_url) {
+        Ok(url) => match url.host_str() {
+            Some(host) => host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            None => return false,
+        },
+        Err(_) => return false,
+    };
+
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The part of `url` after `base_url`, or all of it for another server.
+pub(crate) fn relative_path(base_url: &str, url: &str) -> String {
+    match url.strip_prefix(base_url) {
+        Some(path) => path.to_string(),
+        None => url.to_string(),
+    }
+}
+
+/// Sends requests for a [`MunHttpClient`].
+///
+/// Implemented by [`HttpTransport`] and by wrappers that record, replay or
+/// disturb traffic. Clients of several tabs send through one transport at the same
+/// time, so implementations only lock their own state, never around the network.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: &MunRequest) -> Result<RawResponse, String>;
+}
+
+impl Transport for Box<dyn Transport> {
+    fn send(&self, request: &MunRequest) -> Result<RawResponse, String> {
+        (**self).send(request)
+    }
+}
+
+/// Blocking HTTP transport with a 5 second timeout.
+pub struct HttpTransport {
     http_client: reqwest::blocking::Client,
-    tried_once: bool,
 }
 
-impl MunHttpClient {
-    pub fn new(base_url: String) -> MunHttpClient {
-        MunHttpClient {
-            base_url: base_url,
-            tried_once: false,
+impl HttpTransport {
+    pub fn new() -> HttpTransport {
+        HttpTransport {
             http_client: reqwest::blocking::ClientBuilder::new()
                 .timeout(time::Duration::from_secs(5))
                 .build()
                 .unwrap(),
         }
     }
+}
 
-    fn send_request<T>(&mut self, request: MunRequest) -> Result<T, model::Error>
-    where
-        T: model::MunModel,
-    {
+impl Default for HttpTransport {
+    fn default() -> HttpTransport {
+        HttpTransport::new()
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, request: &MunRequest) -> Result<RawResponse, String> {
         let result = match request.clone() {
             MunRequest::Get(url) => self.http_client.get(url).send(),
             MunRequest::Post(url, body) => self
@@ -49,8 +134,74 @@ impl MunHttpClient {
 
         match result {
             Ok(response) => {
+                let status = response.status().as_u16();
+                match response.text() {
+                    Ok(body) => Ok(RawResponse { status, body }),
+                    Err(error) => Err(format!("{}: {}", ERROR_SERDE, error)),
+                }
+            }
+            Err(error) => Err(error.to_string()),
+        }
+    }
+}
+
+/// Typed client for the mungeon endpoints.
+///
+/// Transport failures and 5xx answers to GET requests are retried once; 400, 404 and 409
+/// answers are returned as [`model::Error`]. Clones share one transport.
+#[derive(Clone)]
+pub struct MunHttpClient {
+    pub base_url: String,
+    transport: Arc<dyn Transport>,
+    tried_once: bool,
+}
+
+impl fmt::Debug for MunHttpClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MunHttpClient")
+            .field("base_url", &self.base_url)
+            .field("tried_once", &self.tried_once)
+            .finish()
+    }
+}
+
+impl MunHttpClient {
+    pub fn new(base_url: String) -> MunHttpClient {
+        MunHttpClient::with_transport(base_url, Box::new(HttpTransport::new()))
+    }
+
+    pub fn with_transport(base_url: String, transport: Box<dyn Transport>) -> MunHttpClient {
+        MunHttpClient {
+            base_url,
+            tried_once: false,
+            transport: Arc::from(transport),
+        }
+    }
+
+    fn send_request<T>(&mut self, request: MunRequest) -> Result<T, model::Error>
+    where
+        T: model::MunModel,
+    {
+        let started = time::Instant::now();
+        let result = self.transport.send(&request);
+
+        match result {
+            Ok(response) => {
+                log::info!(
+                    target: "net",
+                    "method={} url={} status={} latency_ms={} retries={}",
+                    request.method(),
+                    logging::url(self.base_url.as_str(), request.url()),
+                    response.status,
+                    started.elapsed().as_millis(),
+                    self.tried_once as u8
+                );
+                if response.status >= 500 && !self.tried_once && request.is_idempotent() {
+                    self.tried_once = true;
+                    return self.send_request(request);
+                }
                 self.tried_once = false;
-                match response.status().as_u16() {
+                match response.status {
                     400 => Err(model::Error {
                         code: Some(400),
                         detail: model::ErrorDetail {
@@ -65,21 +216,31 @@ impl MunHttpClient {
                             message: ERROR_404.to_string(),
                         },
                     }),
-                    409 => Err(match response.text() {
-                        Ok(text) => model::Error {
-                            code: Some(409),
-                            detail: model::ErrorDetail::from_str(text.as_str())?,
+                    409 => Err(model::Error {
+                        code: Some(409),
+                        detail: model::ErrorDetail::from_str(response.body.as_str())?,
+                    }),
+                    500..=599 => Err(model::Error {
+                        code: Some(response.status),
+                        detail: model::ErrorDetail {
+                            r#type: None,
+                            message: ERROR_5XX.to_string(),
                         },
-                        Err(_) => Err(model::Error::from_error_string(ERROR_SERDE.to_string()))?,
                     }),
-                    _ => match response.text() {
-                        Ok(text) => T::from_str(text.as_str()),
-                        Err(_) => Err(model::Error::from_error_string(ERROR_SERDE.to_string())),
-                    },
+                    _ => T::from_str(response.body.as_str()),
                 }
             }
-            Err(_) => {
-                if self.tried_once {
+            Err(error) => {
+                log::warn!(
+                    target: "net",
+                    "method={} url={} status=none latency_ms={} retries={} error=\"{}\"",
+                    request.method(),
+                    logging::url(self.base_url.as_str(), request.url()),
+                    started.elapsed().as_millis(),
+                    self.tried_once as u8,
+                    error
+                );
+                if self.tried_once || !request.is_idempotent() {
                     self.tried_once = false;
                     Err(model::Error {
                         code: None,
@@ -96,16 +257,19 @@ impl MunHttpClient {
         }
     }
 
+    /// `POST /connect`: creates a player.
     pub fn connect(&mut self) -> Result<model::Status, model::Error> {
         let request = MunRequest::Post(format!("{}/connect", self.base_url), String::new());
         self.send_request::<model::Status>(request)
     }
 
+    /// `GET /<guid>/regarder`: the player's current room.
     pub fn look_room(&mut self, guid: String) -> Result<model::Room, model::Error> {
         let request = MunRequest::Get(format!("{}/{}/regarder", self.base_url, guid));
         self.send_request::<model::Room>(request)
     }
 
+    /// `POST /<guid>/deplacement`: moves the player and returns the new room.
     pub fn r#move(
         &mut self,
         guid: String,
@@ -118,6 +282,7 @@ impl MunHttpClient {
         self.send_request::<model::Room>(request)
     }
 
+    /// `GET /<guid>/examiner/<guid_dest>`: examines an entity in the same room.
     pub fn look_entity(
         &mut self,
         guid: String,
@@ -127,6 +292,7 @@ impl MunHttpClient {
         self.send_request::<model::Entity>(request)
     }
 
+    /// `POST /<guid>/taper/<guid_dest>`: attacks an entity in the same room.
     pub fn attack(
         &mut self,
         guid: String,
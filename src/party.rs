@@ -0,0 +1,388 @@
+//! Drives several characters as a group: the leader moves, followers retrace its path
+//! to its room, and everyone attacks the same target.
+
+use crate::logging;
+use crate::model;
+use crate::session;
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+/// What happened to one member during a party action.
+#[derive(Clone, Debug)]
+pub struct MemberReport {
+    pub guid: String,
+    pub outcome: Result<String, String>,
+}
+
+static MAX_TRAIL: usize = 64;
+
+/// Remembers where the leader went, so followers can retrace its path.
+///
+/// Rooms are told apart by position, as descriptions can repeat. Every member
+/// counts positions from where it connected, so a follower's offset to the
+/// leader's positions is learned whenever they share a room.
+#[derive(Clone, Debug, Default)]
+pub struct Party {
+    leader: String,
+    // Leader's position in the room it left and the direction it took, oldest first
+    trail: VecDeque<(session::Position, model::Direction)>,
+    // What to add to a follower's position to get the leader's, by follower guid
+    offsets: HashMap<String, session::Position>,
+}
+
+fn guid(member: &session::Session) -> String {
+    member.get_guid().unwrap_or_default()
+}
+
+// Whether the leader sees `follower` in its room, the leader's view being the freshest
+fn together(leader: &session::Session, follower: &session::Session) -> bool {
+    match (&leader.status, &follower.status) {
+        (Some(leader), Some(follower)) => leader.room.entities.contains(&follower.guid),
+        _ => false,
+    }
+}
+
+fn describe(error: &model::Error) -> String {
+    match &error.detail.r#type {
+        Some(error_type) => format!("{}: {}", error_type, error.detail.message),
+        None => error.detail.message.clone(),
+    }
+}
+
+// Errors are reported by the party, except deaths which the member must still see
+fn take_error(member: &mut session::Session) -> Option<model::Error> {
+    let error = member.error.clone()?;
+    if !matches!(error.detail.r#type, Some(model::ErrorType::Dead)) {
+        member.error = None;
+    }
+
+    Some(error)
+}
+
+impl Party {
+    pub fn new() -> Party {
+        Party::default()
+    }
+
+    /// Moves the leader, then brings every follower to the leader's room.
+    pub fn r#move(
+        &mut self,
+        leader: &mut session::Session,
+        followers: &mut [&mut session::Session],
+        direction: model::Direction,
+    ) -> Vec<MemberReport> {
+        let leader_guid = guid(leader);
+        if self.leader != leader_guid {
+            self.leader = leader_guid.clone();
+            self.trail.clear();
+            self.offsets.clear();
+        }
+        let strangers = followers
+            .iter()
+            .any(|follower| follower.is_connected() && !self.offsets.contains_key(&guid(follower)));
+        if strangers {
+            leader.look_room();
+        }
+        for follower in followers.iter() {
+            self.meet(leader, follower);
+        }
+        let left = leader.is_connected().then_some(leader.position);
+
+        leader.r#move(direction.clone());
+        leader.update();
+        if let Some(error) = take_error(leader) {
+            return vec![MemberReport {
+                guid: leader_guid,
+                outcome: Err(describe(&error)),
+            }];
+        }
+        if let Some(left) = left {
+            if self.trail.len() >= MAX_TRAIL {
+                self.trail.pop_front();
+            }
+            self.trail.push_back((left, direction.clone()));
+        }
+        let mut reports = vec![MemberReport {
+            guid: leader_guid,
+            outcome: Ok(format!("moved {:?}", direction)),
+        }];
+
+        for follower in followers.iter_mut() {
+            if !follower.is_connected() {
+                continue;
+            }
+            reports.push(self.regroup(leader, follower));
+        }
+        log::info!(
+            target: "party",
+            "move direction={:?} members={} trail={}",
+            direction,
+            reports.len(),
+            self.trail.len()
+        );
+
+        reports
+    }
+
+    fn meet(&mut self, leader: &session::Session, follower: &session::Session) -> bool {
+        if !together(leader, follower) {
+            return false;
+        }
+        let offset = (
+            leader.position.0 - follower.position.0,
+            leader.position.1 - follower.position.1,
+        );
+        self.offsets.insert(guid(follower), offset);
+
+        true
+    }
+
+    // Retraces the leader's path from the last time it left the follower's room
+    fn regroup(
+        &mut self,
+        leader: &session::Session,
+        follower: &mut session::Session,
+    ) -> MemberReport {
+        let follower_guid = guid(follower);
+        if self.meet(leader, follower) {
+            return MemberReport {
+                guid: follower_guid,
+                outcome: Ok(String::from("with the leader")),
+            };
+        }
+
+        let on_trail = self.offsets.get(&follower_guid).and_then(|offset| {
+            let position = (
+                follower.position.0 + offset.0,
+                follower.position.1 + offset.1,
+            );
+            self.trail.iter().rposition(|(left, _)| *left == position)
+        });
+        let start = match on_trail {
+            Some(start) => start,
+            None => {
+                return MemberReport {
+                    guid: follower_guid,
+                    outcome: Err(String::from("separated, not on the leader's trail")),
+                }
+            }
+        };
+        let path: Vec<model::Direction> = self
+            .trail
+            .iter()
+            .skip(start)
+            .map(|(_, direction)| direction.clone())
+            .collect();
+
+        for (i, direction) in path.iter().enumerate() {
+            follower.r#move(direction.clone());
+            follower.update();
+            // The trail is kept, the next move retries from wherever the follower stopped
+            if let Some(error) = take_error(follower) {
+                return MemberReport {
+                    guid: follower_guid,
+                    outcome: Err(format!(
+                        "{}, {} moves behind",
+                        describe(&error),
+                        path.len() - i
+                    )),
+                };
+            }
+        }
+
+        MemberReport {
+            guid: follower_guid,
+            outcome: Ok(if path.len() == 1 {
+                String::from("followed")
+            } else {
+                format!("caught up {} moves", path.len())
+            }),
+        }
+    }
+
+    /// Every connected member attacks `target`.
+    pub fn attack(
+        &mut self,
+        members: &mut [&mut session::Session],
+        target: &str,
+    ) -> Vec<MemberReport> {
+        let mut reports: Vec<MemberReport> = Vec::new();
+
+        for member in members.iter_mut().filter(|member| member.is_connected()) {
+            let member_guid = guid(member);
+            if member_guid == target {
+                continue;
+            }
+            member.attack(target.to_string());
+            let fight = member.fight_info.take();
+            member.update();
+            let outcome = match (take_error(member), fight) {
+                (Some(error), _) => Err(describe(&error)),
+                (None, Some(fight)) => Ok(format!(
+                    "dealt {}, took {}, target at {}",
+                    fight.attacker.damage, fight.defender.damage, fight.defender.life
+                )),
+                (None, None) => Ok(String::from("attacked")),
+            };
+            reports.push(MemberReport {
+                guid: member_guid,
+                outcome,
+            });
+        }
+        log::info!(
+            target: "party",
+            "attack target={} members={}",
+            logging::guid(target),
+            reports.len()
+        );
+
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net;
+    use crate::sim;
+
+    static BASE_URL: &str = "http://offline";
+    // The two middle rooms look the same
+    static CORRIDOR: &str = r#"
+        spawn = [0, 0]
+
+        [[rooms]]
+        x = 0
+        y = 0
+        description = "West end"
+        exits = ["E"]
+
+        [[rooms]]
+        x = 1
+        y = 0
+        description = "Corridor"
+        exits = ["E", "W"]
+
+        [[rooms]]
+        x = 2
+        y = 0
+        description = "Corridor"
+        exits = ["E", "W"]
+
+        [[rooms]]
+        x = 3
+        y = 0
+        description = "East end"
+        exits = ["W"]
+    "#;
+
+    fn members() -> (session::Session, session::Session) {
+        let dungeon: sim::DungeonFile = toml::from_str(CORRIDOR).unwrap();
+        let world = sim::World::from_dungeon(dungeon, 1).unwrap();
+        let mut leader = session::Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(sim::SimTransport::new(BASE_URL.to_string(), world)),
+        ));
+        let mut follower = session::Session::with_client(leader.client.clone());
+        leader.connect();
+        follower.connect();
+
+        (leader, follower)
+    }
+
+    fn outcomes(reports: Vec<MemberReport>) -> Vec<Result<String, String>> {
+        reports.into_iter().map(|report| report.outcome).collect()
+    }
+
+    fn room_of(member: &session::Session) -> String {
+        member
+            .status
+            .as_ref()
+            .map(|status| status.room.description.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn separated_follower_retraces_the_trail() {
+        let (mut leader, mut follower) = members();
+        let mut party = Party::new();
+
+        let reports = party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+        assert_eq!(
+            outcomes(reports),
+            vec![Ok(String::from("moved E")), Ok(String::from("followed"))]
+        );
+        assert_eq!(room_of(&follower), "Corridor");
+
+        follower.r#move(model::Direction::W);
+        let reports = party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+        assert_eq!(
+            outcomes(reports),
+            vec![
+                Ok(String::from("moved E")),
+                Ok(String::from("caught up 2 moves"))
+            ]
+        );
+        assert_eq!(follower.position, (2, 0));
+        assert_eq!(follower.position, leader.position);
+    }
+
+    #[test]
+    fn rooms_sharing_a_description_are_told_apart() {
+        let (mut leader, mut follower) = members();
+        let mut party = Party::new();
+        party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+
+        // Left behind in the first corridor room while the leader enters the second
+        leader.r#move(model::Direction::E);
+        leader.update();
+        let reports = party.r#move(&mut leader, &mut [&mut follower], model::Direction::W);
+        assert_eq!(
+            outcomes(reports),
+            vec![
+                Ok(String::from("moved W")),
+                Ok(String::from("with the leader"))
+            ]
+        );
+
+        let reports = party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+        assert_eq!(
+            outcomes(reports),
+            vec![Ok(String::from("moved E")), Ok(String::from("followed"))]
+        );
+        assert_eq!(room_of(&follower), room_of(&leader));
+        assert_eq!(follower.position, (2, 0));
+    }
+
+    #[test]
+    fn follower_off_the_trail_is_reported() {
+        let (mut leader, mut follower) = members();
+        let mut party = Party::new();
+        party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+
+        follower.r#move(model::Direction::E);
+        follower.r#move(model::Direction::E);
+        let reports = party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+        assert_eq!(
+            outcomes(reports),
+            vec![
+                Ok(String::from("moved E")),
+                Err(String::from("separated, not on the leader's trail"))
+            ]
+        );
+
+        // Coming back to a room of the trail is enough to follow again
+        follower.r#move(model::Direction::W);
+        follower.r#move(model::Direction::W);
+        let reports = party.r#move(&mut leader, &mut [&mut follower], model::Direction::E);
+        assert_eq!(
+            outcomes(reports),
+            vec![
+                Ok(String::from("moved E")),
+                Ok(String::from("caught up 2 moves"))
+            ]
+        );
+        assert_eq!(room_of(&follower), "East end");
+    }
+}
@@ -0,0 +1,275 @@
+//! Local relay through which teammates' clients share where their characters are.
+//!
+//! One client hosts it with `relay-host=<port>` and the others `relay-join=<address>` with
+//! the `relay-token=` the host printed.
+//! Clients publish with `POST /members` and read everyone back with `GET /members`;
+//! members that stop publishing are dropped after [`STALE_AFTER`].
+//!
+//! Both routes need the relay's token as a bearer token. A guid is all it takes to play
+//! a character, so members are published under [`member_id`] instead of their guid.
+
+use crate::httpd;
+use crate::model;
+use crate::session;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time;
+
+pub static STALE_AFTER: time::Duration = time::Duration::from_secs(10);
+
+static ERROR_RELAY_REQUEST: &str = "Relay request failed";
+static ERROR_RELAY_ANSWER: &str = "Relay answered with an error";
+
+/// What a client publishes about one of its characters.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Member {
+    /// [`member_id`] of the character's guid.
+    pub id: String,
+    pub room: String,
+    pub exits: Vec<model::Direction>,
+    pub life: u32,
+    pub total_life: u32,
+    /// Ids of the other entities in the room.
+    pub sightings: Vec<String>,
+}
+
+/// Keyed hash of a guid: teammates holding `token` can match it against the guids
+/// in their room, anyone else learns nothing usable.
+pub fn member_id(token: &str, guid: &str) -> String {
+    hmac_sha256::HMAC::mac(guid.as_bytes(), token.as_bytes())
+        .iter()
+        .take(16)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// An IP literal or localhost on the relay's port, a DNS rebinding page has neither
+fn host_allowed(host: &str, port: u16) -> bool {
+    match host.rsplit_once(':') {
+        Some((name, host_port)) => {
+            let name = name.trim_start_matches('[').trim_end_matches(']');
+            host_port.parse() == Ok(port)
+                && (name == "localhost" || name.parse::<net::IpAddr>().is_ok())
+        }
+        None => false,
+    }
+}
+
+impl Member {
+    pub fn from_session(session: &session::Session, token: &str) -> Option<Member> {
+        let status = session.status.as_ref()?;
+
+        Some(Member {
+            id: member_id(token, status.guid.as_str()),
+            room: status.room.description.clone(),
+            exits: status.room.paths.clone(),
+            life: status.life.unwrap_or(0),
+            total_life: status.total_life,
+            sightings: status
+                .room
+                .entities
+                .iter()
+                .filter(|guid| **guid != status.guid)
+                .map(|guid| member_id(token, guid))
+                .collect(),
+        })
+    }
+}
+
+/// Serves the relay on `listener` from a background thread, to clients holding `token`.
+pub fn host(listener: net::TcpListener, token: String) -> Result<(), io::Error> {
+    let port = listener.local_addr()?.port();
+    let members: Arc<Mutex<HashMap<String, (Member, time::Instant)>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+
+    httpd::serve(listener, move |request| {
+        if let Err(response) =
+            httpd::authorize(&request, |host| host_allowed(host, port), token.as_str())
+        {
+            return response;
+        }
+
+        let mut members = match members.lock() {
+            Ok(members) => members,
+            Err(_) => return httpd::Response::error(500, "relay unavailable"),
+        };
+        members.retain(|_, (_, published_at)| published_at.elapsed() < STALE_AFTER);
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/members") => {
+                let fresh: Vec<&Member> = members.values().map(|(member, _)| member).collect();
+                httpd::Response::json(200, serde_json::json!(fresh).to_string())
+            }
+            ("POST", "/members") => match serde_json::from_str::<Member>(request.body.as_str()) {
+                Ok(member) => {
+                    log::debug!(target: "relay", "publish room=\"{}\"", member.room);
+                    members.insert(member.id.clone(), (member, time::Instant::now()));
+                    httpd::Response::json(202, String::from("{\"published\":true}"))
+                }
+                Err(error) => httpd::Response::error(400, error.to_string().as_str()),
+            },
+            (_, "/members") => httpd::Response::error(405, "method not allowed"),
+            _ => httpd::Response::error(404, "not found"),
+        }
+    });
+
+    Ok(())
+}
+
+/// Blocking client for a relay at `host:port`.
+#[derive(Clone)]
+pub struct RelayClient {
+    pub address: String,
+    token: String,
+    http_client: reqwest::blocking::Client,
+}
+
+impl RelayClient {
+    pub fn new(address: &str, token: String) -> RelayClient {
+        RelayClient {
+            address: address.to_string(),
+            token,
+            http_client: reqwest::blocking::ClientBuilder::new()
+                .timeout(time::Duration::from_millis(500))
+                .build()
+                .unwrap(),
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}/members", self.address)
+    }
+
+    /// [`member_id`] of `guid` on this relay.
+    pub fn id(&self, guid: &str) -> String {
+        member_id(self.token.as_str(), guid)
+    }
+
+    pub fn member(&self, session: &session::Session) -> Option<Member> {
+        Member::from_session(session, self.token.as_str())
+    }
+
+    pub fn publish(&self, member: &Member) -> Result<(), String> {
+        let response = self
+            .http_client
+            .post(self.url())
+            .bearer_auth(&self.token)
+            .json(member)
+            .send()
+            .map_err(|e| format!("{}: {}", ERROR_RELAY_REQUEST, e))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{}: got \"{}\"",
+                ERROR_RELAY_ANSWER,
+                response.status()
+            ))
+        }
+    }
+
+    pub fn members(&self) -> Result<Vec<Member>, String> {
+        let response = self
+            .http_client
+            .get(self.url())
+            .bearer_auth(&self.token)
+            .send()
+            .map_err(|e| format!("{}: {}", ERROR_RELAY_REQUEST, e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "{}: got \"{}\"",
+                ERROR_RELAY_ANSWER,
+                response.status()
+            ));
+        }
+
+        response
+            .json::<Vec<Member>>()
+            .map_err(|e| format!("{}: {}", ERROR_RELAY_REQUEST, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::io::Write;
+
+    fn member(id: &str) -> Member {
+        Member {
+            id: id.to_string(),
+            room: String::from("Hall"),
+            exits: vec![model::Direction::N],
+            life: 10,
+            total_life: 20,
+            sightings: Vec::new(),
+        }
+    }
+
+    fn hosted(token: &str) -> String {
+        let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        host(listener, token.to_string()).unwrap();
+
+        address
+    }
+
+    #[test]
+    fn only_ip_literals_and_localhost_are_allowed_hosts() {
+        assert!(host_allowed("127.0.0.1:9000", 9000));
+        assert!(host_allowed("192.168.1.20:9000", 9000));
+        assert!(host_allowed("[::1]:9000", 9000));
+        assert!(host_allowed("localhost:9000", 9000));
+        assert!(!host_allowed("evil.example:9000", 9000));
+        assert!(!host_allowed("127.0.0.1:9001", 9000));
+        assert!(!host_allowed("127.0.0.1", 9000));
+    }
+
+    #[test]
+    fn ids_depend_on_the_token() {
+        let guid = "2f1f6a4e-8e0b-4c2a-9d5e-1b2c3d4e5f60";
+        assert_eq!(member_id("a", guid), member_id("a", guid));
+        assert_ne!(member_id("a", guid), member_id("b", guid));
+        assert!(!member_id("a", guid).contains(guid));
+    }
+
+    #[test]
+    fn members_need_the_token() {
+        let address = hosted("secret");
+
+        let client = RelayClient::new(address.as_str(), String::from("secret"));
+        client.publish(&member("one")).unwrap();
+        let members = client.members().unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].id, "one");
+
+        let stranger = RelayClient::new(address.as_str(), String::from("guess"));
+        assert!(stranger.members().is_err());
+        assert!(stranger.publish(&member("two")).is_err());
+        assert_eq!(client.members().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn foreign_hosts_are_rejected() {
+        let address = hosted("secret");
+
+        let mut stream = net::TcpStream::connect(address.as_str()).unwrap();
+        write!(
+            stream,
+            "GET /members HTTP/1.1\r\nHost: evil.example:80\r\nAuthorization: Bearer secret\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    }
+}
@@ -0,0 +1,477 @@
+//! Step-by-step viewer for cassettes recorded with `record=`.
+//!
+//! Only cassettes can be replayed: session logs leave out the response bodies and
+//! may redact guids, so they are not enough to rebuild the session.
+
+use crate::cassette;
+use crate::model;
+use crate::model::MunModel;
+use crate::net;
+use crate::session;
+
+use std::time;
+
+static ACTION_UNKNOWN: &str = "unknown request";
+static LABEL_LOOK_ROOM: &str = "look around";
+static LABEL_LOOK_SELF: &str = "look self";
+static LABEL_UPDATE: &str = "update";
+
+static SPEEDS: [f64; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+static DEFAULT_SPEED_INDEX: usize = 2;
+static MAX_FRAME_GAP_MILLIS: u64 = 2000;
+static MIN_FRAME_GAP_MILLIS: u64 = 100;
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub session: session::Session,
+    pub label: String,
+    pub timestamp_ms: u64,
+}
+
+impl Frame {
+    pub fn is_death(&self) -> bool {
+        matches!(
+            &self.session.error,
+            Some(model::Error {
+                detail: model::ErrorDetail {
+                    r#type: Some(model::ErrorType::Dead),
+                    ..
+                },
+                ..
+            })
+        )
+    }
+
+    pub fn is_event(&self) -> bool {
+        self.session.error.is_some()
+            || self.session.fight_info.is_some()
+            || self.session.entity_info.is_some()
+    }
+}
+
+enum Step {
+    Connect,
+    LookRoom,
+    Move(model::Direction),
+    LookSelf,
+    LookEntity(String),
+    Attack(String),
+}
+
+impl Step {
+    fn parse(entry: &cassette::CassetteEntry) -> Option<Step> {
+        let segments: Vec<&str> = entry
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        match segments.as_slice() {
+            ["connect"] => Some(Step::Connect),
+            [_, "regarder"] => Some(Step::LookRoom),
+            [_, "deplacement"] => {
+                let movement: serde_json::Value = serde_json::from_str(entry.body.as_str()).ok()?;
+                let direction =
+                    model::Direction::from_str(&movement["direction"].to_string()).ok()?;
+                Some(Step::Move(direction))
+            }
+            [guid, "examiner", guid_dest] if guid == guid_dest => Some(Step::LookSelf),
+            [_, "examiner", guid_dest] => Some(Step::LookEntity(guid_dest.to_string())),
+            [_, "taper", guid_dest] => Some(Step::Attack(guid_dest.to_string())),
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Step::Connect => String::from("connect"),
+            Step::LookRoom => String::from(LABEL_LOOK_ROOM),
+            Step::Move(direction) => format!("move {:?}", direction),
+            Step::LookSelf => String::from(LABEL_LOOK_SELF),
+            Step::LookEntity(guid) => format!("look entity {}", guid),
+            Step::Attack(guid) => format!("attack {}", guid),
+        }
+    }
+
+    fn apply(&self, session: &mut session::Session) {
+        match self {
+            Step::Connect => session.connect(),
+            Step::LookRoom => session.look_room(),
+            Step::Move(direction) => session.r#move(direction.clone()),
+            Step::LookSelf => session.look_self(),
+            Step::LookEntity(guid) => session.look_entity(guid.clone()),
+            Step::Attack(guid) => session.attack(guid.clone()),
+        }
+    }
+}
+
+pub fn build_frames(entries: Vec<cassette::CassetteEntry>) -> Vec<Frame> {
+    let base_url = String::from("replay:");
+    let transport = cassette::ReplayTransport::new(base_url.clone(), entries.clone());
+    let pending = transport.pending();
+    let mut session = session::Session::with_client(net::MunHttpClient::with_transport(
+        base_url,
+        Box::new(transport),
+    ));
+
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut index = 0;
+
+    while index < entries.len() {
+        let entry = &entries[index];
+        let before = pending.lock().map(|pending| pending.len()).unwrap_or(0);
+
+        session.clear_infos();
+        let label = match Step::parse(entry) {
+            Some(step) => {
+                step.apply(&mut session);
+                step.label()
+            }
+            None => format!("{} {} {}", ACTION_UNKNOWN, entry.method, entry.path),
+        };
+
+        // A step that did not reach the transport (e.g. no status yet) still consumes its entry
+        let after = pending.lock().map(|pending| pending.len()).unwrap_or(0);
+        if after == before {
+            if let Ok(mut pending) = pending.lock() {
+                pending.pop_front();
+            }
+        }
+        index += before.saturating_sub(after).max(1);
+
+        let mut frame = Frame {
+            session: session.clone(),
+            label,
+            timestamp_ms: entry.timestamp_ms,
+        };
+        if frame.is_death() {
+            frame.label = format!("{} (death)", frame.label);
+            session.disconnect();
+        }
+
+        // The auto-update issues a look around followed by a look self, shown as a single step
+        match frames.last_mut() {
+            Some(last) if frame.label == LABEL_LOOK_SELF && last.label == LABEL_LOOK_ROOM => {
+                if !last.is_event() {
+                    frame.label = String::from(LABEL_UPDATE);
+                    *last = frame;
+                } else {
+                    frames.push(frame);
+                }
+            }
+            _ => frames.push(frame),
+        }
+    }
+
+    frames
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayState {
+    pub frames: Vec<Frame>,
+    pub index: usize,
+    pub playing: bool,
+    speed_index: usize,
+    last_step: time::Instant,
+}
+
+impl ReplayState {
+    pub fn new(frames: Vec<Frame>) -> ReplayState {
+        ReplayState {
+            frames,
+            index: 0,
+            playing: false,
+            speed_index: DEFAULT_SPEED_INDEX,
+            last_step: time::Instant::now(),
+        }
+    }
+
+    pub fn current(&self) -> Option<&Frame> {
+        self.frames.get(self.index)
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed_index]
+    }
+
+    pub fn faster(&mut self) {
+        self.speed_index = (self.speed_index + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed_index = self.speed_index.saturating_sub(1);
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.playing = !self.playing;
+        self.last_step = time::Instant::now();
+    }
+
+    pub fn go_to(&mut self, index: usize) {
+        if !self.frames.is_empty() {
+            self.index = index.min(self.frames.len() - 1);
+        }
+        self.last_step = time::Instant::now();
+    }
+
+    pub fn next(&mut self) {
+        self.go_to(self.index + 1);
+    }
+
+    pub fn previous(&mut self) {
+        self.go_to(self.index.saturating_sub(1));
+    }
+
+    pub fn first(&mut self) {
+        self.go_to(0);
+    }
+
+    pub fn last(&mut self) {
+        self.go_to(self.frames.len().saturating_sub(1));
+    }
+
+    pub fn next_event(&mut self) {
+        if let Some(offset) = self
+            .frames
+            .iter()
+            .skip(self.index + 1)
+            .position(|frame| frame.is_event())
+        {
+            self.go_to(self.index + 1 + offset);
+        }
+    }
+
+    pub fn previous_event(&mut self) {
+        if let Some(index) = self
+            .frames
+            .iter()
+            .take(self.index)
+            .rposition(|frame| frame.is_event())
+        {
+            self.go_to(index);
+        }
+    }
+
+    pub fn death(&mut self) {
+        if let Some(index) = self.frames.iter().position(|frame| frame.is_death()) {
+            self.go_to(index);
+        }
+    }
+
+    // Recorded gaps are clamped so idle minutes do not stall playback
+    fn frame_gap(&self) -> time::Duration {
+        let gap_millis = match (self.frames.get(self.index), self.frames.get(self.index + 1)) {
+            (Some(current), Some(next)) => next
+                .timestamp_ms
+                .saturating_sub(current.timestamp_ms)
+                .clamp(MIN_FRAME_GAP_MILLIS, MAX_FRAME_GAP_MILLIS),
+            _ => MAX_FRAME_GAP_MILLIS,
+        };
+
+        time::Duration::from_millis((gap_millis as f64 / self.speed()) as u64)
+    }
+
+    pub fn tick(&mut self) -> bool {
+        if !self.playing || self.last_step.elapsed() < self.frame_gap() {
+            return false;
+        }
+        if self.index + 1 >= self.frames.len() {
+            self.playing = false;
+            return false;
+        }
+        self.next();
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static HALL: &str = r#"{"description":"Hall","passages":["E"],"entites":["me","rat"]}"#;
+
+    fn entry(
+        method: &str,
+        path: &str,
+        status: u16,
+        response: &str,
+        at: u64,
+    ) -> cassette::CassetteEntry {
+        cassette::CassetteEntry {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: String::new(),
+            status: Some(status),
+            response: Some(response.to_string()),
+            error: None,
+            elapsed_ms: 1,
+            timestamp_ms: at,
+        }
+    }
+
+    // Connects, looks around, fights a rat, walks east and dies there
+    fn cassette() -> Vec<cassette::CassetteEntry> {
+        let mut movement = entry(
+            "POST",
+            "/me/deplacement",
+            200,
+            r#"{"description":"Cave","passages":["W"],"entites":["me","rat"]}"#,
+            9000,
+        );
+        movement.body = model::Direction::E.to_movement_json();
+
+        vec![
+            entry(
+                "POST",
+                "/connect",
+                200,
+                &format!(r#"{{"guid":"me","totalvie":20,"salle":{}}}"#, HALL),
+                1000,
+            ),
+            entry("GET", "/me/regarder", 200, HALL, 2000),
+            entry(
+                "GET",
+                "/me/examiner/me",
+                200,
+                r#"{"description":"You","type":"JOUEUR","vie":20,"totalvie":20}"#,
+                2010,
+            ),
+            entry(
+                "GET",
+                "/me/examiner/rat",
+                200,
+                r#"{"description":"A rat","type":"MONSTRE","vie":10,"totalvie":10}"#,
+                3000,
+            ),
+            entry(
+                "POST",
+                "/me/taper/rat",
+                200,
+                r#"{"attaquant":{"guid":"me","degats":3,"vie":12},"attaque":{"guid":"rat","degats":8,"vie":7}}"#,
+                4000,
+            ),
+            movement,
+            entry(
+                "POST",
+                "/me/taper/rat",
+                409,
+                r#"{"type":"MORT","message":"You are dead"}"#,
+                9050,
+            ),
+        ]
+    }
+
+    fn labels(frames: &[Frame]) -> Vec<&str> {
+        frames.iter().map(|frame| frame.label.as_str()).collect()
+    }
+
+    #[test]
+    fn frames_follow_the_cassette() {
+        let frames = build_frames(cassette());
+        assert_eq!(
+            labels(&frames),
+            vec![
+                "connect",
+                "update",
+                "look entity rat",
+                "attack rat",
+                "move E",
+                "attack rat (death)"
+            ]
+        );
+        assert_eq!(frames[1].timestamp_ms, 2010);
+        assert_eq!(
+            frames[2].session.entity_info.as_ref().unwrap().description,
+            "A rat"
+        );
+        assert_eq!(
+            frames[3].session.fight_info.as_ref().unwrap().defender.life,
+            7
+        );
+        let room = &frames[4].session.status.as_ref().unwrap().room;
+        assert_eq!(room.description, "Cave");
+        assert!(frames[5].is_death());
+    }
+
+    #[test]
+    fn navigation_stays_within_the_frames() {
+        let mut replay = ReplayState::new(build_frames(cassette()));
+        replay.previous();
+        assert_eq!(replay.index, 0);
+        replay.next();
+        replay.next();
+        assert_eq!(replay.current().unwrap().label, "look entity rat");
+        replay.previous();
+        assert_eq!(replay.index, 1);
+        replay.last();
+        replay.next();
+        assert_eq!(replay.index, 5);
+        replay.first();
+        assert_eq!(replay.index, 0);
+        replay.go_to(100);
+        assert_eq!(replay.index, 5);
+    }
+
+    #[test]
+    fn events_and_death_can_be_jumped_to() {
+        let mut replay = ReplayState::new(build_frames(cassette()));
+        replay.next_event();
+        assert_eq!(replay.current().unwrap().label, "look entity rat");
+        replay.next_event();
+        assert_eq!(replay.current().unwrap().label, "attack rat");
+        replay.previous_event();
+        assert_eq!(replay.index, 2);
+
+        replay.death();
+        assert_eq!(replay.index, 5);
+        assert!(replay.current().unwrap().is_death());
+        // Nothing after the death, so the position is kept
+        replay.next_event();
+        assert_eq!(replay.index, 5);
+    }
+
+    #[test]
+    fn speed_scales_the_recorded_gaps() {
+        let mut replay = ReplayState::new(build_frames(cassette()));
+        replay.go_to(2);
+        assert_eq!(replay.speed(), 1.0);
+        assert_eq!(replay.frame_gap(), time::Duration::from_millis(1000));
+
+        replay.faster();
+        assert_eq!(replay.frame_gap(), time::Duration::from_millis(500));
+        for _ in 0..10 {
+            replay.faster();
+        }
+        assert_eq!(replay.speed(), 16.0);
+        for _ in 0..10 {
+            replay.slower();
+        }
+        assert_eq!(replay.speed(), 0.25);
+
+        // Gaps are clamped, whatever was recorded
+        replay.faster();
+        replay.faster();
+        replay.go_to(3);
+        assert_eq!(replay.frame_gap(), time::Duration::from_millis(2000));
+        replay.go_to(4);
+        assert_eq!(replay.frame_gap(), time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn playing_stops_on_the_last_frame() {
+        let mut replay = ReplayState::new(build_frames(cassette()));
+        assert!(!replay.tick());
+        replay.last();
+        replay.toggle_playing();
+        replay.last_step -= time::Duration::from_secs(60);
+        assert!(!replay.tick());
+        assert!(!replay.playing);
+
+        replay.previous();
+        replay.toggle_playing();
+        replay.last_step -= time::Duration::from_secs(60);
+        assert!(replay.tick());
+        assert_eq!(replay.index, 5);
+    }
+}
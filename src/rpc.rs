@@ -0,0 +1,479 @@
+use crate::model;
+use crate::session;
+
+use serde::Serialize;
+use serde_json::json;
+
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+static METHODS: [&str; 7] = [
+    "connect",
+    "disconnect",
+    "look",
+    "move",
+    "examine",
+    "attack",
+    "status",
+];
+
+static PARSE_ERROR: i64 = -32700;
+static INVALID_REQUEST: i64 = -32600;
+static METHOD_NOT_FOUND: i64 = -32601;
+static INVALID_PARAMS: i64 = -32602;
+static SESSION_ERROR: i64 = -32000;
+
+enum RpcEvent {
+    Request(String),
+    AutoUpdate,
+    Closed,
+}
+
+#[derive(Default, PartialEq)]
+struct Observed {
+    // Rooms entered so far, as rooms can share a description
+    room: Option<u64>,
+    life: Option<u32>,
+}
+
+impl Observed {
+    fn from_session(session: &session::Session) -> Observed {
+        match &session.status {
+            Some(status) => Observed {
+                room: Some(session.rooms_entered),
+                life: status.life,
+            },
+            None => Observed::default(),
+        }
+    }
+}
+
+pub struct RpcServer {
+    session: session::Session,
+    observed: Observed,
+    // Written after the response to the request that caused them
+    notifications: Vec<serde_json::Value>,
+}
+
+fn write_message<T: Serialize>(message: &T) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    match serde_json::to_string(message) {
+        Ok(line) => {
+            let _ = writeln!(stdout, "{}", line);
+            let _ = stdout.flush();
+        }
+        Err(error) => log::warn!(target: "rpc", "encode_failed error=\"{}\"", error),
+    }
+}
+
+fn error_response(id: serde_json::Value, code: i64, message: &str) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn is_dead(error: &Option<model::Error>) -> bool {
+    matches!(
+        error,
+        Some(model::Error {
+            detail: model::ErrorDetail {
+                r#type: Some(model::ErrorType::Dead),
+                ..
+            },
+            ..
+        })
+    )
+}
+
+impl RpcServer {
+    pub fn new(session: session::Session) -> RpcServer {
+        RpcServer {
+            session,
+            observed: Observed::default(),
+            notifications: Vec::new(),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: serde_json::Value) {
+        log::debug!(target: "rpc", "notify method={}", method);
+        self.notifications.push(json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }));
+    }
+
+    fn check_death(&mut self) {
+        if is_dead(&self.session.error) {
+            self.notify("died", json!(self.session.error));
+            self.session.disconnect();
+        }
+    }
+
+    fn check_changes(&mut self) {
+        let observed = Observed::from_session(&self.session);
+        if observed == self.observed {
+            return;
+        }
+
+        if let Some(status) = self.session.status.clone() {
+            if observed.room != self.observed.room {
+                self.notify("room_changed", json!(status.room));
+            }
+            if observed.life != self.observed.life {
+                self.notify(
+                    "life_changed",
+                    json!({ "life": status.life, "totalvie": status.total_life }),
+                );
+            }
+        }
+        self.observed = observed;
+    }
+
+    fn observe(&mut self) -> Vec<serde_json::Value> {
+        self.check_death();
+        self.check_changes();
+
+        self.notifications.drain(..).collect()
+    }
+
+    fn result(&self, method: &str) -> serde_json::Value {
+        match method {
+            "connect" => json!(self.session.status),
+            "look" | "move" => json!(self.session.status.as_ref().map(|status| &status.room)),
+            "examine" => json!(self.session.entity_info),
+            "attack" => json!(self.session.fight_info),
+            _ => json!(self.session.snapshot()),
+        }
+    }
+
+    fn handle_request(&mut self, line: &str) -> Option<serde_json::Value> {
+        let request: serde_json::Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(error) => {
+                return Some(error_response(
+                    serde_json::Value::Null,
+                    PARSE_ERROR,
+                    error.to_string().as_str(),
+                ))
+            }
+        };
+
+        match request {
+            // A batch is answered with the responses of its calls, in order
+            serde_json::Value::Array(requests) if requests.is_empty() => Some(error_response(
+                serde_json::Value::Null,
+                INVALID_REQUEST,
+                "empty batch",
+            )),
+            serde_json::Value::Array(requests) => {
+                let responses: Vec<serde_json::Value> = requests
+                    .iter()
+                    .filter_map(|request| self.handle_call(request))
+                    .collect();
+                if responses.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::Value::Array(responses))
+                }
+            }
+            request => self.handle_call(&request),
+        }
+    }
+
+    fn handle_call(&mut self, request: &serde_json::Value) -> Option<serde_json::Value> {
+        let id = request.get("id").cloned();
+        let response_id = id.clone().unwrap_or(serde_json::Value::Null);
+
+        let method = match (request.get("jsonrpc"), request.get("method")) {
+            (Some(version), Some(serde_json::Value::String(method))) if version == "2.0" => {
+                method.clone()
+            }
+            _ => {
+                return Some(error_response(
+                    response_id,
+                    INVALID_REQUEST,
+                    "invalid request",
+                ))
+            }
+        };
+        if !METHODS.contains(&method.as_str()) {
+            return Some(error_response(
+                response_id,
+                METHOD_NOT_FOUND,
+                format!("method not found: {}", method).as_str(),
+            ));
+        }
+
+        log::info!(target: "rpc", "request method={}", method);
+        if method != "status" {
+            let mut command = match request.get("params") {
+                Some(serde_json::Value::Object(params)) => params.clone(),
+                None | Some(serde_json::Value::Null) => serde_json::Map::new(),
+                Some(_) => {
+                    return Some(error_response(
+                        response_id,
+                        INVALID_PARAMS,
+                        "params must be an object",
+                    ))
+                }
+            };
+            command.insert(String::from("command"), json!(method));
+            let command = match serde_json::from_value::<session::Command>(command.into()) {
+                Ok(command) => command,
+                Err(error) => {
+                    return Some(error_response(
+                        response_id,
+                        INVALID_PARAMS,
+                        error.to_string().as_str(),
+                    ))
+                }
+            };
+
+            self.session.clear_infos();
+            self.session.apply(command);
+        }
+
+        // A status read returns the snapshot, with any stale error inside it
+        let response = match &self.session.error {
+            Some(error) if method != "status" => json!({
+                "jsonrpc": "2.0",
+                "id": response_id,
+                "error": {
+                    "code": SESSION_ERROR,
+                    "message": error.detail.message,
+                    "data": error,
+                },
+            }),
+            _ => json!({
+                "jsonrpc": "2.0",
+                "id": response_id,
+                "result": self.result(method.as_str()),
+            }),
+        };
+
+        // Requests without an id are notifications and get no response
+        id.map(|_| response)
+    }
+
+    fn spawn_stdin_thread(sender: mpsc::Sender<RpcEvent>) {
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                let sent = match line {
+                    Ok(line) if line.trim().is_empty() => Ok(()),
+                    Ok(line) => sender.send(RpcEvent::Request(line)),
+                    Err(_) => break,
+                };
+                if sent.is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(RpcEvent::Closed);
+        });
+    }
+
+    fn spawn_update_thread(sender: mpsc::Sender<RpcEvent>, update_rate_millis: u64) {
+        thread::spawn(move || loop {
+            thread::sleep(time::Duration::from_millis(update_rate_millis));
+            if sender.send(RpcEvent::AutoUpdate).is_err() {
+                break;
+            }
+        });
+    }
+
+    pub fn run(&mut self) -> Result<(), mpsc::RecvError> {
+        let (sender, receiver) = mpsc::channel::<RpcEvent>();
+        RpcServer::spawn_stdin_thread(sender.clone());
+        RpcServer::spawn_update_thread(sender, 1000);
+
+        loop {
+            match receiver.recv()? {
+                RpcEvent::Request(line) => {
+                    if let Some(response) = self.handle_request(line.as_str()) {
+                        write_message(&response);
+                    }
+                }
+                RpcEvent::AutoUpdate if self.session.is_connected() => {
+                    self.session.clear_infos();
+                    self.session.update();
+                }
+                RpcEvent::AutoUpdate => (),
+                RpcEvent::Closed => {
+                    log::info!(target: "rpc", "stdin closed");
+                    break;
+                }
+            }
+            for notification in self.observe() {
+                write_message(&notification);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net;
+    use crate::sim;
+
+    static BASE_URL: &str = "http://offline";
+
+    fn server() -> RpcServer {
+        let path = format!("{}/dungeons/dead_end.toml", env!("CARGO_MANIFEST_DIR"));
+        let world = sim::World::load(path.as_str(), 7).unwrap();
+
+        RpcServer::new(session::Session::with_client(
+            net::MunHttpClient::with_transport(
+                BASE_URL.to_string(),
+                Box::new(sim::SimTransport::new(BASE_URL.to_string(), world)),
+            ),
+        ))
+    }
+
+    fn call(
+        server: &mut RpcServer,
+        id: u64,
+        method: &str,
+        params: serde_json::Value,
+    ) -> serde_json::Value {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        server.handle_request(request.to_string().as_str()).unwrap()
+    }
+
+    fn error_code(response: &serde_json::Value) -> i64 {
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    fn methods(notifications: &[serde_json::Value]) -> Vec<&str> {
+        notifications
+            .iter()
+            .map(|notification| notification["method"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn malformed_requests_get_protocol_errors() {
+        let mut server = server();
+
+        let response = server.handle_request("{not json").unwrap();
+        assert_eq!(error_code(&response), PARSE_ERROR);
+        assert_eq!(response["id"], serde_json::Value::Null);
+
+        for request in [
+            r#"{"id":1,"method":"look"}"#,
+            r#"{"jsonrpc":"1.0","id":1,"method":"look"}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":7}"#,
+            r#""look""#,
+        ] {
+            let response = server.handle_request(request).unwrap();
+            assert_eq!(error_code(&response), INVALID_REQUEST, "{}", request);
+        }
+
+        let response = call(&mut server, 2, "fly", json!({}));
+        assert_eq!(error_code(&response), METHOD_NOT_FOUND);
+        assert_eq!(response["id"], 2);
+
+        let response = call(&mut server, 3, "move", json!(["E"]));
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+        let response = call(&mut server, 4, "move", json!({ "direction": "up" }));
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+        let response = call(&mut server, 5, "attack", json!({}));
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+    }
+
+    #[test]
+    fn session_errors_carry_their_data() {
+        let mut server = server();
+        call(&mut server, 1, "connect", json!(null));
+
+        let response = call(&mut server, 2, "move", json!({ "direction": "W" }));
+        assert_eq!(error_code(&response), SESSION_ERROR);
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["error"]["data"]["code"], 409);
+        assert_eq!(response["error"]["data"]["detail"]["type"], "MUR");
+
+        // The status still answers, with the pending error in the snapshot
+        let response = call(&mut server, 3, "status", json!(null));
+        assert!(response.get("result").is_some(), "{}", response);
+    }
+
+    #[test]
+    fn notifications_get_no_response() {
+        let mut server = server();
+        let request = json!({ "jsonrpc": "2.0", "method": "connect" });
+        assert!(server
+            .handle_request(request.to_string().as_str())
+            .is_none());
+        assert!(server.session.is_connected());
+
+        let request = json!({ "jsonrpc": "2.0", "method": "move", "params": { "direction": "W" } });
+        assert!(server
+            .handle_request(request.to_string().as_str())
+            .is_none());
+    }
+
+    #[test]
+    fn batches_are_answered_in_order() {
+        let mut server = server();
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "connect" },
+            { "jsonrpc": "2.0", "method": "look" },
+            { "jsonrpc": "2.0", "id": 2, "method": "fly" },
+            { "jsonrpc": "2.0", "id": 3, "method": "move", "params": { "direction": "E" } },
+        ]);
+        let responses = server.handle_request(batch.to_string().as_str()).unwrap();
+        let responses = responses.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(error_code(&responses[1]), METHOD_NOT_FOUND);
+        assert_eq!(responses[2]["result"]["description"], "Dead end");
+
+        let response = server.handle_request("[]").unwrap();
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+        let notifications = json!([{ "jsonrpc": "2.0", "method": "look" }]);
+        assert!(server
+            .handle_request(notifications.to_string().as_str())
+            .is_none());
+    }
+
+    #[test]
+    fn room_changes_and_death_are_notified() {
+        let mut server = server();
+        call(&mut server, 1, "connect", json!(null));
+        let notifications = server.observe();
+        assert_eq!(
+            methods(&notifications),
+            vec!["room_changed", "life_changed"]
+        );
+        assert_eq!(notifications[0]["params"]["description"], "Entrance");
+
+        call(&mut server, 2, "look", json!(null));
+        assert!(server.observe().is_empty());
+
+        call(&mut server, 3, "move", json!({ "direction": "E" }));
+        let notifications = server.observe();
+        assert_eq!(methods(&notifications), vec!["room_changed"]);
+        assert_eq!(notifications[0]["params"]["description"], "Dead end");
+
+        let mut died = Vec::new();
+        for id in 4..20 {
+            call(&mut server, id, "attack", json!({ "target": "1" }));
+            died = server.observe();
+            if !server.session.is_connected() {
+                break;
+            }
+        }
+        assert_eq!(methods(&died)[0], "died");
+        assert_eq!(died[0]["params"]["detail"]["type"], "MORT");
+    }
+}
@@ -1,9 +1,18 @@
+use crate::bot;
+use crate::config;
+use crate::control;
+use crate::history;
+use crate::keymap;
+use crate::logging;
 use crate::model;
+use crate::party;
+use crate::relay;
+use crate::replay;
+use crate::screen;
 use crate::session;
+use crate::theme;
 
 use crossterm::event;
-use crossterm::execute;
-use crossterm::terminal;
 use tui::backend;
 use tui::layout;
 use tui::style;
@@ -11,51 +20,28 @@ use tui::text;
 use tui::widgets;
 use tui::Terminal;
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::error;
+use std::fmt;
 use std::io;
+use std::io::Write;
+use std::path;
 use std::sync::mpsc;
 use std::thread;
 use std::time;
 
-static STYLE_GREEN: style::Style = style::Style {
-    fg: Some(style::Color::Black),
-    bg: Some(style::Color::Green),
-    add_modifier: style::Modifier::empty(),
-    sub_modifier: style::Modifier::empty(),
-};
-
-static STYLE_RED: style::Style = style::Style {
-    fg: Some(style::Color::White),
-    bg: Some(style::Color::Red),
-    add_modifier: style::Modifier::empty(),
-    sub_modifier: style::Modifier::empty(),
-};
-
-static STYLE_TITLE: style::Style = style::Style {
-    fg: Some(style::Color::White),
-    bg: Some(style::Color::Blue),
-    add_modifier: style::Modifier::empty(),
-    sub_modifier: style::Modifier::empty(),
-};
-
-static STYLE_DUNGEON: style::Style = style::Style {
-    fg: Some(style::Color::White),
-    bg: Some(style::Color::Blue),
-    add_modifier: style::Modifier::empty(),
-    sub_modifier: style::Modifier::empty(),
-};
-
-static STYLE_RESET: style::Style = style::Style {
-    fg: Some(style::Color::Reset),
-    bg: Some(style::Color::Reset),
-    add_modifier: style::Modifier::empty(),
-    sub_modifier: style::Modifier::empty(),
-};
-
-enum ChannelEvent<I> {
+pub enum ChannelEvent<I> {
     Input(I),
     Tick,
     AutoUpdate,
+    Command(session::Command),
+    /// A background tab's session after a refresh, for the refresh with that id.
+    Refreshed(u64, Box<session::Session>),
+    /// Everyone on the relay after a sync, or why the sync failed.
+    Relayed(Result<Vec<relay::Member>, String>),
+    Suspend,
+    Terminate,
 }
 
 #[derive(Clone)]
@@ -73,6 +59,10 @@ impl EntitiesList {
     }
 
     pub fn try_select_next(&mut self) {
+        if self.entities.is_empty() {
+            self.state.select(None);
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.entities.len() - 1 {
@@ -87,6 +77,10 @@ impl EntitiesList {
     }
 
     pub fn try_select_previous(&mut self) {
+        if self.entities.is_empty() {
+            self.state.select(None);
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -100,8 +94,17 @@ impl EntitiesList {
         self.state.select(Some(i));
     }
 
+    pub fn try_select(&mut self, index: usize) -> bool {
+        if index < self.entities.len() {
+            self.state.select(Some(index));
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn get_selected_entity(&self) -> Option<u32> {
-        Some(self.entities[self.state.selected()?])
+        self.entities.get(self.state.selected()?).copied()
     }
 }
 
@@ -110,8 +113,11 @@ struct PopupManager {
     pub popup_mode: bool,
     pub title: String,
     pub infos: Vec<String>,
+    // First row of `infos` shown, for popups taller than the screen
+    pub scroll: u16,
     pub will_look: bool,
     pub will_attack: bool,
+    pub leaderboard: Option<history::SortKey>,
     pub entities_list: EntitiesList,
 }
 
@@ -120,49 +126,129 @@ impl PopupManager {
         PopupManager {
             popup_mode: false,
             infos: Vec::new(),
+            scroll: 0,
             title: String::new(),
             will_look: false,
             will_attack: false,
+            leaderboard: None,
             entities_list: EntitiesList::new(),
         }
     }
 }
 
-pub struct Runner {
-    terminal: Terminal<backend::CrosstermBackend<std::io::Stdout>>,
-    session: session::Session,
-    receiver: mpsc::Receiver<ChannelEvent<event::KeyEvent>>,
-    popup_manager: PopupManager,
+struct Alerts {
+    config: config::AlertConfig,
+    pending: Vec<String>,
+    banner: Option<(String, time::Instant)>,
+    last_shown: Option<time::Instant>,
 }
 
-impl Runner {
-    pub fn try_new(session: session::Session) -> Result<Runner, io::Error> {
-        terminal::enable_raw_mode()?;
+impl Alerts {
+    pub fn new(config: config::AlertConfig) -> Alerts {
+        Alerts {
+            config,
+            pending: Vec::new(),
+            banner: None,
+            last_shown: None,
+        }
+    }
 
-        let mut stdout = io::stdout();
-        execute!(stdout, terminal::EnterAlternateScreen)?;
-        let backend = backend::CrosstermBackend::new(stdout);
-        let mut terminal = Terminal::new(backend)?;
-        terminal.hide_cursor()?;
-        terminal.clear()?;
+    // Pending alerts are shown together once the minimum interval has passed
+    pub fn show_due(&mut self) -> bool {
+        let interval = time::Duration::from_millis(self.config.min_interval_ms);
+        if self.pending.is_empty()
+            || self
+                .last_shown
+                .is_some_and(|last_shown| last_shown.elapsed() < interval)
+        {
+            return false;
+        }
 
-        let (sender, receiver) = mpsc::channel::<ChannelEvent<event::KeyEvent>>();
-        let tick_rate = 100;
+        let now = time::Instant::now();
+        self.banner = Some((self.pending.join("  |  "), now));
+        self.pending.clear();
+        self.last_shown = Some(now);
 
-        Runner::spawn_sender_thread(sender, tick_rate);
+        true
+    }
 
-        Ok(Runner {
-            session: session,
-            terminal: terminal,
-            receiver: receiver,
-            popup_manager: PopupManager::new(),
-        })
+    pub fn current(&self) -> Option<(String, bool)> {
+        let (text, shown) = self.banner.as_ref()?;
+        let elapsed = shown.elapsed();
+        if elapsed > time::Duration::from_millis(self.config.duration_ms) {
+            return None;
+        }
+
+        Some((text.clone(), (elapsed.as_millis() / 500) % 2 == 0))
     }
+}
 
-    fn fill_entities_list(&mut self) {
-        self.popup_manager.entities_list.entities = self.session.get_entities_keys();
+static DEATH_LOG_LINES: usize = 8;
+static LEADERBOARD_SIZE: usize = 10;
+static MAX_HOOK_ROUNDS: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+enum Badge {
+    Attacked,
+    Error,
+    Dead,
+}
+
+impl fmt::Display for Badge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Badge::Attacked => write!(f, "HIT"),
+            Badge::Error => write!(f, "ERR"),
+            Badge::Dead => write!(f, "DEAD"),
+        }
     }
+}
 
+struct Tab {
+    session: session::Session,
+    badge: Option<Badge>,
+    death: Option<DeathScreen>,
+    // Id of the refresh running on a copy of the session, if any
+    refreshing: Option<u64>,
+}
+
+struct DeathScreen {
+    record: history::RunRecord,
+    log: Vec<String>,
+    message: Option<String>,
+}
+
+pub trait EventSource {
+    fn spawn(self, sender: mpsc::Sender<ChannelEvent<event::KeyEvent>>) -> Result<(), io::Error>;
+}
+
+/// Rings the terminal bell through the backend the runner draws on.
+pub trait Bell {
+    fn ring(&mut self) -> Result<(), io::Error>;
+}
+
+impl<W: io::Write> Bell for backend::CrosstermBackend<W> {
+    fn ring(&mut self) -> Result<(), io::Error> {
+        self.write_all(b"\x07")?;
+        io::Write::flush(self)
+    }
+}
+
+pub struct TerminalEvents {
+    pub tick_rate_millis: u64,
+}
+
+impl EventSource for TerminalEvents {
+    fn spawn(self, sender: mpsc::Sender<ChannelEvent<event::KeyEvent>>) -> Result<(), io::Error> {
+        #[cfg(unix)]
+        TerminalEvents::spawn_signal_thread(sender.clone())?;
+        TerminalEvents::spawn_sender_thread(sender, self.tick_rate_millis);
+
+        Ok(())
+    }
+}
+
+impl TerminalEvents {
     fn spawn_sender_thread(
         sender: mpsc::Sender<ChannelEvent<event::KeyEvent>>,
         tick_rate_millis: u64,
@@ -178,63 +264,872 @@ impl Runner {
                 let timeout = tick_rate
                     .checked_sub(last_tick.elapsed())
                     .unwrap_or_else(|| time::Duration::from_secs(0));
-                if event::poll(timeout).unwrap() {
-                    if let event::Event::Key(key) = event::read().unwrap() {
-                        sender.send(ChannelEvent::Input(key)).unwrap();
-                    }
+                let sent = match event::poll(timeout) {
+                    Ok(true) => match event::read() {
+                        Ok(event::Event::Key(key)) => sender.send(ChannelEvent::Input(key)),
+                        Ok(_) => Ok(()),
+                        Err(_) => sender.send(ChannelEvent::Terminate),
+                    },
+                    Ok(false) => Ok(()),
+                    Err(_) => sender.send(ChannelEvent::Terminate),
+                };
+                if sent.is_err() {
+                    break;
                 }
                 if last_tick.elapsed() >= tick_rate {
-                    sender.send(ChannelEvent::Tick).unwrap();
+                    if sender.send(ChannelEvent::Tick).is_err() {
+                        break;
+                    }
                     last_tick = time::Instant::now();
                 }
                 if update_tick.elapsed() >= update_rate {
-                    sender.send(ChannelEvent::AutoUpdate).unwrap();
+                    if sender.send(ChannelEvent::AutoUpdate).is_err() {
+                        break;
+                    }
                     update_tick = time::Instant::now();
                 }
             }
         });
     }
 
+    #[cfg(unix)]
+    fn spawn_signal_thread(
+        sender: mpsc::Sender<ChannelEvent<event::KeyEvent>>,
+    ) -> Result<(), io::Error> {
+        use signal_hook::consts::signal;
+
+        let mut signals = signal_hook::iterator::Signals::new([
+            signal::SIGINT,
+            signal::SIGTERM,
+            signal::SIGHUP,
+            signal::SIGTSTP,
+        ])?;
+
+        thread::spawn(move || {
+            for received in signals.forever() {
+                let channel_event = match received {
+                    signal::SIGTSTP => ChannelEvent::Suspend,
+                    _ => ChannelEvent::Terminate,
+                };
+                if sender.send(channel_event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl EventSource for Vec<ChannelEvent<event::KeyEvent>> {
+    fn spawn(self, sender: mpsc::Sender<ChannelEvent<event::KeyEvent>>) -> Result<(), io::Error> {
+        for channel_event in self {
+            if sender.send(channel_event).is_err() {
+                return Ok(());
+            }
+        }
+        let _ = sender.send(ChannelEvent::Terminate);
+
+        Ok(())
+    }
+}
+
+pub struct Runner<B: backend::Backend> {
+    terminal: Terminal<B>,
+    screen: Option<screen::ScreenGuard>,
+    session: session::Session,
+    receiver: mpsc::Receiver<ChannelEvent<event::KeyEvent>>,
+    sender: mpsc::Sender<ChannelEvent<event::KeyEvent>>,
+    snapshot: Option<control::SharedSnapshot>,
+    popup_manager: PopupManager,
+    keymap: keymap::Keymap,
+    theme: theme::Theme,
+    replay: Option<replay::ReplayState>,
+    bot: Option<bot::BotRunner>,
+    hooks: Option<Box<dyn bot::Hooks>>,
+    ticks: u64,
+    alerts: Alerts,
+    defense: config::DefenseConfig,
+    defended_hits: u32,
+    history: config::HistoryConfig,
+    death: Option<DeathScreen>,
+    background: Vec<Tab>,
+    active: usize,
+    refreshes: u64,
+    party: Option<party::Party>,
+    relay: Option<relay::RelayClient>,
+    relay_failing: bool,
+    relay_syncing: bool,
+    teammates: Vec<relay::Member>,
+}
+
+impl Runner<backend::CrosstermBackend<io::Stdout>> {
+    pub fn try_new(
+        session: session::Session,
+        keymap: keymap::Keymap,
+        theme: theme::Theme,
+    ) -> Result<Runner<backend::CrosstermBackend<io::Stdout>>, io::Error> {
+        let screen = screen::ScreenGuard::enter()?;
+
+        let backend = backend::CrosstermBackend::new(io::stdout());
+        let events = TerminalEvents {
+            tick_rate_millis: 100,
+        };
+
+        let mut runner = Runner::with_backend(backend, events, session, keymap, theme)?;
+        runner.screen = Some(screen);
+
+        Ok(runner)
+    }
+}
+
+impl<B: backend::Backend + Bell> Runner<B> {
+    pub fn with_backend<E: EventSource>(
+        backend: B,
+        events: E,
+        session: session::Session,
+        keymap: keymap::Keymap,
+        theme: theme::Theme,
+    ) -> Result<Runner<B>, io::Error> {
+        let mut terminal = Terminal::new(backend)?;
+        terminal.hide_cursor()?;
+        terminal.clear()?;
+
+        let (sender, receiver) = mpsc::channel::<ChannelEvent<event::KeyEvent>>();
+        events.spawn(sender.clone())?;
+
+        Ok(Runner {
+            session,
+            terminal,
+            screen: None,
+            receiver,
+            sender,
+            snapshot: None,
+            popup_manager: PopupManager::new(),
+            keymap,
+            theme,
+            replay: None,
+            bot: None,
+            hooks: None,
+            ticks: 0,
+            alerts: Alerts::new(config::AlertConfig::default()),
+            defense: config::DefenseConfig::default(),
+            defended_hits: 0,
+            history: config::HistoryConfig::default(),
+            death: None,
+            background: Vec::new(),
+            active: 0,
+            refreshes: 0,
+            party: None,
+            relay: None,
+            relay_failing: false,
+            relay_syncing: false,
+            teammates: Vec::new(),
+        })
+    }
+
+    pub fn enable_control(
+        &mut self,
+        listener: std::net::TcpListener,
+        token: String,
+    ) -> Result<(), io::Error> {
+        self.snapshot = Some(control::spawn(listener, token, self.sender.clone())?);
+        self.publish_snapshot();
+
+        Ok(())
+    }
+
+    fn publish_snapshot(&self) {
+        if let Some(snapshot) = &self.snapshot {
+            match serde_json::to_string(&self.session.snapshot()) {
+                Ok(json) => {
+                    if let Ok(mut published) = snapshot.lock() {
+                        published.session = json;
+                        published.spectating = self.is_spectating();
+                    }
+                }
+                Err(error) => log::warn!(target: "control", "encode_failed error=\"{}\"", error),
+            }
+        }
+    }
+
+    pub fn set_bot(&mut self, bot: bot::BotRunner) {
+        self.bot = Some(bot);
+    }
+
+    pub fn set_hooks(&mut self, hooks: Box<dyn bot::Hooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    pub fn set_alerts(&mut self, alerts: config::AlertConfig) {
+        self.alerts = Alerts::new(alerts);
+    }
+
+    fn collect_alerts(&mut self) {
+        let events = self.session.take_events();
+        if self.replay.is_some() || !self.alerts.config.enabled {
+            return;
+        }
+
+        for event in events {
+            let alert = match event {
+                session::SessionEvent::Entered { guid } => {
+                    match self
+                        .session
+                        .entity_map
+                        .iter()
+                        .find(|(_, other)| **other == guid)
+                    {
+                        Some((key, _)) => format!("#{} entered the room", key),
+                        None => String::from("Someone entered the room"),
+                    }
+                }
+                session::SessionEvent::Left { .. } => String::from("Someone left the room"),
+                session::SessionEvent::Damaged { amount, life } => {
+                    format!("Lost {} HP without attacking, {} left", amount, life)
+                }
+            };
+            self.alerts.pending.push(alert);
+        }
+
+        if self.alerts.show_due() && self.alerts.config.bell {
+            self.ring_bell();
+        }
+    }
+
+    fn ring_bell(&mut self) {
+        let _ = self.terminal.backend_mut().ring();
+    }
+
+    /// Sessions then append their finished runs to the history file themselves,
+    /// however they get disconnected.
+    pub fn set_history(&mut self, history: config::HistoryConfig) {
+        self.history = history;
+        let history_path = self.history_path();
+        self.session.history_path = history_path.clone();
+        for tab in self.background.iter_mut() {
+            tab.session.history_path = history_path.clone();
+        }
+    }
+
+    fn history_path(&self) -> Option<path::PathBuf> {
+        match self.history.enabled {
+            true => history::resolve_path(self.history.path.as_deref()),
+            false => None,
+        }
+    }
+
+    // Ends the current run and appends it to the history file
+    fn record_run(&mut self, cause: history::EndCause) -> Option<history::RunRecord> {
+        if self.replay.is_some() {
+            return None;
+        }
+
+        self.session.close_run(cause)
+    }
+
+    /// Adds a character in a background tab.
+    pub fn add_session(&mut self, mut session: session::Session) {
+        session.history_path = self.history_path();
+        self.background.push(Tab {
+            session,
+            badge: None,
+            death: None,
+            refreshing: None,
+        });
+    }
+
+    fn tab_count(&self) -> usize {
+        self.background.len() + 1
+    }
+
+    // `background` holds every tab but the active one, in tab order
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.active || index >= self.tab_count() {
+            return;
+        }
+
+        let mut tab = self.background.remove(if index < self.active {
+            index
+        } else {
+            index - 1
+        });
+        std::mem::swap(&mut self.session, &mut tab.session);
+        std::mem::swap(&mut self.death, &mut tab.death);
+        tab.badge = None;
+        tab.refreshing = None;
+        self.background.insert(
+            if self.active < index {
+                self.active
+            } else {
+                self.active - 1
+            },
+            tab,
+        );
+        self.active = index;
+        self.popup_manager = PopupManager::new();
+        self.defended_hits = 0;
+        log::info!(target: "runner", "tab={}", index + 1);
+    }
+
+    fn new_tab(&mut self) {
+        let session = session::Session::with_client(self.session.client.clone());
+        self.add_session(session);
+        let index = self.tab_count() - 1;
+        self.switch_tab(index);
+        self.session.connect();
+    }
+
+    fn close_tab(&mut self) {
+        if self.background.is_empty() {
+            return;
+        }
+
+        self.session.disconnect();
+        let index = self.active.min(self.background.len() - 1);
+        let tab = self.background.remove(index);
+        self.session = tab.session;
+        self.death = tab.death;
+        self.active = index;
+        self.popup_manager = PopupManager::new();
+    }
+
+    // Background tabs are refreshed on their own threads, so a slow server doesn't hold
+    // up the keys; results come back as `ChannelEvent::Refreshed`
+    fn update_background(&mut self) {
+        for tab in self.background.iter_mut() {
+            if !tab.session.is_connected() || tab.refreshing.is_some() {
+                continue;
+            }
+            self.refreshes += 1;
+            tab.refreshing = Some(self.refreshes);
+
+            let id = self.refreshes;
+            let mut session = tab.session.clone();
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                session.update();
+                let _ = sender.send(ChannelEvent::Refreshed(id, Box::new(session)));
+            });
+        }
+    }
+
+    // Background tabs report trouble as badges
+    fn refreshed(&mut self, id: u64, session: session::Session) {
+        // Tabs changed since, by a switch or a party action, drop the result
+        let tab = match self
+            .background
+            .iter_mut()
+            .find(|tab| tab.refreshing == Some(id))
+        {
+            Some(tab) => tab,
+            None => return,
+        };
+        tab.refreshing = None;
+        tab.session = session;
+
+        let attacked = tab
+            .session
+            .take_events()
+            .iter()
+            .any(|event| matches!(event, session::SessionEvent::Damaged { .. }));
+        if let Some(error) = tab.session.error.clone() {
+            if matches!(error.detail.r#type, Some(model::ErrorType::Dead)) {
+                let record = tab.session.close_run(history::EndCause::Death);
+                tab.session.disconnect();
+                tab.badge = Some(Badge::Dead);
+                tab.death = record.map(|record| DeathScreen {
+                    record,
+                    log: logging::recent(DEATH_LOG_LINES),
+                    message: None,
+                });
+            } else {
+                tab.badge = Some(Badge::Error);
+            }
+        } else if attacked && tab.badge.is_none() {
+            tab.badge = Some(Badge::Attacked);
+        }
+        tab.session.clear_infos();
+    }
+
+    fn tab_strip(&self) -> Vec<(String, bool)> {
+        let describe = |session: &session::Session, badge: &Option<Badge>| -> String {
+            let state = match &session.status {
+                Some(status) => format!(
+                    "{}/{} {}",
+                    status.life.unwrap_or(0),
+                    status.total_life,
+                    status.room.description
+                ),
+                None => String::from("disconnected"),
+            };
+            match badge {
+                Some(badge) => format!("{} [{}]", state, badge),
+                None => state,
+            }
+        };
+
+        let mut tabs: Vec<(&session::Session, &Option<Badge>)> = self
+            .background
+            .iter()
+            .map(|tab| (&tab.session, &tab.badge))
+            .collect();
+        tabs.insert(self.active, (&self.session, &None));
+
+        tabs.into_iter()
+            .enumerate()
+            .map(|(i, (session, badge))| {
+                (
+                    format!("{} {}", i + 1, describe(session, badge)),
+                    i == self.active,
+                )
+            })
+            .collect()
+    }
+
+    pub fn set_relay(&mut self, relay: relay::RelayClient) {
+        self.relay = Some(relay);
+    }
+
+    // Publishes every character, then reads back everyone on the relay, on a thread of
+    // its own so an unreachable relay doesn't hold up the keys
+    fn sync_relay(&mut self) {
+        let relay = match &self.relay {
+            Some(relay) if !self.relay_syncing => relay.clone(),
+            _ => return,
+        };
+
+        let mut sessions: Vec<&session::Session> = vec![&self.session];
+        sessions.extend(self.background.iter().map(|tab| &tab.session));
+        let members: Vec<relay::Member> = sessions
+            .into_iter()
+            .filter(|session| session.is_connected())
+            .filter_map(|session| relay.member(session))
+            .collect();
+        self.relay_syncing = true;
+
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let result = members
+                .iter()
+                .try_for_each(|member| relay.publish(member))
+                .and_then(|_| relay.members());
+            let _ = sender.send(ChannelEvent::Relayed(result));
+        });
+    }
+
+    fn relayed(&mut self, result: Result<Vec<relay::Member>, String>) {
+        self.relay_syncing = false;
+
+        match result {
+            Ok(members) => {
+                if self.relay_failing {
+                    log::info!(target: "runner", "relay=ok members={}", members.len());
+                }
+                self.relay_failing = false;
+                self.teammates = members;
+            }
+            Err(error) => {
+                if !self.relay_failing {
+                    log::warn!(target: "runner", "relay_failed error=\"{}\"", error);
+                }
+                self.relay_failing = true;
+                self.teammates.clear();
+            }
+        }
+    }
+
+    // Everyone on the relay but the active character, by guid for those in our room
+    // and by relay id for the others
+    fn teammates(&self) -> BTreeMap<String, relay::Member> {
+        let relay = match &self.relay {
+            Some(relay) => relay,
+            None => return BTreeMap::new(),
+        };
+        let own_id = self
+            .session
+            .get_guid()
+            .ok()
+            .map(|guid| relay.id(guid.as_str()));
+        let here: HashMap<String, String> = self
+            .session
+            .status
+            .iter()
+            .flat_map(|status| status.room.entities.iter())
+            .map(|guid| (relay.id(guid.as_str()), guid.clone()))
+            .collect();
+
+        self.teammates
+            .iter()
+            .filter(|member| Some(&member.id) != own_id.as_ref())
+            .map(|member| {
+                let key = here.get(&member.id).unwrap_or(&member.id);
+                (key.clone(), member.clone())
+            })
+            .collect()
+    }
+
+    fn toggle_party(&mut self) {
+        self.party = match self.party {
+            Some(_) => None,
+            None => Some(party::Party::new()),
+        };
+        log::info!(target: "runner", "party={}", self.party.is_some());
+    }
+
+    // The active tab leads, background tabs follow
+    fn r#move(&mut self, direction: model::Direction) {
+        match &mut self.party {
+            Some(party) => {
+                // Refreshes in flight would undo the followers' moves
+                for tab in self.background.iter_mut() {
+                    tab.refreshing = None;
+                }
+                let mut followers: Vec<&mut session::Session> = self
+                    .background
+                    .iter_mut()
+                    .map(|tab| &mut tab.session)
+                    .collect();
+                let reports = party.r#move(&mut self.session, &mut followers, direction);
+                if reports.iter().any(|report| report.outcome.is_err()) {
+                    self.display_party_reports(String::from("Party move"), reports);
+                }
+            }
+            None => {
+                self.session.r#move(direction);
+                self.session.update();
+            }
+        }
+    }
+
+    fn attack(&mut self, guid: String) {
+        match &mut self.party {
+            Some(party) => {
+                for tab in self.background.iter_mut() {
+                    tab.refreshing = None;
+                }
+                let mut members: Vec<&mut session::Session> = vec![&mut self.session];
+                members.extend(self.background.iter_mut().map(|tab| &mut tab.session));
+                let reports = party.attack(&mut members, guid.as_str());
+                self.display_party_reports(String::from("Party attack"), reports);
+            }
+            None => {
+                self.session.attack(guid);
+                self.session.update();
+            }
+        }
+    }
+
+    fn display_party_reports(&mut self, title: String, reports: Vec<party::MemberReport>) {
+        let mut guids: Vec<String> = self
+            .background
+            .iter()
+            .map(|tab| tab.session.get_guid().unwrap_or_default())
+            .collect();
+        guids.insert(self.active, self.session.get_guid().unwrap_or_default());
+
+        self.popup_manager.popup_mode = true;
+        self.popup_manager.title = title;
+        self.popup_manager.infos = reports
+            .iter()
+            .map(|report| {
+                let tab = guids
+                    .iter()
+                    .position(|guid| *guid == report.guid)
+                    .map(|i| (i + 1).to_string())
+                    .unwrap_or_else(|| String::from("?"));
+                match &report.outcome {
+                    Ok(message) => format!("{}: {}", tab, message),
+                    Err(message) => format!("{}: FAILED {}", tab, message),
+                }
+            })
+            .collect();
+    }
+
+    fn display_leaderboard(&mut self, sort: history::SortKey) {
+        let history = match history::resolve_path(self.history.path.as_deref()) {
+            Some(history_path) => history::History::load(&history_path),
+            None => Ok(history::History::default()),
+        };
+        let infos = match history {
+            Ok(history) => {
+                let records = history.query(&history::Query {
+                    sort,
+                    limit: Some(LEADERBOARD_SIZE),
+                    ..history::Query::default()
+                });
+                if records.is_empty() {
+                    vec![String::from("No runs recorded yet")]
+                } else {
+                    history::table(&records, false)
+                }
+            }
+            Err(error) => vec![error],
+        };
+
+        self.popup_manager.popup_mode = true;
+        self.popup_manager.title = format!("Leaderboard by {}", sort);
+        self.popup_manager.scroll = 0;
+        self.popup_manager.leaderboard = Some(sort);
+        self.popup_manager.infos = infos;
+    }
+
+    fn save_report(&mut self) {
+        if let Some(death) = &mut self.death {
+            let directory = path::PathBuf::from(self.history.report_dir.as_deref().unwrap_or("."));
+            let json = self.history.report_format == config::ReportFormat::Json;
+            death.message = Some(
+                match history::save_report(&directory, &death.record, &death.log, json) {
+                    Ok(report_path) => format!("Saved to {}", report_path.display()),
+                    Err(error) => error,
+                },
+            );
+        }
+    }
+
+    pub fn set_defense(&mut self, defense: config::DefenseConfig) {
+        self.defense = defense;
+    }
+
+    // Responds once to each new hit while under attack
+    fn defend(&mut self) {
+        let suspects = match &self.session.under_attack {
+            Some(under_attack) if under_attack.hits != self.defended_hits => {
+                self.defended_hits = under_attack.hits;
+                under_attack.suspects.clone()
+            }
+            Some(_) => return,
+            None => {
+                self.defended_hits = 0;
+                return;
+            }
+        };
+
+        let action = match self.defense.response {
+            config::DefenseResponse::None => None,
+            config::DefenseResponse::Alert => {
+                self.ring_bell();
+                None
+            }
+            config::DefenseResponse::Flee => self
+                .session
+                .status
+                .as_ref()
+                .and_then(|status| status.room.paths.first().cloned())
+                .map(bot::Action::Move),
+            config::DefenseResponse::Counterattack => {
+                suspects.first().cloned().map(bot::Action::Attack)
+            }
+        };
+        log::info!(
+            target: "runner",
+            "defense response={:?} action={:?}",
+            self.defense.response,
+            action
+        );
+        if let Some(command) = action.and_then(|action| action.to_command()) {
+            self.session.apply(command);
+        }
+    }
+
+    // Runs after every change to the session; actions the hooks take are changes too,
+    // so they get a few more rounds to react to them
+    fn run_hooks(&mut self) {
+        if let Some(hooks) = &mut self.hooks {
+            for _ in 0..MAX_HOOK_ROUNDS {
+                let observation = bot::Observation::from_session(&self.session, self.ticks);
+                let commands: Vec<session::Command> = hooks
+                    .react(&observation)
+                    .iter()
+                    .filter_map(|action| action.to_command())
+                    .collect();
+                if commands.is_empty() {
+                    break;
+                }
+                for command in commands {
+                    self.session.apply(command);
+                }
+            }
+        }
+    }
+
+    fn is_spectating(&self) -> bool {
+        self.replay.is_some() || self.bot.is_some()
+    }
+
+    pub fn set_replay(&mut self, replay: replay::ReplayState) {
+        self.replay = Some(replay);
+        self.load_replay_frame();
+    }
+
+    fn load_replay_frame(&mut self) {
+        if let Some(frame) = self.replay.as_ref().and_then(|replay| replay.current()) {
+            self.session = frame.session.clone();
+            self.popup_manager = PopupManager::new();
+        }
+    }
+
+    fn context(&self) -> keymap::Context {
+        if self.death.is_some() {
+            keymap::Context::Death
+        } else if self.popup_manager.popup_mode {
+            keymap::Context::Popup
+        } else if self.replay.is_some() {
+            keymap::Context::Replay
+        } else {
+            keymap::Context::Normal
+        }
+    }
+
+    fn fill_entities_list(&mut self) {
+        self.popup_manager.entities_list.entities = self.session.get_entities_keys();
+    }
+
     fn display_misc_info(&mut self) {
-        match &self.session.fight_info {
-            Some(fight) => {
-                self.popup_manager.popup_mode = true;
-                self.popup_manager.title = String::from("Fight result");
-                let infos_vec = vec![
-                    format!(
-                        "You inflicted {} DP and have {} HP left",
-                        fight.attacker.damage, fight.attacker.life
-                    ),
-                    format!(
-                        "Your enemy inflicted {} DP and has {} HP left",
-                        fight.defender.damage, fight.defender.life
-                    ),
-                ];
-                self.popup_manager.infos = infos_vec;
-            }
-            None => (),
+        if let Some(fight) = &self.session.fight_info {
+            self.popup_manager.popup_mode = true;
+            self.popup_manager.title = String::from("Fight result");
+            self.popup_manager.infos = Self::fight_infos(fight);
         };
-        match &self.session.entity_info {
-            Some(entity) => {
-                self.popup_manager.popup_mode = true;
-                self.popup_manager.title = String::from("Entity info");
-                let infos_vec = vec![
-                    entity.description.clone(),
-                    entity.r#type.to_string(),
-                    format!("{}/{} HP", &entity.life, &entity.total_life),
-                ];
-                self.popup_manager.infos = infos_vec;
-            }
-            None => (),
+        if let Some(entity) = &self.session.entity_info {
+            self.popup_manager.popup_mode = true;
+            self.popup_manager.title = String::from("Entity info");
+            self.popup_manager.infos = Self::entity_infos(entity);
         };
     }
 
+    fn fight_infos(fight: &model::Fight) -> Vec<String> {
+        vec![
+            format!(
+                "You inflicted {} DP and have {} HP left",
+                fight.attacker.damage, fight.attacker.life
+            ),
+            format!(
+                "Your enemy inflicted {} DP and has {} HP left",
+                fight.defender.damage, fight.defender.life
+            ),
+        ]
+    }
+
+    fn entity_infos(entity: &model::Entity) -> Vec<String> {
+        vec![
+            entity.description.clone(),
+            entity.r#type.to_string(),
+            format!("{}/{} HP", &entity.life, &entity.total_life),
+        ]
+    }
+
+    fn error_infos(err: &model::Error) -> Vec<String> {
+        let mut infos_vec: Vec<String> = Vec::new();
+        if let Some(code) = err.code {
+            infos_vec.push(format!("Code: {}", code))
+        }
+        if let Some(r#type) = &err.detail.r#type {
+            infos_vec.push(format!("Type: {}", r#type))
+        }
+        infos_vec.push(format!("Message: {}", err.detail.message));
+
+        infos_vec
+    }
+
     fn draw(&mut self) -> Result<(), io::Error> {
         let session = self.session.clone();
         let mut popup_manager = self.popup_manager.clone();
+        let theme = self.theme.clone();
+        let status_bar = self.status_bar();
+        let banner = self.alerts.current();
+        let death_lines = self.death_lines();
+        let tab_strip = match self.tab_count() {
+            1 => None,
+            _ => Some(self.tab_strip()),
+        };
+        let party = self.party.is_some();
+        let teammates = self.teammates();
+        let mut max_scroll: u16 = 0;
 
         self.terminal.draw(|f| {
-            let size = f.size();
+            let mut size = f.size();
+
+            f.render_widget(widgets::Block::default().style(theme.base), size);
+
+            if let Some((banner_text, flash)) = banner {
+                let y_chunks = layout::Layout::default()
+                    .direction(layout::Direction::Vertical)
+                    .constraints(
+                        [layout::Constraint::Length(1), layout::Constraint::Min(0)].as_ref(),
+                    )
+                    .split(size);
+                let banner_style = if flash {
+                    theme.bad
+                } else {
+                    theme.bad.add_modifier(style::Modifier::REVERSED)
+                };
+                let banner_paragraph = widgets::Paragraph::new(text::Spans::from(
+                    text::Span::styled(banner_text, banner_style),
+                ))
+                .style(banner_style)
+                .alignment(layout::Alignment::Center);
+                f.render_widget(banner_paragraph, y_chunks[0]);
+                size = y_chunks[1];
+            }
+
+            if let Some(tab_strip) = tab_strip {
+                let y_chunks = layout::Layout::default()
+                    .direction(layout::Direction::Vertical)
+                    .constraints(
+                        [layout::Constraint::Length(1), layout::Constraint::Min(0)].as_ref(),
+                    )
+                    .split(size);
+                let mut tab_spans: Vec<text::Span> = Vec::new();
+                if party {
+                    tab_spans.push(text::Span::styled("PARTY ", theme.bad));
+                }
+                for (i, (label, active)) in tab_strip.into_iter().enumerate() {
+                    if i > 0 {
+                        tab_spans.push(text::Span::raw(" | "));
+                    }
+                    tab_spans.push(if active {
+                        text::Span::styled(label, theme.highlight)
+                    } else {
+                        text::Span::raw(label)
+                    });
+                }
+                let tabs_paragraph =
+                    widgets::Paragraph::new(text::Spans::from(tab_spans)).style(theme.base);
+                f.render_widget(tabs_paragraph, y_chunks[0]);
+                size = y_chunks[1];
+            }
+
+            if let Some((bar_title, bar_lines)) = status_bar {
+                let bar_height = bar_lines.len() as u16 + 2;
+                let y_chunks = layout::Layout::default()
+                    .direction(layout::Direction::Vertical)
+                    .constraints(
+                        [
+                            layout::Constraint::Min(0),
+                            layout::Constraint::Length(bar_height),
+                        ]
+                        .as_ref(),
+                    )
+                    .split(size);
+                let bar_block = widgets::Block::default()
+                    .title(bar_title)
+                    .style(theme.base)
+                    .borders(widgets::Borders::ALL);
+                let bar_spans: Vec<text::Spans> = bar_lines
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, line)| match i {
+                        0 => text::Spans::from(text::Span::styled(line, theme.title)),
+                        _ => text::Spans::from(line),
+                    })
+                    .collect();
+                let bar_paragraph = widgets::Paragraph::new(bar_spans)
+                    .block(bar_block)
+                    .wrap(widgets::Wrap { trim: false });
+                f.render_widget(bar_paragraph, y_chunks[1]);
+                size = y_chunks[0];
+            }
 
             let x_chunks = layout::Layout::default()
                 .direction(layout::Direction::Horizontal)
@@ -260,27 +1155,29 @@ impl Runner {
 
             let net_block = widgets::Block::default()
                 .title("Net")
+                .style(theme.base)
                 .borders(widgets::Borders::ALL);
 
             let status_block = widgets::Block::default()
                 .title("Status")
+                .style(theme.base)
                 .borders(widgets::Borders::ALL);
 
             let dungeon_block = widgets::Block::default()
                 .title("Dungeon")
-                .style(if matches!(session.status.clone(), Some(_)) {
-                    STYLE_DUNGEON
+                .style(if session.status.clone().is_some() {
+                    theme.dungeon
                 } else {
-                    STYLE_RESET
+                    theme.base
                 })
                 .borders(widgets::Borders::ALL);
 
             let net_paragraph = widgets::Paragraph::new(vec![
                 text::Spans::from(text::Span::raw(session.client.base_url.clone())),
-                text::Spans::from(if session.is_connected().clone() {
-                    text::Span::styled("CONNECTED", STYLE_GREEN)
+                text::Spans::from(if session.is_connected() {
+                    text::Span::styled("CONNECTED", theme.good)
                 } else {
-                    text::Span::styled("DISCONNECTED", STYLE_RED)
+                    text::Span::styled("DISCONNECTED", theme.bad)
                 }),
             ])
             .block(net_block)
@@ -288,23 +1185,73 @@ impl Runner {
 
             let status_spans = match session.status.clone() {
                 Some(status) => {
-                    let temp_vec = vec![
+                    let mut temp_vec = vec![
                         text::Spans::from(vec![
-                            text::Span::styled("HP", STYLE_TITLE),
+                            text::Span::styled("HP", theme.title),
                             text::Span::raw(String::from(" ")),
                             text::Span::raw(format!(
                                 "{}/{}",
-                                status.life.clone().unwrap_or(0).to_string(),
-                                status.total_life.clone().to_string()
+                                status.life.unwrap_or(0),
+                                status.total_life.clone()
                             )),
                         ]),
                         text::Spans::from(text::Span::raw(String::from("\n"))),
                         text::Spans::from(vec![
-                            text::Span::styled("ROOM", STYLE_TITLE),
+                            text::Span::styled("ROOM", theme.title),
                             text::Span::raw(String::from(" ")),
                             text::Span::raw(status.room.description.clone()),
                         ]),
                     ];
+                    if let Some(under_attack) = &session.under_attack {
+                        let suspects: Vec<String> = under_attack
+                            .suspects
+                            .iter()
+                            .map(|guid| {
+                                match session.entity_map.iter().find(|(_, other)| *other == guid) {
+                                    Some((key, _)) => format!("#{}", key),
+                                    None => String::from("?"),
+                                }
+                            })
+                            .collect();
+                        temp_vec.push(text::Spans::from(text::Span::raw(String::from("\n"))));
+                        temp_vec.push(text::Spans::from(vec![
+                            text::Span::styled("UNDER ATTACK", theme.bad),
+                            text::Span::raw(format!(
+                                " -{} HP/tick, {} lost",
+                                under_attack.last_loss, under_attack.total_loss
+                            )),
+                        ]));
+                        temp_vec.push(text::Spans::from(vec![
+                            text::Span::styled("SUSPECTS", theme.title),
+                            text::Span::raw(String::from(" ")),
+                            text::Span::raw(if suspects.is_empty() {
+                                String::from("unknown")
+                            } else {
+                                suspects.join(" ")
+                            }),
+                        ]));
+                    }
+                    if !teammates.is_empty() {
+                        temp_vec.push(text::Spans::from(text::Span::raw(String::from("\n"))));
+                        temp_vec.push(text::Spans::from(text::Span::styled("TEAM", theme.title)));
+                        for (teammate, member) in teammates.iter() {
+                            let name = match session
+                                .entity_map
+                                .iter()
+                                .find(|(_, guid)| *guid == teammate)
+                            {
+                                Some((key, _)) => format!("#{}", key),
+                                None => teammate.chars().take(8).collect(),
+                            };
+                            temp_vec.push(text::Spans::from(vec![
+                                text::Span::styled(name, theme.good),
+                                text::Span::raw(format!(
+                                    " {}/{} {}",
+                                    member.life, member.total_life, member.room
+                                )),
+                            ]));
+                        }
+                    }
 
                     temp_vec
                 }
@@ -317,82 +1264,113 @@ impl Runner {
 
             let dungeon_canvas = widgets::canvas::Canvas::default()
                 .block(dungeon_block)
-                .background_color(STYLE_DUNGEON.bg.unwrap())
+                .background_color(if session.status.clone().is_some() {
+                    theme.dungeon_bg()
+                } else {
+                    theme.base.bg.unwrap_or(style::Color::Reset)
+                })
                 .paint(|ctx| {
-                    if matches!(session.status.clone(), Some(_)) {
+                    if session.status.clone().is_some() {
                         ctx.draw(&widgets::canvas::Rectangle {
                             x: 0.0,
                             y: 0.0,
                             width: 100.0,
                             height: 100.0,
-                            color: STYLE_DUNGEON.fg.unwrap(),
+                            color: theme.dungeon_fg(),
                         });
                         ctx.layer();
-                        match session.status.clone() {
-                            Some(status) => {
-                                for direction in status.room.paths.clone().iter() {
-                                    match direction {
-                                        model::Direction::N => {
-                                            ctx.print(50.0, 100.0, "N", STYLE_DUNGEON.fg.unwrap())
-                                        }
-                                        model::Direction::S => {
-                                            ctx.print(50.0, 0.0, "S", STYLE_DUNGEON.fg.unwrap())
-                                        }
-                                        model::Direction::W => {
-                                            ctx.print(0.0, 50.0, "W", STYLE_DUNGEON.fg.unwrap())
-                                        }
-                                        model::Direction::E => {
-                                            ctx.print(100.0, 50.0, "E", STYLE_DUNGEON.fg.unwrap())
-                                        }
+                        if let Some(status) = session.status.clone() {
+                            for direction in status.room.paths.clone().iter() {
+                                match direction {
+                                    model::Direction::N => {
+                                        ctx.print(50.0, 100.0, "N", theme.dungeon_fg())
+                                    }
+                                    model::Direction::S => {
+                                        ctx.print(50.0, 0.0, "S", theme.dungeon_fg())
+                                    }
+                                    model::Direction::W => {
+                                        ctx.print(0.0, 50.0, "W", theme.dungeon_fg())
+                                    }
+                                    model::Direction::E => {
+                                        ctx.print(100.0, 50.0, "E", theme.dungeon_fg())
                                     }
                                 }
                             }
-                            None => (),
                         };
                     }
                 })
                 .x_bounds([00.0, 100.0])
                 .y_bounds([00.0, 100.0]);
 
-            let entities_block = widgets::Block::default().borders(widgets::Borders::NONE);
-            let mut entities_string = String::new();
-            for entity in session.get_entities_keys().iter() {
-                entities_string += entity.to_string().as_str();
-                entities_string += "   ";
+            let entities_block = widgets::Block::default()
+                .style(theme.dungeon)
+                .borders(widgets::Borders::NONE);
+            let mut entities_spans: Vec<text::Span> = Vec::new();
+            for key in session.get_entities_keys() {
+                let guid = session.entity_map.get(&key).cloned().unwrap_or_default();
+                entities_spans.push(if teammates.contains_key(&guid) {
+                    text::Span::styled(format!("{} (team)", key), theme.good)
+                } else {
+                    text::Span::raw(key.to_string())
+                });
+                entities_spans.push(text::Span::raw("   "));
             }
             let entities_paragraph =
-                widgets::Paragraph::new(vec![text::Spans::from(entities_string)])
+                widgets::Paragraph::new(vec![text::Spans::from(entities_spans)])
                     .block(entities_block)
                     .wrap(widgets::Wrap { trim: false });
-            let entities_area = Runner::centered_rect(80, 80, x_chunks[1]);
+            let entities_area = Self::centered_rect(80, 80, x_chunks[1]);
             f.render_widget(widgets::Clear, entities_area);
             f.render_widget(entities_paragraph, entities_area);
 
             f.render_widget(net_paragraph, left_chunks[0]);
             f.render_widget(status_paragraph, left_chunks[1]);
-            f.render_widget(dungeon_canvas, x_chunks[1]);
+            if x_chunks[1].width > 2 && x_chunks[1].height > 2 {
+                f.render_widget(dungeon_canvas, x_chunks[1]);
+            }
 
-            if popup_manager.popup_mode {
+            if let Some(death_lines) = death_lines {
+                let death_block = widgets::Block::default()
+                    .title(text::Span::styled("You died", theme.bad))
+                    .style(theme.popup)
+                    .borders(widgets::Borders::ALL);
+                let death_paragraph = widgets::Paragraph::new(
+                    death_lines
+                        .into_iter()
+                        .map(text::Spans::from)
+                        .collect::<Vec<text::Spans>>(),
+                )
+                .block(death_block)
+                .wrap(widgets::Wrap { trim: false });
+                let area = Self::centered_rect(80, 80, size);
+                f.render_widget(widgets::Clear, area);
+                f.render_widget(death_paragraph, area);
+            } else if popup_manager.popup_mode {
                 if popup_manager.will_attack || popup_manager.will_look {
                     let popup_block = widgets::Block::default()
                         .title(popup_manager.title.clone())
+                        .style(theme.popup)
                         .borders(widgets::Borders::ALL);
-                    let area = Runner::centered_rect(60, 60, size);
+                    let area = Self::centered_rect(60, 60, size);
                     let entities: Vec<widgets::ListItem> = popup_manager
                         .entities_list
                         .entities
                         .iter()
                         .map(|i| {
-                            widgets::ListItem::new(vec![text::Spans::from(text::Span::raw(
-                                format!("{}", i),
-                            ))])
+                            let teammate = session
+                                .entity_map
+                                .get(i)
+                                .is_some_and(|guid| teammates.contains_key(guid));
+                            widgets::ListItem::new(vec![text::Spans::from(if teammate {
+                                text::Span::styled(format!("{} (team)", i), theme.good)
+                            } else {
+                                text::Span::raw(format!("{}", i))
+                            })])
                         })
                         .collect();
                     let popup_list = widgets::List::new(entities)
                         .block(popup_block)
-                        .highlight_style(
-                            style::Style::default().add_modifier(style::Modifier::BOLD),
-                        )
+                        .highlight_style(theme.highlight)
                         .highlight_symbol("> ");
                     f.render_widget(widgets::Clear, area);
                     f.render_stateful_widget(
@@ -405,82 +1383,236 @@ impl Runner {
                     for info in popup_manager.infos.clone().iter() {
                         popup_spans.push(text::Spans::from(info.clone()));
                     }
+                    let area = Self::centered_rect(60, 60, size);
+                    let inner_width = area.width.saturating_sub(2).max(1) as usize;
+                    let inner_height = area.height.saturating_sub(2);
+                    let rows: usize = popup_manager
+                        .infos
+                        .iter()
+                        .map(|info| info.chars().count().div_ceil(inner_width).max(1))
+                        .sum();
+                    max_scroll = (rows as u16).saturating_sub(inner_height);
+                    let scroll = popup_manager.scroll.min(max_scroll);
+                    let title = match max_scroll {
+                        0 => popup_manager.title.clone(),
+                        _ => format!(
+                            "{} {}-{}/{}",
+                            popup_manager.title,
+                            scroll + 1,
+                            scroll + inner_height,
+                            rows
+                        ),
+                    };
                     let popup_block = widgets::Block::default()
-                        .title(popup_manager.title.clone())
+                        .title(title)
+                        .style(theme.popup)
                         .borders(widgets::Borders::ALL);
                     let popup_paragraph = widgets::Paragraph::new(popup_spans)
                         .block(popup_block)
-                        .wrap(widgets::Wrap { trim: false });
-                    let area = Runner::centered_rect(60, 60, size);
+                        .wrap(widgets::Wrap { trim: false })
+                        .scroll((scroll, 0));
                     f.render_widget(widgets::Clear, area);
                     f.render_widget(popup_paragraph, area);
                 }
             }
         })?;
+        self.popup_manager.scroll = self.popup_manager.scroll.min(max_scroll);
 
         Ok(())
     }
 
-    fn handle_errors(&mut self) {
-        match self.session.error.clone() {
-            Some(err) => {
-                if matches!(err.detail.r#type, Some(model::ErrorType::Dead)) {
-                    self.session.disconnect();
-                }
+    fn death_lines(&self) -> Option<Vec<String>> {
+        let death = self.death.as_ref()?;
+        let record = &death.record;
 
-                self.popup_manager.popup_mode = true;
-                self.popup_manager.title = String::from("Error");
+        let mut lines = vec![
+            format!("Survived: {}", history::format_duration(record.survived())),
+            format!("Rooms explored: {}", record.rooms_explored),
+            format!("Kills: {}", record.kills),
+            format!(
+                "Damage dealt / taken: {} / {}",
+                record.damage_dealt, record.damage_taken
+            ),
+            format!(
+                "Died in: {}",
+                record.last_room.as_deref().unwrap_or("unknown room")
+            ),
+            format!(
+                "Killed by: {}",
+                record.last_opponent.as_deref().unwrap_or("unknown")
+            ),
+            String::new(),
+        ];
+        lines.extend(self.keymap.help_lines(&[keymap::Context::Death]));
+        if self.tab_count() > 1 {
+            lines.extend(self.keymap.help_lines(&[keymap::Context::Tabs]));
+        }
+        if let Some(message) = &death.message {
+            lines.push(message.clone());
+        }
+        if !death.log.is_empty() {
+            lines.push(String::new());
+            lines.push(String::from("Last log entries:"));
+            lines.extend(death.log.iter().cloned());
+        }
 
-                let mut infos_vec: Vec<String> = Vec::new();
-                match err.code {
-                    Some(code) => infos_vec.push(format!("Code: {}", code)),
-                    None => (),
-                }
-                match err.detail.r#type {
-                    Some(r#type) => infos_vec.push(format!("Type: {}", r#type.to_string())),
-                    None => (),
+        Some(lines)
+    }
+
+    fn handle_errors(&mut self) {
+        if let Some(err) = self.session.error.clone() {
+            if matches!(err.detail.r#type, Some(model::ErrorType::Dead)) {
+                let record = self.record_run(history::EndCause::Death);
+                self.session.disconnect();
+                if let Some(record) = record {
+                    self.popup_manager = PopupManager::new();
+                    self.death = Some(DeathScreen {
+                        record,
+                        log: logging::recent(DEATH_LOG_LINES),
+                        message: None,
+                    });
+                    return;
                 }
-                infos_vec.push(format!("Message: {}", err.detail.message));
-                self.popup_manager.infos = infos_vec;
             }
-            _ => (),
+
+            self.popup_manager.popup_mode = true;
+            self.popup_manager.title = String::from("Error");
+            self.popup_manager.infos = Self::error_infos(&err);
         }
     }
 
     fn display_keybinds(&mut self) {
+        let contexts: &[keymap::Context] = match (&self.replay, &self.bot) {
+            (Some(_), _) => &[keymap::Context::Global, keymap::Context::Replay],
+            (None, Some(_)) => &[keymap::Context::Global],
+            (None, None) => &[
+                keymap::Context::Global,
+                keymap::Context::Normal,
+                keymap::Context::Tabs,
+            ],
+        };
+        let mut infos = self.keymap.help_lines(contexts);
+        infos.extend(self.keymap.help_lines(&[keymap::Context::Popup]));
+
         self.popup_manager.popup_mode = true;
         self.popup_manager.title = String::from("Keybinds");
-        self.popup_manager.infos = vec![
-            String::from("[c]        (re)connect"),
-            String::from("[d]        disconnect"),
-            String::from("[l]        look around"),
-            String::from("[e]        look entity"),
-            String::from("[a]        attack"),
-            String::from("[arrows]   move"),
-            String::from("[q]        quit"),
-        ];
+        self.popup_manager.infos = infos;
+    }
+
+    fn status_bar(&self) -> Option<(String, Vec<String>)> {
+        let (title, header, session) = match (&self.replay, &self.bot) {
+            (Some(replay), _) => {
+                let frame = replay.current()?;
+                let header = format!(
+                    "{} {}/{}  {}x  {}",
+                    if replay.playing { "PLAYING" } else { "PAUSED" },
+                    replay.index + 1,
+                    replay.frames.len(),
+                    replay.speed(),
+                    frame.label
+                );
+                (String::from("Replay"), header, &frame.session)
+            }
+            (None, Some(bot)) => {
+                let header = format!(
+                    "{} step {}  {}",
+                    if bot.finished { "FINISHED" } else { "RUNNING" },
+                    bot.steps,
+                    bot.last_action
+                        .as_ref()
+                        .map(|action| format!("{:?}", action))
+                        .unwrap_or_default()
+                );
+                (format!("Bot: {}", bot.name()), header, &self.session)
+            }
+            (None, None) => return None,
+        };
+
+        let mut lines = vec![header];
+        if let Some(fight) = &session.fight_info {
+            lines.extend(Self::fight_infos(fight));
+        }
+        if let Some(entity) = &session.entity_info {
+            lines.extend(Self::entity_infos(entity));
+        }
+        if let Some(err) = &session.error {
+            lines.extend(Self::error_infos(err));
+        }
+
+        Some((title, lines))
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn error::Error>> {
         loop {
-            self.display_misc_info();
-            self.handle_errors();
+            if !self.is_spectating() {
+                self.display_misc_info();
+                self.handle_errors();
+            }
             self.fill_entities_list();
+            self.collect_alerts();
+            self.publish_snapshot();
 
             self.draw()?;
 
             match self.receiver.recv()? {
-                ChannelEvent::Input(event) => match event.code {
-                    event::KeyCode::Char('q') => {
-                        self.restore_terminal()?;
-                        break;
+                ChannelEvent::Input(event) => {
+                    match self.keymap.action_for(&event, self.context()) {
+                        Some(keymap::Action::Quit) => {
+                            log::info!(target: "runner", "quit");
+                            self.record_run(history::EndCause::Quit);
+                            for tab in self.background.iter_mut() {
+                                tab.session.close_run(history::EndCause::Quit);
+                            }
+                            break;
+                        }
+                        Some(keymap::Action::Suspend) => self.suspend()?,
+                        Some(action) => {
+                            self.handle_action(action);
+                            if !self.is_spectating() {
+                                self.run_hooks();
+                            }
+                        }
+                        None => (),
                     }
-                    e => self.handle_input(e),
-                },
-                ChannelEvent::AutoUpdate => {
+                }
+                ChannelEvent::Tick => {
+                    if let Some(replay) = &mut self.replay {
+                        if replay.tick() {
+                            self.load_replay_frame();
+                        }
+                    }
+                    if let Some(bot) = &mut self.bot {
+                        if bot.is_due() {
+                            bot.step(&mut self.session);
+                        }
+                    }
+                }
+                ChannelEvent::AutoUpdate if !self.is_spectating() => {
+                    self.ticks += 1;
                     if self.session.is_connected() {
                         self.session.update();
+                        self.defend();
+                        self.run_hooks();
                     }
+                    self.update_background();
+                    self.sync_relay();
+                }
+                ChannelEvent::Command(command) if !self.is_spectating() => {
+                    self.session.apply(command);
+                    self.run_hooks();
+                }
+                // Queued before the runner started spectating
+                ChannelEvent::Command(command) => log::warn!(
+                    target: "control",
+                    "command_dropped reason=spectating command={:?}",
+                    command
+                ),
+                ChannelEvent::Refreshed(id, session) => self.refreshed(id, *session),
+                ChannelEvent::Relayed(result) => self.relayed(result),
+                ChannelEvent::Suspend => self.suspend()?,
+                ChannelEvent::Terminate => {
+                    log::info!(target: "runner", "terminated");
+                    break;
                 }
                 _ => (),
             }
@@ -516,96 +1648,462 @@ impl Runner {
             .split(popup_layout[1])[1]
     }
 
-    fn restore_terminal(&mut self) -> Result<(), io::Error> {
-        terminal::disable_raw_mode()?;
-        execute!(self.terminal.backend_mut(), terminal::LeaveAlternateScreen)?;
-        self.terminal.show_cursor()?;
+    fn suspend(&mut self) -> Result<(), io::Error> {
+        if let Some(screen) = &self.screen {
+            log::info!(target: "runner", "suspend");
+            screen.suspend()?;
+            log::info!(target: "runner", "resume");
+            self.terminal.clear()?;
+        }
 
         Ok(())
     }
 
-    fn handle_input(&mut self, e: event::KeyCode) {
+    fn handle_replay_action(&mut self, action: keymap::Action) {
+        if let Some(replay) = &mut self.replay {
+            match action {
+                keymap::Action::ReplayNext => replay.next(),
+                keymap::Action::ReplayPrevious => replay.previous(),
+                keymap::Action::ReplayPlayPause => replay.toggle_playing(),
+                keymap::Action::ReplayFaster => replay.faster(),
+                keymap::Action::ReplaySlower => replay.slower(),
+                keymap::Action::ReplayNextEvent => replay.next_event(),
+                keymap::Action::ReplayPreviousEvent => replay.previous_event(),
+                keymap::Action::ReplayDeath => replay.death(),
+                keymap::Action::ReplayFirst => replay.first(),
+                keymap::Action::ReplayLast => replay.last(),
+                _ => return,
+            }
+        }
+        self.load_replay_frame();
+    }
+
+    fn handle_tab_action(&mut self, action: keymap::Action) {
+        match action {
+            keymap::Action::NextTab => self.switch_tab((self.active + 1) % self.tab_count()),
+            keymap::Action::PreviousTab => {
+                self.switch_tab((self.active + self.tab_count() - 1) % self.tab_count())
+            }
+            keymap::Action::CloseTab => self.close_tab(),
+            _ => (),
+        }
+    }
+
+    fn handle_action(&mut self, action: keymap::Action) {
+        if self.death.is_some() {
+            match action {
+                keymap::Action::NewCharacter => {
+                    self.death = None;
+                    self.session.connect();
+                }
+                keymap::Action::SaveReport => self.save_report(),
+                keymap::Action::NextTab
+                | keymap::Action::PreviousTab
+                | keymap::Action::CloseTab => self.handle_tab_action(action),
+                _ => (),
+            }
+            return;
+        }
+
+        if self.is_spectating() && !self.popup_manager.popup_mode {
+            match action {
+                keymap::Action::Help => self.display_keybinds(),
+                keymap::Action::Leaderboard => self.display_leaderboard(history::SortKey::Survived),
+                _ => self.handle_replay_action(action),
+            }
+            return;
+        }
+
         match self.popup_manager.popup_mode {
-            true => match e {
-                event::KeyCode::Enter => {
-                    self.popup_manager.popup_mode = false;
-                    if self.popup_manager.will_attack {
-                        match self.popup_manager.entities_list.get_selected_entity() {
-                            Some(id) => match self.session.get_entity_guid(id) {
-                                Some(guid) => {
-                                    self.session.attack(guid);
-                                    self.session.update();
-                                }
-                                None => (),
-                            },
-                            None => (),
-                        }
-                    } else if self.popup_manager.will_look {
-                        match self.popup_manager.entities_list.get_selected_entity() {
-                            Some(id) => match self.session.get_entity_guid(id) {
-                                Some(guid) => {
-                                    self.session.look_entity(guid);
-                                    self.session.update();
-                                }
-                                None => (),
-                            },
-                            None => (),
-                        }
-                    } else {
-                        self.session.clear_infos();
+            true => match action {
+                keymap::Action::Confirm => self.confirm_popup(),
+                keymap::Action::Leaderboard => {
+                    if let Some(sort) = self.popup_manager.leaderboard {
+                        self.display_leaderboard(sort.next());
                     }
-                    self.popup_manager.will_look = false;
-                    self.popup_manager.will_attack = false;
-                    self.popup_manager.entities_list.state.select(None);
                 }
-                event::KeyCode::Up => {
-                    if self.popup_manager.will_attack || self.popup_manager.will_look {
-                        self.popup_manager.entities_list.try_select_previous();
-                    }
+                keymap::Action::SelectPrevious
+                    if self.popup_manager.will_attack || self.popup_manager.will_look =>
+                {
+                    self.popup_manager.entities_list.try_select_previous();
                 }
-                event::KeyCode::Down => {
-                    if self.popup_manager.will_attack || self.popup_manager.will_look {
-                        self.popup_manager.entities_list.try_select_next();
-                    }
+                keymap::Action::SelectNext
+                    if self.popup_manager.will_attack || self.popup_manager.will_look =>
+                {
+                    self.popup_manager.entities_list.try_select_next();
+                }
+                keymap::Action::SelectPrevious => {
+                    self.popup_manager.scroll = self.popup_manager.scroll.saturating_sub(1)
+                }
+                // Capped to the popup's height on the next draw
+                keymap::Action::SelectNext => {
+                    self.popup_manager.scroll = self.popup_manager.scroll.saturating_add(1)
+                }
+                keymap::Action::QuickTarget(slot)
+                    if self.popup_manager.will_attack || self.popup_manager.will_look =>
+                {
+                    self.quick_target(slot);
                 }
                 _ => (),
             },
-            false => match e {
-                event::KeyCode::Char(c) => match c {
-                    'c' => self.session.connect(),
-                    'd' => self.session.disconnect(),
-                    'l' => self.session.update(),
-                    'h' => self.display_keybinds(),
-                    'a' => {
-                        self.popup_manager.popup_mode = true;
-                        self.popup_manager.title = String::from("Attack who");
-                        self.popup_manager.will_attack = true;
-                    }
-                    'e' => {
-                        self.popup_manager.popup_mode = true;
-                        self.popup_manager.title = String::from("Look who");
-                        self.popup_manager.will_look = true;
-                    }
-                    _ => (),
-                },
-                event::KeyCode::Up => {
-                    self.session.r#move(model::Direction::N);
-                    self.session.update();
+            false => match action {
+                keymap::Action::Connect => self.session.connect(),
+                keymap::Action::Disconnect => self.session.disconnect(),
+                keymap::Action::Look => self.session.update(),
+                keymap::Action::Help => self.display_keybinds(),
+                keymap::Action::Leaderboard => self.display_leaderboard(history::SortKey::Survived),
+                keymap::Action::NextTab
+                | keymap::Action::PreviousTab
+                | keymap::Action::CloseTab => self.handle_tab_action(action),
+                keymap::Action::NewTab => self.new_tab(),
+                keymap::Action::PartyMode => self.toggle_party(),
+                keymap::Action::Attack => {
+                    self.popup_manager.popup_mode = true;
+                    self.popup_manager.title = String::from("Attack who");
+                    self.popup_manager.will_attack = true;
                 }
-                event::KeyCode::Down => {
-                    self.session.r#move(model::Direction::S);
-                    self.session.update();
+                keymap::Action::Examine => {
+                    self.popup_manager.popup_mode = true;
+                    self.popup_manager.title = String::from("Look who");
+                    self.popup_manager.will_look = true;
                 }
-                event::KeyCode::Right => {
-                    self.session.r#move(model::Direction::E);
-                    self.session.update();
+                keymap::Action::MoveNorth => self.r#move(model::Direction::N),
+                keymap::Action::MoveSouth => self.r#move(model::Direction::S),
+                keymap::Action::MoveEast => self.r#move(model::Direction::E),
+                keymap::Action::MoveWest => self.r#move(model::Direction::W),
+                _ => (),
+            },
+        }
+    }
+
+    fn quick_target(&mut self, slot: u8) {
+        if self
+            .popup_manager
+            .entities_list
+            .try_select(slot as usize - 1)
+        {
+            self.confirm_popup();
+        }
+    }
+
+    fn confirm_popup(&mut self) {
+        self.popup_manager.popup_mode = false;
+        self.popup_manager.scroll = 0;
+        if self.popup_manager.will_attack {
+            if let Some(id) = self.popup_manager.entities_list.get_selected_entity() {
+                if let Some(guid) = self.session.get_entity_guid(id) {
+                    self.attack(guid);
                 }
-                event::KeyCode::Left => {
-                    self.session.r#move(model::Direction::W);
+            }
+        } else if self.popup_manager.will_look {
+            if let Some(id) = self.popup_manager.entities_list.get_selected_entity() {
+                if let Some(guid) = self.session.get_entity_guid(id) {
+                    self.session.look_entity(guid);
                     self.session.update();
                 }
-                _ => (),
-            },
+            }
+        } else {
+            self.session.clear_infos();
+        }
+        self.popup_manager.will_look = false;
+        self.popup_manager.will_attack = false;
+        self.popup_manager.leaderboard = None;
+        self.popup_manager.entities_list.state.select(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net;
+    use crate::sim;
+
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    static BASE_URL: &str = "http://offline";
+    // Set to rewrite the snapshots instead of comparing against them
+    static UPDATE_SNAPSHOTS: &str = "UPDATE_SNAPSHOTS";
+
+    thread_local! {
+        // Bells rung on this test's thread, which owns its runner
+        static BELLS: std::cell::Cell<u32> = const { std::cell::Cell::new(0) };
+    }
+
+    impl Bell for backend::TestBackend {
+        fn ring(&mut self) -> Result<(), io::Error> {
+            BELLS.with(|bells| bells.set(bells.get() + 1));
+            Ok(())
+        }
+    }
+
+    fn key(code: event::KeyCode) -> ChannelEvent<event::KeyEvent> {
+        ChannelEvent::Input(event::KeyEvent::new(code, event::KeyModifiers::NONE))
+    }
+
+    fn char(c: char) -> ChannelEvent<event::KeyEvent> {
+        key(event::KeyCode::Char(c))
+    }
+
+    fn session(dungeon: &str) -> session::Session {
+        let path = format!("{}/dungeons/{}", env!("CARGO_MANIFEST_DIR"), dungeon);
+        let world = sim::World::load(path.as_str(), 7).unwrap();
+
+        session::Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(sim::SimTransport::new(BASE_URL.to_string(), world)),
+        ))
+    }
+
+    // Plays the events to the end and returns the last frame, one line per row
+    fn render(dungeon: &str, events: Vec<ChannelEvent<event::KeyEvent>>) -> String {
+        let backend = backend::TestBackend::new(80, 24);
+        let keymap = keymap::Keymap::from_config(&HashMap::new()).unwrap();
+        let mut runner = Runner::with_backend(
+            backend,
+            events,
+            session(dungeon),
+            keymap,
+            theme::Theme::monochrome(),
+        )
+        .unwrap();
+        runner.set_alerts(config::AlertConfig {
+            enabled: false,
+            ..config::AlertConfig::default()
+        });
+        runner.set_history(config::HistoryConfig {
+            enabled: false,
+            ..config::HistoryConfig::default()
+        });
+        runner.run().unwrap();
+
+        let buffer = runner.terminal.backend().buffer();
+        let width = buffer.area.width as usize;
+        buffer
+            .content
+            .chunks(width)
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.symbol.as_str()).collect();
+                format!("{}\n", line.trim_end())
+            })
+            .collect()
+    }
+
+    fn assert_snapshot(name: &str, frame: String) {
+        let path = format!("{}/src/snapshots/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        if env::var_os(UPDATE_SNAPSHOTS).is_some() {
+            fs::write(&path, &frame).unwrap();
+            return;
         }
+
+        let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!("{} {}: {}, run with {}=1", name, path, e, UPDATE_SNAPSHOTS)
+        });
+        assert!(
+            expected == frame,
+            "{} differs from {}:\n{}",
+            name,
+            path,
+            frame
+        );
+    }
+
+    #[test]
+    fn disconnected() {
+        assert_snapshot("disconnected", render("dead_end.toml", vec![]));
+    }
+
+    #[test]
+    fn connected_canvas_and_status() {
+        let events = vec![char('c'), key(event::KeyCode::Right)];
+        assert_snapshot("connected", render("dead_end.toml", events));
+    }
+
+    #[test]
+    fn entity_list() {
+        let events = vec![char('c'), char('a'), key(event::KeyCode::Down)];
+        assert_snapshot("entity_list", render("two_players.toml", events));
+    }
+
+    #[test]
+    fn entity_popup() {
+        let events = vec![char('c'), char('e'), char('2')];
+        assert_snapshot("entity_popup", render("two_players.toml", events));
+    }
+
+    #[test]
+    fn fight_popup() {
+        let events = vec![char('c'), key(event::KeyCode::Right), char('a'), char('1')];
+        assert_snapshot("fight_popup", render("dead_end.toml", events));
+    }
+
+    #[test]
+    fn error_popup() {
+        let events = vec![char('c'), key(event::KeyCode::Left)];
+        assert_snapshot("error_popup", render("dead_end.toml", events));
+    }
+
+    // Opens a second tab, then gets the first one killed by the troll
+    fn die_in_first_tab() -> Vec<ChannelEvent<event::KeyEvent>> {
+        let mut events = vec![
+            char('c'),
+            char('t'),
+            key(event::KeyCode::Tab),
+            key(event::KeyCode::Right),
+        ];
+        for _ in 0..5 {
+            events.extend(vec![char('a'), char('1'), key(event::KeyCode::Enter)]);
+        }
+
+        events
+    }
+
+    #[test]
+    fn death_screen_is_kept_per_tab() {
+        let dead = render("dead_end.toml", die_in_first_tab());
+        assert!(dead.contains("Killed by"));
+        assert!(dead.contains("[Tab]"));
+
+        let mut events = die_in_first_tab();
+        events.push(key(event::KeyCode::Tab));
+        let other = render("dead_end.toml", events);
+        assert!(!other.contains("Killed by"));
+        assert!(other.contains("ROOM Entrance"));
+
+        let mut events = die_in_first_tab();
+        // crossterm reports Shift+Tab as BackTab with SHIFT set
+        events.extend(vec![
+            key(event::KeyCode::Tab),
+            ChannelEvent::Input(event::KeyEvent::new(
+                event::KeyCode::BackTab,
+                event::KeyModifiers::SHIFT,
+            )),
+        ]);
+        assert!(render("dead_end.toml", events).contains("Killed by"));
+    }
+
+    // Holds up every request of one character
+    struct SlowTransport {
+        inner: sim::SimTransport,
+        slow_guid: Arc<Mutex<Option<String>>>,
+        held: Mutex<mpsc::Sender<()>>,
+    }
+
+    static SLOW_DELAY: time::Duration = time::Duration::from_secs(2);
+
+    impl net::Transport for SlowTransport {
+        fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+            let slow = match self.slow_guid.lock().unwrap().as_ref() {
+                Some(guid) => request.url().contains(guid.as_str()),
+                None => false,
+            };
+            if slow {
+                let _ = self.held.lock().unwrap().send(());
+                thread::sleep(SLOW_DELAY);
+            }
+            self.inner.send(request)
+        }
+    }
+
+    #[test]
+    fn slow_background_refresh_does_not_block_the_active_tab() {
+        let path = format!("{}/dungeons/dead_end.toml", env!("CARGO_MANIFEST_DIR"));
+        let world = sim::World::load(path.as_str(), 7).unwrap();
+        let slow_guid = Arc::new(Mutex::new(None));
+        let (held, holding) = mpsc::channel();
+        let client = net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(SlowTransport {
+                inner: sim::SimTransport::new(BASE_URL.to_string(), world),
+                slow_guid: slow_guid.clone(),
+                held: Mutex::new(held),
+            }),
+        );
+        let keymap = keymap::Keymap::from_config(&HashMap::new()).unwrap();
+        let mut runner = Runner::with_backend(
+            backend::TestBackend::new(80, 24),
+            Vec::new(),
+            session::Session::with_client(client.clone()),
+            keymap,
+            theme::Theme::monochrome(),
+        )
+        .unwrap();
+        runner.session.connect();
+        let mut background = session::Session::with_client(client);
+        background.connect();
+        *slow_guid.lock().unwrap() = background.get_guid().ok();
+        runner.add_session(background);
+
+        runner.update_background();
+        // The refresh is in the middle of its request
+        holding.recv().unwrap();
+        let started = time::Instant::now();
+        runner.handle_action(keymap::Action::MoveEast);
+        assert!(started.elapsed() < SLOW_DELAY / 2);
+        assert_eq!(
+            runner.session.status.as_ref().unwrap().room.description,
+            "Dead end"
+        );
+        assert!(runner.background[0].refreshing.is_some());
+    }
+
+    #[test]
+    fn help_popup() {
+        assert_snapshot("help_popup", render("dead_end.toml", vec![char('h')]));
+    }
+
+    fn scroll_help(downs: usize, ups: usize) -> Vec<ChannelEvent<event::KeyEvent>> {
+        let mut events = vec![char('h')];
+        events.extend((0..downs).map(|_| key(event::KeyCode::Down)));
+        events.extend((0..ups).map(|_| key(event::KeyCode::Up)));
+
+        events
+    }
+
+    #[test]
+    fn help_popup_scrolls_to_the_end() {
+        let end = render("dead_end.toml", scroll_help(30, 0));
+        assert_snapshot("help_popup_end", end.clone());
+
+        // Scrolling past the end is capped, so one step up moves right away
+        assert_ne!(render("dead_end.toml", scroll_help(30, 1)), end);
+        assert_eq!(
+            render("dead_end.toml", scroll_help(30, 30)),
+            render("dead_end.toml", scroll_help(0, 0))
+        );
+    }
+
+    #[test]
+    fn alerts_ring_the_bell_through_the_backend() {
+        let keymap = keymap::Keymap::from_config(&HashMap::new()).unwrap();
+        let mut runner = Runner::with_backend(
+            backend::TestBackend::new(80, 24),
+            Vec::new(),
+            session("dead_end.toml"),
+            keymap,
+            theme::Theme::monochrome(),
+        )
+        .unwrap();
+        runner.set_alerts(config::AlertConfig {
+            bell: true,
+            ..config::AlertConfig::default()
+        });
+
+        runner
+            .alerts
+            .pending
+            .push(String::from("Someone entered the room"));
+        runner.collect_alerts();
+        assert_eq!(BELLS.with(|bells| bells.get()), 1);
+
+        // Within the minimum interval the next alert waits, and so does its bell
+        runner
+            .alerts
+            .pending
+            .push(String::from("Someone left the room"));
+        runner.collect_alerts();
+        assert_eq!(BELLS.with(|bells| bells.get()), 1);
     }
 }
@@ -0,0 +1,93 @@
+use crossterm::cursor;
+use crossterm::execute;
+use crossterm::terminal;
+
+use std::io;
+use std::panic;
+use std::sync::atomic;
+use std::sync::Mutex;
+use std::sync::Once;
+use std::thread;
+
+static PANIC_HOOK: Once = Once::new();
+static SCREEN_ACTIVE: atomic::AtomicBool = atomic::AtomicBool::new(false);
+// The thread holding the ScreenGuard, the only one whose panic ends the program
+static SCREEN_OWNER: Mutex<Option<thread::ThreadId>> = Mutex::new(None);
+
+pub struct ScreenGuard;
+
+impl ScreenGuard {
+    pub fn enter() -> Result<ScreenGuard, io::Error> {
+        ScreenGuard::install_panic_hook();
+
+        let guard = ScreenGuard;
+        set_owner(Some(thread::current().id()));
+        enter_screen()?;
+
+        Ok(guard)
+    }
+
+    fn install_panic_hook() {
+        PANIC_HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                log::error!(target: "panic", "{}", info);
+                if is_owner(thread::current().id()) {
+                    let _ = restore_screen();
+                }
+                default_hook(info);
+            }));
+        });
+    }
+
+    #[cfg(unix)]
+    pub fn suspend(&self) -> Result<(), io::Error> {
+        restore_screen()?;
+        signal_hook::low_level::emulate_default_handler(signal_hook::consts::SIGTSTP)?;
+        enter_screen()
+    }
+
+    #[cfg(not(unix))]
+    pub fn suspend(&self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+impl Drop for ScreenGuard {
+    fn drop(&mut self) {
+        let _ = restore_screen();
+        set_owner(None);
+    }
+}
+
+fn set_owner(owner: Option<thread::ThreadId>) {
+    if let Ok(mut current) = SCREEN_OWNER.lock() {
+        *current = owner;
+    }
+}
+
+fn is_owner(id: thread::ThreadId) -> bool {
+    SCREEN_OWNER
+        .lock()
+        .map(|owner| *owner == Some(id))
+        .unwrap_or(false)
+}
+
+fn enter_screen() -> Result<(), io::Error> {
+    SCREEN_ACTIVE.store(true, atomic::Ordering::SeqCst);
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    Ok(())
+}
+
+pub fn restore_screen() -> Result<(), io::Error> {
+    if !SCREEN_ACTIVE.swap(false, atomic::Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    terminal::disable_raw_mode()?;
+    execute!(io::stdout(), terminal::LeaveAlternateScreen, cursor::Show)?;
+
+    Ok(())
+}
@@ -0,0 +1,351 @@
+use crate::bot;
+use crate::config;
+use crate::model;
+
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::FuncArgs;
+use rhai::Scope;
+use rhai::AST;
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time;
+
+static ERROR_SCRIPT_READ: &str = "Could not read script";
+static ERROR_SCRIPT_COMPILE: &str = "Could not compile script";
+static ERROR_SCRIPT_RUN: &str = "Could not run script";
+static ERROR_SCRIPT_TIMEOUT: &str = "script call timed out";
+
+static MAX_CALL_LEVELS: usize = 32;
+static MAX_EXPR_DEPTH: usize = 64;
+static MAX_STRING_SIZE: usize = 64 * 1024;
+static MAX_COLLECTION_SIZE: usize = 10_000;
+static MAX_QUEUED_ACTIONS: usize = 16;
+
+type ActionQueue = Arc<Mutex<Vec<bot::Action>>>;
+
+fn queue(queued: &ActionQueue, action: bot::Action) {
+    if let Ok(mut queued) = queued.lock() {
+        if queued.len() < MAX_QUEUED_ACTIONS {
+            queued.push(action);
+        }
+    }
+}
+
+fn parse_direction(direction: &str) -> Option<model::Direction> {
+    match direction.to_uppercase().as_str() {
+        "N" | "NORTH" => Some(model::Direction::N),
+        "S" | "SOUTH" => Some(model::Direction::S),
+        "E" | "EAST" => Some(model::Direction::E),
+        "W" | "WEST" => Some(model::Direction::W),
+        _ => None,
+    }
+}
+
+/// Rhai scripts reacting to session changes through `on_room_enter(room)`,
+/// `on_entity_seen(entity)`, `on_fight(fight)`, `on_low_hp(status)` and
+/// `on_tick()`, and acting with `move`, `attack`, `examine` and `log`.
+///
+/// `on_entity_seen` gets `guid`, `number` and the examined `entity`, which is
+/// `()` until examined; it is called again once when the entity gets examined.
+pub struct ScriptHooks {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    queued: ActionQueue,
+    deadline: Arc<Mutex<time::Instant>>,
+    call_timeout: time::Duration,
+    low_hp_ratio: f64,
+    // Rooms entered when on_room_enter was last called
+    room: Option<u64>,
+    // Whether each entity of the room was already reported with its examined data
+    seen: HashMap<String, bool>,
+    fight: Option<String>,
+    low_hp: bool,
+    tick: Option<u64>,
+}
+
+impl ScriptHooks {
+    pub fn load(path: &str, script_config: &config::ScriptConfig) -> Result<ScriptHooks, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{} {}: {}", ERROR_SCRIPT_READ, path, e))?;
+
+        ScriptHooks::compile(text.as_str(), path, script_config)
+    }
+
+    // `path` only names the script in errors and logs
+    fn compile(
+        text: &str,
+        path: &str,
+        script_config: &config::ScriptConfig,
+    ) -> Result<ScriptHooks, String> {
+        let queued: ActionQueue = Arc::new(Mutex::new(Vec::new()));
+        let call_timeout = time::Duration::from_millis(script_config.call_timeout_ms);
+        let deadline = Arc::new(Mutex::new(time::Instant::now() + call_timeout));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(script_config.max_operations);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_COLLECTION_SIZE);
+        engine.set_max_map_size(MAX_COLLECTION_SIZE);
+
+        let progress_deadline = deadline.clone();
+        engine.on_progress(move |_| {
+            let expired = progress_deadline
+                .lock()
+                .map(|deadline| time::Instant::now() > *deadline)
+                .unwrap_or(true);
+            if expired {
+                Some(Dynamic::from(ERROR_SCRIPT_TIMEOUT))
+            } else {
+                None
+            }
+        });
+        engine.on_print(|text| log::info!(target: "script", "{}", text));
+        engine.on_debug(|text, _, _| log::debug!(target: "script", "{}", text));
+
+        let actions = queued.clone();
+        engine.register_fn("log", |text: &str| log::info!(target: "script", "{}", text));
+        engine.register_fn("move", move |direction: &str| {
+            match parse_direction(direction) {
+                Some(direction) => queue(&actions, bot::Action::Move(direction)),
+                None => log::warn!(target: "script", "invalid_direction direction={}", direction),
+            }
+        });
+        let actions = queued.clone();
+        engine.register_fn("attack", move |target: &str| {
+            queue(&actions, bot::Action::Attack(target.to_string()))
+        });
+        let actions = queued.clone();
+        engine.register_fn("attack", move |target: i64| {
+            queue(&actions, bot::Action::Attack(target.to_string()))
+        });
+        let actions = queued.clone();
+        engine.register_fn("examine", move |target: &str| {
+            queue(&actions, bot::Action::Examine(target.to_string()))
+        });
+        let actions = queued.clone();
+        engine.register_fn("examine", move |target: i64| {
+            queue(&actions, bot::Action::Examine(target.to_string()))
+        });
+
+        let ast = engine
+            .compile(text)
+            .map_err(|e| format!("{} {}: {}", ERROR_SCRIPT_COMPILE, path, e))?;
+
+        let mut hooks = ScriptHooks {
+            engine,
+            ast,
+            scope: Scope::new(),
+            queued,
+            deadline,
+            call_timeout,
+            low_hp_ratio: script_config.low_hp_ratio,
+            room: None,
+            seen: HashMap::new(),
+            fight: None,
+            low_hp: false,
+            tick: None,
+        };
+
+        hooks.arm_deadline();
+        hooks
+            .engine
+            .run_ast_with_scope(&mut hooks.scope, &hooks.ast)
+            .map_err(|e| format!("{} {}: {}", ERROR_SCRIPT_RUN, path, e))?;
+        log::info!(target: "script", "loaded path={}", path);
+
+        Ok(hooks)
+    }
+
+    fn arm_deadline(&mut self) {
+        if let Ok(mut deadline) = self.deadline.lock() {
+            *deadline = time::Instant::now() + self.call_timeout;
+        }
+    }
+
+    fn has_callback(&self, name: &str, arity: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == arity)
+    }
+
+    fn call(&mut self, name: &str, args: impl FuncArgs) {
+        self.arm_deadline();
+        if let Err(error) = self
+            .engine
+            .call_fn::<Dynamic>(&mut self.scope, &self.ast, name, args)
+        {
+            log::warn!(target: "script", "callback_failed name={} error=\"{}\"", name, error);
+        }
+    }
+
+    fn call_with<T: serde::Serialize>(&mut self, name: &str, value: &T) {
+        if !self.has_callback(name, 1) {
+            return;
+        }
+        match rhai::serde::to_dynamic(value) {
+            Ok(value) => self.call(name, (value,)),
+            Err(error) => {
+                log::warn!(target: "script", "encode_failed name={} error=\"{}\"", name, error)
+            }
+        }
+    }
+}
+
+impl bot::Hooks for ScriptHooks {
+    fn react(&mut self, observation: &bot::Observation) -> Vec<bot::Action> {
+        match (&observation.status, &observation.room) {
+            (Some(status), Some(room)) => {
+                if self.room != Some(observation.rooms_entered) {
+                    self.room = Some(observation.rooms_entered);
+                    self.seen.clear();
+                    self.call_with("on_room_enter", room);
+                }
+
+                for (index, guid) in observation.others().iter().enumerate() {
+                    let entity = observation.examined.get(guid);
+                    let reported = self.seen.get(guid).copied();
+                    if reported.is_none() || (reported == Some(false) && entity.is_some()) {
+                        self.seen.insert(guid.clone(), entity.is_some());
+                        self.call_with(
+                            "on_entity_seen",
+                            &serde_json::json!({ "guid": guid, "number": index + 1, "entity": entity }),
+                        );
+                    }
+                }
+
+                let fight = observation
+                    .fight
+                    .as_ref()
+                    .and_then(|fight| serde_json::to_string(fight).ok());
+                if fight.is_some() && fight != self.fight {
+                    if let Some(fight) = &observation.fight {
+                        self.call_with("on_fight", fight);
+                    }
+                }
+                self.fight = fight;
+
+                let low_hp = observation.life_ratio() < self.low_hp_ratio;
+                if low_hp && !self.low_hp {
+                    self.call_with("on_low_hp", status);
+                }
+                self.low_hp = low_hp;
+            }
+            _ => {
+                self.room = None;
+                self.seen.clear();
+                self.low_hp = false;
+            }
+        }
+
+        // Hooks also run after every action, but on_tick only once per step
+        if self.tick != Some(observation.step) && self.has_callback("on_tick", 0) {
+            self.call("on_tick", ());
+        }
+        self.tick = Some(observation.step);
+
+        match self.queued.lock() {
+            Ok(mut queued) => queued.drain(..).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bot::Hooks;
+
+    fn hooks(text: &str, script_config: config::ScriptConfig) -> ScriptHooks {
+        ScriptHooks::compile(text, "test.rhai", &script_config).unwrap()
+    }
+
+    fn tick(hooks: &mut ScriptHooks, step: u64) -> Vec<bot::Action> {
+        hooks.react(&bot::Observation {
+            step,
+            ..bot::Observation::default()
+        })
+    }
+
+    #[test]
+    fn endless_callbacks_are_cut_off_by_the_time_limit() {
+        let mut hooks = hooks(
+            "fn on_tick() { move(\"n\"); loop {} }",
+            config::ScriptConfig {
+                call_timeout_ms: 50,
+                max_operations: 0,
+                ..config::ScriptConfig::default()
+            },
+        );
+
+        let started = time::Instant::now();
+        // Actions queued before the cut off still count
+        assert_eq!(
+            tick(&mut hooks, 0),
+            vec![bot::Action::Move(model::Direction::N)]
+        );
+        assert_eq!(tick(&mut hooks, 1).len(), 1);
+        assert!(started.elapsed() < time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn endless_callbacks_are_cut_off_by_max_operations() {
+        let mut hooks = hooks(
+            "fn on_tick() { loop {} }",
+            config::ScriptConfig {
+                call_timeout_ms: 60_000,
+                max_operations: 10_000,
+                ..config::ScriptConfig::default()
+            },
+        );
+
+        let started = time::Instant::now();
+        assert!(tick(&mut hooks, 0).is_empty());
+        assert!(started.elapsed() < time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn actions_are_queued_by_name() {
+        let mut hooks = hooks(
+            r#"
+            fn on_tick() {
+                move("north");
+                move("W");
+                move("up");
+                attack(2);
+                examine("abc");
+            }
+            "#,
+            config::ScriptConfig::default(),
+        );
+
+        assert_eq!(
+            tick(&mut hooks, 0),
+            vec![
+                bot::Action::Move(model::Direction::N),
+                bot::Action::Move(model::Direction::W),
+                bot::Action::Attack(String::from("2")),
+                bot::Action::Examine(String::from("abc")),
+            ]
+        );
+        // on_tick runs once per step
+        assert!(tick(&mut hooks, 0).is_empty());
+    }
+
+    #[test]
+    fn queued_actions_are_capped() {
+        let mut hooks = hooks(
+            "fn on_tick() { for i in 0..100 { move(\"n\"); } }",
+            config::ScriptConfig::default(),
+        );
+
+        assert_eq!(tick(&mut hooks, 0).len(), MAX_QUEUED_ACTIONS);
+        assert_eq!(tick(&mut hooks, 1).len(), MAX_QUEUED_ACTIONS);
+    }
+}
@@ -1,13 +1,98 @@
+//! Player state on top of [`MunHttpClient`](crate::net::MunHttpClient).
+
+use crate::history;
+use crate::logging;
 use crate::model;
 use crate::net;
 
+use serde::{Deserialize, Serialize};
+
+use std::cmp;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::path;
 
-static ERROR_STATUS_UNINITALIZED: &'static str =
+static ERROR_STATUS_UNINITALIZED: &str =
     "Error while accessing player status, status is uninitialized";
 
+static MAX_PENDING_EVENTS: usize = 64;
+static UNDER_ATTACK_QUIET_REFRESHES: u32 = 3;
+
+/// Entities of the current room other than the player, numbered from 1.
 pub type EntityMap = HashMap<u32, String>;
 
+/// A room's place relative to the room the character connected in, x growing east
+/// and y north.
+pub type Position = (i64, i64);
+
+fn offset(position: Position, direction: &model::Direction) -> Position {
+    let (x, y) = position;
+    match direction {
+        model::Direction::N => (x, y + 1),
+        model::Direction::S => (x, y - 1),
+        model::Direction::E => (x + 1, y),
+        model::Direction::W => (x - 1, y),
+    }
+}
+
+/// A player action, as sent by external tools (`{"command": "move", "direction": "N"}`).
+///
+/// Targets are an [`EntityMap`] number or a raw guid.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Connect,
+    Disconnect,
+    Look,
+    Move { direction: model::Direction },
+    Examine { target: String },
+    Attack { target: String },
+}
+
+/// A change noticed between two refreshes, see [`Session::take_events`].
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// An entity appeared in the room while we stayed in it.
+    Entered { guid: String },
+    /// An entity disappeared from the room while we stayed in it.
+    Left { guid: String },
+    /// Our life went down by more than our own fights explain.
+    Damaged { amount: u32, life: u32 },
+}
+
+/// Set while our life keeps dropping without us attacking.
+///
+/// `suspects` are the other entities of the room, narrowed down to those present
+/// at every hit, latest arrivals first. The state ends after a few refreshes
+/// without losses.
+#[derive(Serialize, Clone, Debug)]
+pub struct UnderAttack {
+    pub suspects: Vec<String>,
+    pub last_loss: u32,
+    pub total_loss: u32,
+    pub hits: u32,
+    #[serde(skip)]
+    quiet_refreshes: u32,
+}
+
+/// Serializable view of a [`Session`].
+#[derive(Serialize, Clone, Debug)]
+pub struct Snapshot {
+    pub base_url: String,
+    pub connected: bool,
+    pub status: Option<model::Status>,
+    pub entities: BTreeMap<u32, String>,
+    pub fight: Option<model::Fight>,
+    pub entity: Option<model::Entity>,
+    pub error: Option<model::Error>,
+    pub under_attack: Option<UnderAttack>,
+}
+
+/// State of one player.
+///
+/// Actions never fail: errors are stored in `error`, and the last fight or
+/// examined entity in `fight_info` and `entity_info`, until [`Session::clear_infos`].
 #[derive(Clone, Debug)]
 pub struct Session {
     pub status: Option<model::Status>,
@@ -16,18 +101,69 @@ pub struct Session {
     pub fight_info: Option<model::Fight>,
     pub entity_info: Option<model::Entity>,
     pub entity_map: EntityMap,
+    /// Entities of the current room examined so far, by guid.
+    pub examined: HashMap<String, model::Entity>,
+    pub events: Vec<SessionEvent>,
+    pub under_attack: Option<UnderAttack>,
+    /// Where the current room is, counted from successful moves: the server gives
+    /// rooms no identity, and different rooms may share a description.
+    pub position: Position,
+    /// Rooms entered so far, including the one each character connected in.
+    pub rooms_entered: u64,
+    /// Set on connect, taken by [`Session::end_run`].
+    pub stats: Option<history::RunStats>,
+    /// Where [`Session::close_run`] appends finished runs, if anywhere.
+    pub history_path: Option<path::PathBuf>,
+    // Damage taken in our own fights since the last look_self
+    fight_damage: u32,
+    // Entities that entered the current room while we were in it, oldest first
+    arrivals: Vec<String>,
+}
+
+// Entity numbers are kept as is, guids go through redaction
+fn log_target(target: &str) -> String {
+    match target.parse::<u32>() {
+        Ok(_) => target.to_string(),
+        Err(_) => logging::guid(target),
+    }
 }
 
 impl Session {
     pub fn new(url: String) -> Session {
+        Session::with_client(net::MunHttpClient::new(url))
+    }
+
+    pub fn with_client(client: net::MunHttpClient) -> Session {
         Session {
             status: None,
-            client: net::MunHttpClient::new(url),
+            client,
             error: None,
             fight_info: None,
             entity_info: None,
             entity_map: EntityMap::new(),
+            examined: HashMap::new(),
+            events: Vec::new(),
+            under_attack: None,
+            position: (0, 0),
+            rooms_entered: 0,
+            stats: None,
+            history_path: None,
+            fight_damage: 0,
+            arrivals: Vec::new(),
+        }
+    }
+
+    fn push_event(&mut self, event: SessionEvent) {
+        log::info!(target: "session", "event={:?}", event);
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            self.events.remove(0);
         }
+        self.events.push(event);
+    }
+
+    /// Drains the events noticed since the last call.
+    pub fn take_events(&mut self) -> Vec<SessionEvent> {
+        std::mem::take(&mut self.events)
     }
 
     pub fn clear_entities(&mut self) {
@@ -37,20 +173,17 @@ impl Session {
     pub fn update_entity_map(&mut self) {
         self.clear_entities();
 
-        match &self.status {
-            Some(status) => {
-                let mut cpt: u32 = 1;
+        if let Some(status) = &self.status {
+            let mut cpt: u32 = 1;
 
-                for entity in status.room.entities.iter() {
-                    let guid = entity.clone();
+            for entity in status.room.entities.iter() {
+                let guid = entity.clone();
 
-                    if guid != status.guid {
-                        self.entity_map.insert(cpt, guid);
-                        cpt += 1;
-                    }
+                if guid != status.guid {
+                    self.entity_map.insert(cpt, guid);
+                    cpt += 1;
                 }
             }
-            None => (),
         }
     }
 
@@ -64,35 +197,111 @@ impl Session {
         None
     }
 
+    /// Numbers of the entities in the room, in increasing order.
     pub fn get_entities_keys(&self) -> Vec<u32> {
         let mut keys: Vec<u32> = Vec::new();
 
         for key in self.entity_map.keys() {
-            keys.push(key.clone());
+            keys.push(*key);
         }
+        keys.sort_unstable();
 
         keys
     }
 
+    /// Closes the current run as a disconnect, then forgets the character.
     pub fn disconnect(&mut self) {
+        self.close_run(history::EndCause::Disconnect);
+        if let Some(status) = &self.status {
+            log::info!(
+                target: "session",
+                "state=disconnected guid={}",
+                logging::guid(status.guid.as_str())
+            );
+        }
         self.status = None;
         self.clear();
     }
 
+    pub fn set_error(&mut self, error: model::Error) {
+        log::warn!(
+            target: "session",
+            "error code={} type={} message=\"{}\"",
+            error
+                .code
+                .map(|code| code.to_string())
+                .unwrap_or_else(|| String::from("none")),
+            error
+                .detail
+                .r#type
+                .as_ref()
+                .map(|r#type| r#type.to_string())
+                .unwrap_or_else(|| String::from("none")),
+            error.detail.message
+        );
+        self.error = Some(error);
+    }
+
     pub fn is_connected(&self) -> bool {
-        match self.status {
-            Some(_) => true,
-            None => false,
-        }
+        self.status.is_some()
     }
 
+    /// Takes a fresh look at the current room: entities that came or went are events.
     pub fn update_room(&mut self, room: model::Room) {
-        match &mut self.status {
-            Some(status) => {
-                status.room = room;
-                self.update_entity_map();
+        let mut events: Vec<SessionEvent> = Vec::new();
+
+        if let Some(status) = &mut self.status {
+            let others = |entities: &Vec<String>, other: &Vec<String>| -> Vec<String> {
+                entities
+                    .iter()
+                    .filter(|guid| **guid != status.guid && !other.contains(guid))
+                    .cloned()
+                    .collect()
+            };
+            events.extend(
+                others(&room.entities, &status.room.entities)
+                    .into_iter()
+                    .map(|guid| SessionEvent::Entered { guid }),
+            );
+            events.extend(
+                others(&status.room.entities, &room.entities)
+                    .into_iter()
+                    .map(|guid| SessionEvent::Left { guid }),
+            );
+            status.room = room;
+            self.update_entity_map();
+        }
+
+        for event in events {
+            match &event {
+                SessionEvent::Entered { guid } => self.arrivals.push(guid.clone()),
+                SessionEvent::Left { guid } => self.arrivals.retain(|arrival| arrival != guid),
+                SessionEvent::Damaged { .. } => (),
             }
-            _ => (),
+            self.push_event(event);
+        }
+    }
+
+    // After a successful move, whatever the new room looks like
+    fn enter_room(&mut self, direction: &model::Direction, room: model::Room) {
+        if let Some(status) = &mut self.status {
+            self.position = offset(self.position, direction);
+            self.rooms_entered += 1;
+            log::info!(
+                target: "session",
+                "state=room_changed room=\"{}\" position={:?} paths={:?} entities={}",
+                room.description,
+                self.position,
+                room.paths,
+                room.entities.len()
+            );
+            self.arrivals.clear();
+            self.examined.clear();
+            if let Some(stats) = &mut self.stats {
+                stats.rooms.insert(self.position);
+            }
+            status.room = room;
+            self.update_entity_map();
         }
     }
 
@@ -117,6 +326,10 @@ impl Session {
 
     fn clear(&mut self) {
         self.status = None;
+        self.fight_damage = 0;
+        self.under_attack = None;
+        self.arrivals.clear();
+        self.examined.clear();
 
         self.clear_entities();
         self.clear_infos();
@@ -125,10 +338,25 @@ impl Session {
     pub fn connect(&mut self) {
         match self.client.connect() {
             Ok(status) => {
+                log::info!(
+                    target: "session",
+                    "state=connected guid={} total_life={} room=\"{}\"",
+                    logging::guid(status.guid.as_str()),
+                    status.total_life,
+                    status.room.description
+                );
+                // Reconnecting starts a new character, so the previous run is over
+                self.close_run(history::EndCause::Disconnect);
+                self.position = (0, 0);
+                self.rooms_entered += 1;
+                let mut stats = history::RunStats::new();
+                stats.rooms.insert(self.position);
+                self.stats = Some(stats);
                 self.status = Some(status);
+                self.examined.clear();
                 self.update_entity_map();
             }
-            Err(error) => self.error = Some(error),
+            Err(error) => self.set_error(error),
         }
     }
 
@@ -138,48 +366,163 @@ impl Session {
                 Ok(room) => {
                     self.update_room(room);
                 }
-                Err(error) => self.error = Some(error),
+                Err(error) => self.set_error(error),
             },
-            Err(error) => self.error = Some(error),
+            Err(error) => self.set_error(error),
         }
     }
 
     pub fn r#move(&mut self, direction: model::Direction) {
         match self.get_guid() {
-            Ok(guid) => match self.client.r#move(guid, direction) {
-                Ok(room) => self.update_room(room),
-                Err(error) => self.error = Some(error),
+            Ok(guid) => match self.client.r#move(guid, direction.clone()) {
+                Ok(room) => self.enter_room(&direction, room),
+                Err(error) => self.set_error(error),
             },
-            Err(error) => self.error = Some(error),
+            Err(error) => self.set_error(error),
         }
     }
 
     pub fn look_entity(&mut self, guid_dest: String) {
         match self.get_guid() {
-            Ok(guid) => match self.client.look_entity(guid, guid_dest) {
-                Ok(entity) => self.entity_info = Some(entity),
-                Err(error) => self.error = Some(error),
+            Ok(guid) => match self.client.look_entity(guid, guid_dest.clone()) {
+                Ok(entity) => {
+                    if let Some(stats) = &mut self.stats {
+                        stats
+                            .descriptions
+                            .insert(guid_dest.clone(), entity.description.clone());
+                    }
+                    self.examined.insert(guid_dest, entity.clone());
+                    self.entity_info = Some(entity);
+                }
+                Err(error) => self.set_error(error),
             },
-            Err(error) => self.error = Some(error),
+            Err(error) => self.set_error(error),
         }
     }
 
     pub fn look_self(&mut self) {
         match self.get_guid() {
             Ok(guid) => match self.client.look_entity(guid.clone(), guid) {
-                Ok(entity) => match &mut self.status {
-                    Some(status) => {
+                Ok(entity) => {
+                    let fight_damage = std::mem::take(&mut self.fight_damage);
+                    let mut damaged: Option<SessionEvent> = None;
+                    if let Some(status) = &mut self.status {
+                        // Only the part of the loss our own fights don't explain
+                        let amount = status
+                            .life
+                            .map_or(0, |life| life.saturating_sub(entity.life))
+                            .saturating_sub(fight_damage);
+                        if amount > 0 {
+                            damaged = Some(SessionEvent::Damaged {
+                                amount,
+                                life: entity.life,
+                            });
+                        }
+                        if status.life != Some(entity.life) {
+                            log::info!(
+                                target: "session",
+                                "state=life_changed from={} to={} total={}",
+                                status
+                                    .life
+                                    .map(|life| life.to_string())
+                                    .unwrap_or_else(|| String::from("none")),
+                                entity.life,
+                                entity.total_life
+                            );
+                        }
                         status.life = Some(entity.life);
                         status.total_life = entity.total_life;
                     }
-                    None => (),
-                },
-                Err(error) => self.error = Some(error),
+                    match damaged {
+                        Some(SessionEvent::Damaged { amount, .. }) => self.hit(amount),
+                        _ => self.quiet(),
+                    }
+                    if let Some(damaged) = damaged {
+                        self.push_event(damaged);
+                    }
+                }
+                Err(error) => self.set_error(error),
+            },
+            Err(error) => self.set_error(error),
+        }
+    }
+
+    fn others(&self) -> Vec<String> {
+        match &self.status {
+            Some(status) => status
+                .room
+                .entities
+                .iter()
+                .filter(|guid| **guid != status.guid)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn hit(&mut self, amount: u32) {
+        let mut others = self.others();
+        others.sort_by_key(|guid| {
+            cmp::Reverse(self.arrivals.iter().rposition(|arrival| arrival == guid))
+        });
+        let under_attack = match self.under_attack.take() {
+            Some(previous) => {
+                let narrowed: Vec<String> = previous
+                    .suspects
+                    .into_iter()
+                    .filter(|guid| others.contains(guid))
+                    .collect();
+                UnderAttack {
+                    suspects: if narrowed.is_empty() {
+                        others
+                    } else {
+                        narrowed
+                    },
+                    last_loss: amount,
+                    total_loss: previous.total_loss + amount,
+                    hits: previous.hits + 1,
+                    quiet_refreshes: 0,
+                }
+            }
+            None => UnderAttack {
+                suspects: others,
+                last_loss: amount,
+                total_loss: amount,
+                hits: 1,
+                quiet_refreshes: 0,
             },
-            Err(error) => self.error = Some(error),
+        };
+        log::info!(
+            target: "session",
+            "state=under_attack loss={} hits={} suspects={}",
+            under_attack.last_loss,
+            under_attack.hits,
+            under_attack.suspects.len()
+        );
+        if let Some(stats) = &mut self.stats {
+            stats.damage_taken += amount;
+            if let Some(suspect) = under_attack.suspects.first() {
+                stats.last_opponent = Some(suspect.clone());
+            }
+        }
+        self.under_attack = Some(under_attack);
+    }
+
+    fn quiet(&mut self) {
+        if let Some(under_attack) = &mut self.under_attack {
+            under_attack.quiet_refreshes += 1;
+            if under_attack.quiet_refreshes >= UNDER_ATTACK_QUIET_REFRESHES {
+                log::info!(
+                    target: "session",
+                    "state=safe total_loss={}",
+                    under_attack.total_loss
+                );
+                self.under_attack = None;
+            }
         }
     }
 
+    /// Refreshes the room and the player's life.
     pub fn update(&mut self) {
         self.look_room();
         self.look_self();
@@ -188,10 +531,230 @@ impl Session {
     pub fn attack(&mut self, guid_dest: String) {
         match self.get_guid() {
             Ok(guid) => match self.client.attack(guid, guid_dest) {
-                Ok(fight) => self.fight_info = Some(fight),
-                Err(error) => self.error = Some(error),
+                Ok(fight) => {
+                    self.fight_damage += fight.defender.damage;
+                    log::info!(
+                        target: "session",
+                        "fight target={} dealt={} taken={} life={} target_life={}",
+                        logging::guid(fight.defender.guid.as_str()),
+                        fight.attacker.damage,
+                        fight.defender.damage,
+                        fight.attacker.life,
+                        fight.defender.life
+                    );
+                    if let Some(stats) = &mut self.stats {
+                        stats.damage_dealt += fight.attacker.damage;
+                        stats.damage_taken += fight.defender.damage;
+                        if fight.defender.life == 0 {
+                            stats.kills += 1;
+                        }
+                        stats.last_opponent = Some(fight.defender.guid.clone());
+                    }
+                    if let Some(entity) = self.examined.get_mut(&fight.defender.guid) {
+                        entity.life = fight.defender.life;
+                    }
+                    self.fight_info = Some(fight);
+                }
+                Err(error) => self.set_error(error),
             },
-            Err(error) => self.error = Some(error),
+            Err(error) => self.set_error(error),
+        }
+    }
+
+    /// Closes the current run, if any, into a record for the history file.
+    ///
+    /// A run without a character to attribute it to is dropped.
+    pub fn end_run(&mut self, cause: history::EndCause) -> Option<history::RunRecord> {
+        let stats = self.stats.take()?;
+        let status = match &self.status {
+            Some(status) if !status.guid.is_empty() => status,
+            _ => {
+                log::warn!(target: "session", "run_dropped cause={:?} reason=no_guid", cause);
+                return None;
+            }
+        };
+        let record = history::RunRecord {
+            guid: status.guid.clone(),
+            base_url: self.client.base_url.clone(),
+            started_at: stats.started_at,
+            ended_at: history::now(),
+            cause,
+            rooms_explored: stats.rooms.len(),
+            kills: stats.kills,
+            damage_dealt: stats.damage_dealt,
+            damage_taken: stats.damage_taken,
+            last_room: Some(status.room.description.clone()),
+            last_opponent: stats.last_opponent.as_ref().map(|guid| {
+                stats
+                    .descriptions
+                    .get(guid)
+                    .cloned()
+                    .unwrap_or_else(|| guid.clone())
+            }),
+        };
+        log::info!(
+            target: "session",
+            "run_ended cause={:?} survived={} kills={}",
+            record.cause,
+            record.survived(),
+            record.kills
+        );
+
+        Some(record)
+    }
+
+    /// Like [`Session::end_run`], also appending the record to `history_path`.
+    pub fn close_run(&mut self, cause: history::EndCause) -> Option<history::RunRecord> {
+        let record = self.end_run(cause)?;
+
+        if let Some(history_path) = &self.history_path {
+            if let Err(error) = history::append(history_path, &record) {
+                log::warn!(target: "session", "error=\"{}\"", error);
+            }
+        }
+
+        Some(record)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            base_url: self.client.base_url.clone(),
+            connected: self.is_connected(),
+            status: self.status.clone(),
+            entities: self.entity_map.clone().into_iter().collect(),
+            fight: self.fight_info.clone(),
+            entity: self.entity_info.clone(),
+            error: self.error.clone(),
+            under_attack: self.under_attack.clone(),
         }
     }
+
+    // Targets are either an entity number from the room or a raw guid
+    fn resolve_target(&mut self, target: String) -> String {
+        match target.parse::<u32>() {
+            Ok(key) => self.get_entity_guid(key).unwrap_or(target),
+            Err(_) => target,
+        }
+    }
+
+    /// Runs a [`Command`], then refreshes the room and life.
+    pub fn apply(&mut self, command: Command) {
+        match &command {
+            Command::Examine { target } => {
+                log::info!(target: "session", "command=examine target={}", log_target(target))
+            }
+            Command::Attack { target } => {
+                log::info!(target: "session", "command=attack target={}", log_target(target))
+            }
+            _ => log::info!(target: "session", "command={:?}", command),
+        }
+        match command {
+            Command::Connect => self.connect(),
+            Command::Disconnect => self.disconnect(),
+            Command::Look => self.update(),
+            Command::Move { direction } => {
+                self.r#move(direction);
+                self.update();
+            }
+            Command::Examine { target } => {
+                let guid = self.resolve_target(target);
+                self.look_entity(guid);
+                self.update();
+            }
+            Command::Attack { target } => {
+                let guid = self.resolve_target(target);
+                self.attack(guid);
+                self.update();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim;
+
+    use std::fs;
+
+    static BASE_URL: &str = "http://offline";
+
+    fn session(dungeon: &str) -> Session {
+        let path = format!("{}/dungeons/{}", env!("CARGO_MANIFEST_DIR"), dungeon);
+        let world = sim::World::load(path.as_str(), 7).unwrap();
+
+        Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(sim::SimTransport::new(BASE_URL.to_string(), world)),
+        ))
+    }
+
+    // A history file of this test's own, removed by the caller
+    fn history_path(name: &str) -> path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "mungeon-history-{}-{}-{}.jsonl",
+            name,
+            std::process::id(),
+            history::now()
+        ))
+    }
+
+    #[test]
+    fn unexamined_opponents_are_recorded_by_raw_guid() {
+        let mut session = session("dead_end.toml");
+        session.connect();
+        session.r#move(model::Direction::E);
+        session.update();
+        let troll = session.get_entity_guid(1).unwrap();
+        session.attack(troll.clone());
+
+        // Log redaction is on by default, and must not leak into the record
+        let record = session.end_run(history::EndCause::Quit).unwrap();
+        assert_eq!(record.last_opponent, Some(troll));
+    }
+
+    #[test]
+    fn disconnect_command_records_the_run() {
+        let history_path = history_path("disconnect");
+        let mut session = session("dead_end.toml");
+        session.history_path = Some(history_path.clone());
+        session.apply(Command::Connect);
+        session.apply(Command::Move {
+            direction: model::Direction::E,
+        });
+        let guid = session.get_guid().unwrap();
+
+        session.apply(Command::Disconnect);
+        // Nothing is left for a later quit or reconnect to record
+        assert!(session.stats.is_none());
+        assert!(session.end_run(history::EndCause::Quit).is_none());
+        session.apply(Command::Disconnect);
+
+        let lines = fs::read_to_string(&history_path).unwrap().lines().count();
+        let records = history::History::load(&history_path).unwrap().records;
+        let _ = fs::remove_file(&history_path);
+        assert_eq!(lines, 1);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].guid, guid);
+        assert_eq!(records[0].cause, history::EndCause::Disconnect);
+        assert_eq!(records[0].rooms_explored, 2);
+        assert_eq!(records[0].last_room.as_deref(), Some("Dead end"));
+    }
+
+    #[test]
+    fn reconnecting_records_the_previous_run() {
+        let history_path = history_path("reconnect");
+        let mut session = session("dead_end.toml");
+        session.history_path = Some(history_path.clone());
+        session.apply(Command::Connect);
+        let first = session.get_guid().unwrap();
+        session.apply(Command::Connect);
+
+        let records = history::History::load(&history_path).unwrap().records;
+        let _ = fs::remove_file(&history_path);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].guid, first);
+        assert_ne!(session.get_guid().unwrap(), first);
+        assert!(session.stats.is_some());
+    }
 }
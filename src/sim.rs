@@ -0,0 +1,860 @@
+//! An in-process mungeon server, for offline play and repeatable tests.
+//!
+//! A [`World`] is either generated from a seed or loaded from a dungeon file:
+//!
+//! ```toml
+//! spawn = [0, 0]
+//! player_life = 40
+//!
+//! [[rooms]]
+//! x = 0
+//! y = 0
+//! description = "Dead end"
+//! exits = ["E"]
+//!
+//! [[entities]]
+//! description = "A troll"
+//! type = "MONSTRE"
+//! x = 0
+//! y = 0
+//! life = 60
+//! damage = [6, 12]
+//! ```
+//!
+//! The scenarios in `dungeons/` back this module's tests and can be played with `dungeon=`.
+
+use crate::httpd;
+use crate::model;
+use crate::net;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+
+/// How to build an offline world, from the `[offline]` section of the configuration.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct OfflineConfig {
+    pub dungeon: Option<String>,
+    pub width: usize,
+    pub height: usize,
+    pub monsters: usize,
+    pub player_life: u32,
+}
+
+impl Default for OfflineConfig {
+    fn default() -> OfflineConfig {
+        OfflineConfig {
+            dungeon: None,
+            width: 6,
+            height: 6,
+            monsters: 12,
+            player_life: 40,
+        }
+    }
+}
+
+static ADJECTIVES: [&str; 10] = [
+    "Damp",
+    "Dusty",
+    "Narrow",
+    "Vaulted",
+    "Flooded",
+    "Collapsed",
+    "Silent",
+    "Torchlit",
+    "Mossy",
+    "Frozen",
+];
+static PLACES: [&str; 10] = [
+    "corridor", "crypt", "cellar", "hall", "chapel", "cistern", "armory", "library", "cave",
+    "gallery",
+];
+static MONSTERS: [(&str, u32, u32, u32); 5] = [
+    ("rat", 6, 1, 2),
+    ("spider", 10, 2, 4),
+    ("goblin", 14, 2, 5),
+    ("skeleton", 18, 3, 6),
+    ("orc", 26, 4, 8),
+];
+static PLAYER_DAMAGE: (u32, u32) = (3, 8);
+
+static MESSAGE_WALL: &str = "There is a wall in this direction";
+static MESSAGE_DIFF_ROOM: &str = "This entity is not in your room";
+static MESSAGE_DEAD: &str = "You are dead";
+
+static ERROR_DUNGEON_READ: &str = "Could not read dungeon file";
+static ERROR_DUNGEON_PARSE: &str = "Could not parse dungeon file";
+static ERROR_DUPLICATE_ROOM: &str = "Two rooms share a position";
+static ERROR_NO_ROOM: &str = "No room at position";
+static ERROR_DAMAGE_RANGE: &str = "Invalid damage range";
+
+type Position = (i64, i64);
+
+/// A hand-authored dungeon, see the [module documentation](self).
+#[derive(Deserialize, Clone, Debug)]
+pub struct DungeonFile {
+    pub spawn: Position,
+    pub player_life: Option<u32>,
+    pub rooms: Vec<RoomDefinition>,
+    #[serde(default)]
+    pub entities: Vec<EntityDefinition>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RoomDefinition {
+    pub x: i64,
+    pub y: i64,
+    pub description: String,
+    pub exits: Vec<model::Direction>,
+}
+
+/// A monster, or a player that does not act.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EntityDefinition {
+    pub description: String,
+    pub r#type: model::EntityType,
+    pub x: i64,
+    pub y: i64,
+    pub life: u32,
+    pub total_life: Option<u32>,
+    pub damage: (u32, u32),
+}
+
+struct SimRoom {
+    description: String,
+    paths: Vec<model::Direction>,
+}
+
+struct SimEntity {
+    description: String,
+    r#type: model::EntityType,
+    position: Position,
+    life: u32,
+    total_life: u32,
+    damage: (u32, u32),
+}
+
+/// An in-process dungeon with the same rules as a mungeon server.
+pub struct World {
+    rng: StdRng,
+    rooms: HashMap<Position, SimRoom>,
+    entities: HashMap<String, SimEntity>,
+    // Keeps room listings in a stable order
+    order: Vec<String>,
+    spawn: Position,
+    player_life: u32,
+}
+
+fn offset(position: Position, direction: &model::Direction) -> Position {
+    let (x, y) = position;
+    match direction {
+        model::Direction::N => (x, y + 1),
+        model::Direction::S => (x, y - 1),
+        model::Direction::E => (x + 1, y),
+        model::Direction::W => (x - 1, y),
+    }
+}
+
+fn opposite(direction: &model::Direction) -> model::Direction {
+    match direction {
+        model::Direction::N => model::Direction::S,
+        model::Direction::S => model::Direction::N,
+        model::Direction::E => model::Direction::W,
+        model::Direction::W => model::Direction::E,
+    }
+}
+
+impl World {
+    pub fn generate(seed: u64, offline_config: &OfflineConfig) -> World {
+        let width = offline_config.width.max(1) as i64;
+        let height = offline_config.height.max(1) as i64;
+        let mut world = World {
+            rng: StdRng::seed_from_u64(seed),
+            rooms: HashMap::new(),
+            entities: HashMap::new(),
+            order: Vec::new(),
+            spawn: (0, 0),
+            player_life: offline_config.player_life.max(1),
+        };
+
+        let mut used: BTreeSet<String> = BTreeSet::new();
+        for index in 0..width * height {
+            let mut description = format!(
+                "{} {}",
+                ADJECTIVES.choose(&mut world.rng).unwrap_or(&ADJECTIVES[0]),
+                PLACES.choose(&mut world.rng).unwrap_or(&PLACES[0])
+            );
+            let base = description.clone();
+            let mut count = 1;
+            while !used.insert(description.clone()) {
+                count += 1;
+                description = format!("{} {}", base, count);
+            }
+            world.rooms.insert(
+                (index % width, index / width),
+                SimRoom {
+                    description,
+                    paths: Vec::new(),
+                },
+            );
+        }
+        world.carve();
+
+        for _ in 0..offline_config.monsters {
+            let (name, life, min_damage, max_damage) =
+                *MONSTERS.choose(&mut world.rng).unwrap_or(&MONSTERS[0]);
+            // The starting room stays free of monsters
+            let position = match width * height {
+                1 => (0, 0),
+                size => {
+                    let index = world.rng.gen_range(1..size);
+                    (index % width, index / width)
+                }
+            };
+            let guid = world.guid();
+            let description = format!(
+                "A {} {}",
+                ADJECTIVES
+                    .choose(&mut world.rng)
+                    .unwrap_or(&ADJECTIVES[0])
+                    .to_lowercase(),
+                name
+            );
+            world.insert(
+                guid,
+                SimEntity {
+                    description,
+                    r#type: model::EntityType::Monster,
+                    position,
+                    life,
+                    total_life: life,
+                    damage: (min_damage, max_damage),
+                },
+            );
+        }
+        log::info!(
+            target: "sim",
+            "generated seed={} size={}x{} monsters={}",
+            seed,
+            width,
+            height,
+            offline_config.monsters
+        );
+
+        world
+    }
+
+    pub fn load(path: &str, seed: u64) -> Result<World, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{} {}: {}", ERROR_DUNGEON_READ, path, e))?;
+        let dungeon: DungeonFile = toml::from_str(text.as_str())
+            .map_err(|e| format!("{} {}: {}", ERROR_DUNGEON_PARSE, path, e))?;
+
+        World::from_dungeon(dungeon, seed)
+    }
+
+    pub fn from_dungeon(dungeon: DungeonFile, seed: u64) -> Result<World, String> {
+        let mut world = World {
+            rng: StdRng::seed_from_u64(seed),
+            rooms: HashMap::new(),
+            entities: HashMap::new(),
+            order: Vec::new(),
+            spawn: dungeon.spawn,
+            player_life: dungeon
+                .player_life
+                .unwrap_or_else(|| OfflineConfig::default().player_life)
+                .max(1),
+        };
+
+        for room in dungeon.rooms {
+            let position = (room.x, room.y);
+            let previous = world.rooms.insert(
+                position,
+                SimRoom {
+                    description: room.description,
+                    paths: room.exits,
+                },
+            );
+            if previous.is_some() {
+                return Err(format!("{}: got {:?}", ERROR_DUPLICATE_ROOM, position));
+            }
+        }
+
+        let mut required: Vec<Position> = vec![world.spawn];
+        for (position, room) in world.rooms.iter() {
+            required.extend(
+                room.paths
+                    .iter()
+                    .map(|direction| offset(*position, direction)),
+            );
+        }
+        required.extend(dungeon.entities.iter().map(|entity| (entity.x, entity.y)));
+        if let Some(position) = required
+            .into_iter()
+            .find(|position| !world.rooms.contains_key(position))
+        {
+            return Err(format!("{}: got {:?}", ERROR_NO_ROOM, position));
+        }
+
+        for entity in dungeon.entities {
+            if entity.damage.0 > entity.damage.1 {
+                return Err(format!("{}: got {:?}", ERROR_DAMAGE_RANGE, entity.damage));
+            }
+            let guid = world.guid();
+            world.insert(
+                guid,
+                SimEntity {
+                    description: entity.description,
+                    r#type: entity.r#type,
+                    position: (entity.x, entity.y),
+                    life: entity.life,
+                    total_life: entity.total_life.unwrap_or(entity.life),
+                    damage: entity.damage,
+                },
+            );
+        }
+        log::info!(
+            target: "sim",
+            "loaded seed={} rooms={} entities={}",
+            seed,
+            world.rooms.len(),
+            world.order.len()
+        );
+
+        Ok(world)
+    }
+
+    // Randomised depth-first maze, so every room is reachable
+    fn carve(&mut self) {
+        let mut visited: BTreeSet<Position> = BTreeSet::new();
+        let mut stack: Vec<Position> = vec![self.spawn];
+        visited.insert(self.spawn);
+
+        while let Some(&position) = stack.last() {
+            let mut directions = vec![
+                model::Direction::N,
+                model::Direction::E,
+                model::Direction::S,
+                model::Direction::W,
+            ];
+            directions.shuffle(&mut self.rng);
+            let next = directions.into_iter().find_map(|direction| {
+                let next = offset(position, &direction);
+                if self.rooms.contains_key(&next) && !visited.contains(&next) {
+                    Some((direction, next))
+                } else {
+                    None
+                }
+            });
+
+            match next {
+                Some((direction, next)) => {
+                    visited.insert(next);
+                    if let Some(room) = self.rooms.get_mut(&next) {
+                        room.paths.push(opposite(&direction));
+                    }
+                    if let Some(room) = self.rooms.get_mut(&position) {
+                        room.paths.push(direction);
+                    }
+                    stack.push(next);
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    fn guid(&mut self) -> String {
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            self.rng.gen::<u32>(),
+            self.rng.gen::<u16>(),
+            self.rng.gen::<u16>() & 0x0fff | 0x4000,
+            self.rng.gen::<u16>() & 0x3fff | 0x8000,
+            self.rng.gen::<u64>() & 0xffff_ffff_ffff
+        )
+    }
+
+    fn insert(&mut self, guid: String, entity: SimEntity) {
+        self.order.push(guid.clone());
+        self.entities.insert(guid, entity);
+    }
+
+    fn room(&self, position: Position) -> model::Room {
+        let (description, paths) = match self.rooms.get(&position) {
+            Some(room) => (room.description.clone(), room.paths.clone()),
+            None => (String::new(), Vec::new()),
+        };
+
+        model::Room {
+            description,
+            paths,
+            entities: self
+                .order
+                .iter()
+                .filter(|guid| {
+                    self.entities
+                        .get(*guid)
+                        .is_some_and(|entity| entity.position == position && entity.life > 0)
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn entity(&self, guid: &str) -> Option<model::Entity> {
+        self.entities.get(guid).map(|entity| model::Entity {
+            description: entity.description.clone(),
+            r#type: entity.r#type.clone(),
+            life: entity.life,
+            total_life: entity.total_life,
+        })
+    }
+
+    pub fn connect(&mut self) -> model::Status {
+        let guid = self.guid();
+        self.insert(
+            guid.clone(),
+            SimEntity {
+                description: String::from("An adventurer"),
+                r#type: model::EntityType::Player,
+                position: self.spawn,
+                life: self.player_life,
+                total_life: self.player_life,
+                damage: PLAYER_DAMAGE,
+            },
+        );
+
+        model::Status {
+            guid,
+            total_life: self.player_life,
+            life: Some(self.player_life),
+            room: self.room(self.spawn),
+        }
+    }
+
+    fn living_player(&self, guid: &str) -> Result<Position, SimError> {
+        match self.entities.get(guid) {
+            Some(entity) if entity.life == 0 => Err(SimError::Game(model::ErrorType::Dead)),
+            Some(entity) => Ok(entity.position),
+            None => Err(SimError::NotFound),
+        }
+    }
+
+    pub fn look_room(&mut self, guid: &str) -> Result<model::Room, SimError> {
+        let position = self.living_player(guid)?;
+        Ok(self.room(position))
+    }
+
+    pub fn r#move(
+        &mut self,
+        guid: &str,
+        direction: &model::Direction,
+    ) -> Result<model::Room, SimError> {
+        let position = self.living_player(guid)?;
+        let open = self
+            .rooms
+            .get(&position)
+            .is_some_and(|room| room.paths.contains(direction));
+        if !open {
+            return Err(SimError::Game(model::ErrorType::Wall));
+        }
+
+        let next = offset(position, direction);
+        if let Some(entity) = self.entities.get_mut(guid) {
+            entity.position = next;
+        }
+
+        Ok(self.room(next))
+    }
+
+    fn target(&self, guid: &str, guid_dest: &str) -> Result<(), SimError> {
+        let position = self.living_player(guid)?;
+        match self.entities.get(guid_dest) {
+            Some(entity)
+                if entity.position == position && (entity.life > 0 || guid == guid_dest) =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(SimError::Game(model::ErrorType::DiffRoom)),
+            None => Err(SimError::NotFound),
+        }
+    }
+
+    pub fn look_entity(&mut self, guid: &str, guid_dest: &str) -> Result<model::Entity, SimError> {
+        self.target(guid, guid_dest)?;
+        self.entity(guid_dest).ok_or(SimError::NotFound)
+    }
+
+    fn hit(&mut self, guid: &str, victim: &str) -> u32 {
+        let (min_damage, max_damage) = match self.entities.get(guid) {
+            Some(entity) => entity.damage,
+            None => return 0,
+        };
+        let damage = self.rng.gen_range(min_damage..=max_damage);
+        match self.entities.get_mut(victim) {
+            Some(entity) => {
+                let dealt = damage.min(entity.life);
+                entity.life -= dealt;
+                dealt
+            }
+            None => 0,
+        }
+    }
+
+    pub fn attack(&mut self, guid: &str, guid_dest: &str) -> Result<model::Fight, SimError> {
+        self.target(guid, guid_dest)?;
+
+        let dealt = self.hit(guid, guid_dest);
+        let defender_alive = self
+            .entities
+            .get(guid_dest)
+            .is_some_and(|entity| entity.life > 0);
+        let taken = if defender_alive && guid != guid_dest {
+            self.hit(guid_dest, guid)
+        } else {
+            0
+        };
+        let life =
+            |world: &World, guid: &str| world.entities.get(guid).map_or(0, |entity| entity.life);
+
+        Ok(model::Fight {
+            attacker: model::Fighter {
+                guid: guid.to_string(),
+                damage: dealt,
+                life: life(self, guid),
+            },
+            defender: model::Fighter {
+                guid: guid_dest.to_string(),
+                damage: taken,
+                life: life(self, guid_dest),
+            },
+        })
+    }
+}
+
+pub enum SimError {
+    BadRequest,
+    NotFound,
+    Game(model::ErrorType),
+}
+
+fn json<T: Serialize>(value: &T) -> Result<String, SimError> {
+    serde_json::to_string(value).map_err(|_| SimError::BadRequest)
+}
+
+impl World {
+    fn dispatch(&mut self, method: &str, path: &str, body: &str) -> Result<String, SimError> {
+        let segments: Vec<&str> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        match (method, segments.as_slice()) {
+            ("POST", ["connect"]) => json(&self.connect()),
+            ("GET", [guid, "regarder"]) => json(&self.look_room(guid)?),
+            ("POST", [guid, "deplacement"]) => {
+                let movement: serde_json::Value =
+                    serde_json::from_str(body).map_err(|_| SimError::BadRequest)?;
+                let direction: model::Direction =
+                    serde_json::from_value(movement["direction"].clone())
+                        .map_err(|_| SimError::BadRequest)?;
+                json(&self.r#move(guid, &direction)?)
+            }
+            ("GET", [guid, "examiner", guid_dest]) => json(&self.look_entity(guid, guid_dest)?),
+            ("POST", [guid, "taper", guid_dest]) => json(&self.attack(guid, guid_dest)?),
+            _ => Err(SimError::NotFound),
+        }
+    }
+
+    /// Answers a request the way a mungeon server would, `path` being relative to the server root.
+    pub fn handle(&mut self, method: &str, path: &str, body: &str) -> net::RawResponse {
+        match self.dispatch(method, path, body) {
+            Ok(body) => net::RawResponse { status: 200, body },
+            Err(SimError::BadRequest) => net::RawResponse {
+                status: 400,
+                body: String::new(),
+            },
+            Err(SimError::NotFound) => net::RawResponse {
+                status: 404,
+                body: String::new(),
+            },
+            Err(SimError::Game(error_type)) => {
+                let message = match error_type {
+                    model::ErrorType::Wall => MESSAGE_WALL,
+                    model::ErrorType::DiffRoom => MESSAGE_DIFF_ROOM,
+                    model::ErrorType::Dead => MESSAGE_DEAD,
+                };
+                net::RawResponse {
+                    status: 409,
+                    body: json(&model::ErrorDetail {
+                        r#type: Some(error_type),
+                        message: message.to_string(),
+                    })
+                    .unwrap_or_default(),
+                }
+            }
+        }
+    }
+}
+
+/// Serves requests from a [`World`] instead of the network.
+pub struct SimTransport {
+    base_url: String,
+    world: Mutex<World>,
+}
+
+impl SimTransport {
+    pub fn new(base_url: String, world: World) -> SimTransport {
+        SimTransport {
+            base_url,
+            world: Mutex::new(world),
+        }
+    }
+}
+
+impl net::Transport for SimTransport {
+    fn send(&self, request: &net::MunRequest) -> Result<net::RawResponse, String> {
+        let path = net::relative_path(self.base_url.as_str(), request.url());
+
+        match self.world.lock() {
+            Ok(mut world) => Ok(world.handle(request.method(), path.as_str(), request.body())),
+            Err(_) => Err(String::from("world unavailable")),
+        }
+    }
+}
+
+/// Serves a [`World`] over HTTP in the background, as a stand-in server.
+pub fn serve(listener: std::net::TcpListener, world: World) {
+    let world = Mutex::new(world);
+
+    httpd::serve(listener, move |request| match world.lock() {
+        Ok(mut world) => {
+            let response = world.handle(
+                request.method.as_str(),
+                request.path.as_str(),
+                request.body.as_str(),
+            );
+            httpd::Response::json(response.status, response.body)
+        }
+        Err(_) => httpd::Response::error(500, "world unavailable"),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session;
+
+    static BASE_URL: &str = "http://offline";
+
+    fn session(dungeon: &str) -> session::Session {
+        let path = format!("{}/dungeons/{}", env!("CARGO_MANIFEST_DIR"), dungeon);
+        let world = World::load(path.as_str(), 7).unwrap();
+
+        session::Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(SimTransport::new(BASE_URL.to_string(), world)),
+        ))
+    }
+
+    fn error_type(session: &session::Session) -> Option<model::ErrorType> {
+        session
+            .error
+            .as_ref()
+            .and_then(|error| error.detail.r#type.clone())
+    }
+
+    fn room(session: &session::Session) -> String {
+        session.status.as_ref().unwrap().room.description.clone()
+    }
+
+    #[test]
+    fn dead_end_blocks_and_kills() {
+        let mut session = session("dead_end.toml");
+        session.connect();
+        session.update();
+        assert!(session.error.is_none());
+        assert_eq!(room(&session), "Entrance");
+        assert!(session.get_entities_keys().is_empty());
+
+        session.r#move(model::Direction::E);
+        session.update();
+        assert_eq!(room(&session), "Dead end");
+        assert_eq!(session.get_entities_keys(), vec![1]);
+
+        session.r#move(model::Direction::E);
+        assert_eq!(error_type(&session), Some(model::ErrorType::Wall));
+        session.error = None;
+
+        let troll = session.get_entity_guid(1).unwrap();
+        session.look_entity(troll.clone());
+        let examined = session.entity_info.take().unwrap();
+        assert_eq!(examined.description, "A troll");
+        assert_eq!(examined.r#type, model::EntityType::Monster);
+
+        // 30 life against 6 to 12 per hit, while the troll survives at least 8 hits
+        for _ in 0..5 {
+            session.attack(troll.clone());
+            session.update();
+            if session.error.is_some() {
+                break;
+            }
+        }
+        assert_eq!(error_type(&session), Some(model::ErrorType::Dead));
+        assert_eq!(session.fight_info.as_ref().unwrap().attacker.life, 0);
+    }
+
+    #[test]
+    fn auto_update_in_dead_end_is_stable() {
+        let mut session = session("dead_end.toml");
+        session.connect();
+        session.r#move(model::Direction::E);
+
+        for _ in 0..3 {
+            session.update();
+        }
+        assert!(session.error.is_none());
+        assert_eq!(room(&session), "Dead end");
+        assert_eq!(session.status.as_ref().unwrap().life, Some(30));
+        assert!(session.under_attack.is_none());
+        assert!(session.take_events().is_empty());
+    }
+
+    #[test]
+    fn two_players_share_the_hall() {
+        let mut session = session("two_players.toml");
+        session.connect();
+        session.update();
+        assert_eq!(room(&session), "Hall");
+        assert_eq!(session.get_entities_keys(), vec![1, 2]);
+
+        let mut descriptions = Vec::new();
+        for key in session.get_entities_keys() {
+            let guid = session.get_entity_guid(key).unwrap();
+            session.look_entity(guid);
+            let examined = session.entity_info.take().unwrap();
+            assert_eq!(examined.r#type, model::EntityType::Player);
+            descriptions.push(examined.description);
+        }
+        descriptions.sort();
+        assert_eq!(
+            descriptions,
+            vec!["A sleeping adventurer", "A wounded knight"]
+        );
+        assert_eq!(session.examined.len(), 2);
+
+        // Clients share the transport, so a second player joins the same world
+        let mut other = session::Session::with_client(session.client.clone());
+        other.connect();
+        session.update();
+        assert_eq!(session.get_entities_keys(), vec![1, 2, 3]);
+        assert_eq!(session.get_entity_guid(3), other.get_guid().ok());
+
+        session.r#move(model::Direction::N);
+        assert_eq!(error_type(&session), Some(model::ErrorType::Wall));
+    }
+
+    #[test]
+    fn hits_from_others_are_seen_during_own_fight() {
+        let mut session = session("two_players.toml");
+        session.connect();
+        session.update();
+        let mut other = session::Session::with_client(session.client.clone());
+        other.connect();
+        session.update();
+        let knight = session.get_entity_guid(2).unwrap();
+
+        session.attack(knight);
+        let taken = session.fight_info.as_ref().unwrap().defender.damage;
+        other.attack(session.get_guid().unwrap());
+        let hit = other.fight_info.as_ref().unwrap().attacker.damage;
+        session.update();
+
+        let life = session.status.as_ref().unwrap().life.unwrap();
+        assert_eq!(life, 40 - taken - hit);
+        let damaged: Vec<u32> = session
+            .take_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                session::SessionEvent::Damaged { amount, .. } => Some(amount),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(damaged, vec![hit]);
+        assert_eq!(session.under_attack.as_ref().unwrap().total_loss, hit);
+    }
+
+    #[test]
+    fn adjacent_rooms_sharing_a_description_are_told_apart() {
+        let dungeon: DungeonFile = toml::from_str(
+            r#"
+            spawn = [0, 0]
+
+            [[rooms]]
+            x = 0
+            y = 0
+            description = "A damp cave"
+            exits = ["E"]
+
+            [[rooms]]
+            x = 1
+            y = 0
+            description = "A damp cave"
+            exits = ["W"]
+
+            [[entities]]
+            description = "A rat"
+            type = "MONSTRE"
+            x = 0
+            y = 0
+            life = 5
+            damage = [1, 1]
+
+            [[entities]]
+            description = "A bat"
+            type = "MONSTRE"
+            x = 1
+            y = 0
+            life = 5
+            damage = [1, 1]
+        "#,
+        )
+        .unwrap();
+        let world = World::from_dungeon(dungeon, 7).unwrap();
+        let mut session = session::Session::with_client(net::MunHttpClient::with_transport(
+            BASE_URL.to_string(),
+            Box::new(SimTransport::new(BASE_URL.to_string(), world)),
+        ));
+        session.connect();
+        session.update();
+        let rat = session.get_entity_guid(1).unwrap();
+        session.look_entity(rat.clone());
+        assert_eq!(session.examined.len(), 1);
+        session.take_events();
+
+        session.r#move(model::Direction::E);
+        session.update();
+        assert_eq!(room(&session), "A damp cave");
+        assert_eq!(session.position, (1, 0));
+        assert_eq!(session.rooms_entered, 2);
+        assert!(session.examined.is_empty());
+        assert_ne!(session.get_entity_guid(1), Some(rat));
+        // The bat was there before us and the rat did not leave its room
+        assert!(session.take_events().is_empty());
+
+        session.r#move(model::Direction::W);
+        assert_eq!(session.position, (0, 0));
+        assert_eq!(session.rooms_entered, 3);
+        assert_eq!(session.stats.as_ref().unwrap().rooms.len(), 2);
+    }
+}
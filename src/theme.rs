@@ -0,0 +1,256 @@
+use crate::config;
+
+use tui::style;
+
+use std::env;
+
+static ERROR_UNKNOWN_THEME: &str = "Unknown theme";
+static ERROR_THEME_CYCLE: &str = "Theme inherits from itself";
+static ERROR_COLOR_PARSE: &str = "Could not parse color";
+static ERROR_MODIFIER_PARSE: &str = "Could not parse style modifier";
+
+static DEFAULT_THEME: &str = "dark";
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub base: style::Style,
+    pub popup: style::Style,
+    pub good: style::Style,
+    pub bad: style::Style,
+    pub title: style::Style,
+    pub dungeon: style::Style,
+    pub highlight: style::Style,
+}
+
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            base: Theme::colored(style::Color::Reset, style::Color::Reset),
+            popup: Theme::colored(style::Color::Reset, style::Color::Reset),
+            good: Theme::colored(style::Color::Black, style::Color::Green),
+            bad: Theme::colored(style::Color::White, style::Color::Red),
+            title: Theme::colored(style::Color::White, style::Color::Blue),
+            dungeon: Theme::colored(style::Color::White, style::Color::Blue),
+            highlight: style::Style::default().add_modifier(style::Modifier::BOLD),
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            base: Theme::colored(style::Color::Black, style::Color::White),
+            popup: Theme::colored(style::Color::Black, style::Color::Gray),
+            good: Theme::colored(style::Color::Black, style::Color::LightGreen),
+            bad: Theme::colored(style::Color::White, style::Color::Red),
+            title: Theme::colored(style::Color::White, style::Color::Blue),
+            dungeon: Theme::colored(style::Color::Black, style::Color::LightCyan),
+            highlight: Theme::colored(style::Color::White, style::Color::Blue)
+                .add_modifier(style::Modifier::BOLD),
+        }
+    }
+
+    pub fn high_contrast() -> Theme {
+        Theme {
+            base: Theme::colored(style::Color::White, style::Color::Black),
+            popup: Theme::colored(style::Color::White, style::Color::Black),
+            good: Theme::colored(style::Color::Black, style::Color::LightGreen)
+                .add_modifier(style::Modifier::BOLD),
+            bad: Theme::colored(style::Color::Black, style::Color::Yellow)
+                .add_modifier(style::Modifier::BOLD),
+            title: Theme::colored(style::Color::Black, style::Color::White)
+                .add_modifier(style::Modifier::BOLD),
+            dungeon: Theme::colored(style::Color::White, style::Color::Black)
+                .add_modifier(style::Modifier::BOLD),
+            highlight: Theme::colored(style::Color::Black, style::Color::Yellow)
+                .add_modifier(style::Modifier::BOLD),
+        }
+    }
+
+    pub fn monochrome() -> Theme {
+        Theme {
+            base: style::Style::default(),
+            popup: style::Style::default(),
+            good: style::Style::default().add_modifier(style::Modifier::BOLD),
+            bad: style::Style::default()
+                .add_modifier(style::Modifier::BOLD | style::Modifier::REVERSED),
+            title: style::Style::default().add_modifier(style::Modifier::UNDERLINED),
+            dungeon: style::Style::default(),
+            highlight: style::Style::default().add_modifier(style::Modifier::REVERSED),
+        }
+    }
+
+    pub fn built_in(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            "monochrome" => Some(Theme::monochrome()),
+            _ => None,
+        }
+    }
+
+    // https://no-color.org: any non-empty value disables colors
+    pub fn no_color() -> bool {
+        match env::var_os("NO_COLOR") {
+            Some(value) => !value.is_empty(),
+            None => false,
+        }
+    }
+
+    pub fn from_config(config: &config::Config, requested: Option<&str>) -> Result<Theme, String> {
+        // Not resolved through the config, a [themes.monochrome] could bring colors back
+        if Theme::no_color() {
+            return Ok(Theme::monochrome());
+        }
+
+        let name = requested
+            .or(config.theme.as_deref())
+            .unwrap_or(DEFAULT_THEME);
+
+        Theme::resolve(config, name, Vec::new())
+    }
+
+    fn resolve(
+        config: &config::Config,
+        name: &str,
+        mut seen: Vec<String>,
+    ) -> Result<Theme, String> {
+        if seen.iter().any(|seen_name| seen_name == name) {
+            return Err(format!("{}: got \"{}\"", ERROR_THEME_CYCLE, name));
+        }
+        seen.push(name.to_string());
+
+        let theme_config = match config.themes.get(name) {
+            Some(theme_config) => theme_config,
+            None => {
+                return Theme::built_in(name)
+                    .ok_or_else(|| format!("{}: got \"{}\"", ERROR_UNKNOWN_THEME, name))
+            }
+        };
+
+        let mut theme = match &theme_config.inherits {
+            Some(parent) => Theme::resolve(config, parent.as_str(), seen)?,
+            None => Theme::built_in(name).unwrap_or_else(Theme::dark),
+        };
+
+        Theme::apply(&mut theme.base, &theme_config.base)?;
+        Theme::apply(&mut theme.popup, &theme_config.popup)?;
+        Theme::apply(&mut theme.good, &theme_config.good)?;
+        Theme::apply(&mut theme.bad, &theme_config.bad)?;
+        Theme::apply(&mut theme.title, &theme_config.title)?;
+        Theme::apply(&mut theme.dungeon, &theme_config.dungeon)?;
+        Theme::apply(&mut theme.highlight, &theme_config.highlight)?;
+
+        Ok(theme)
+    }
+
+    fn apply(
+        target: &mut style::Style,
+        style_config: &Option<config::StyleConfig>,
+    ) -> Result<(), String> {
+        if let Some(style_config) = style_config {
+            let mut parsed = style::Style::default();
+
+            if let Some(fg) = &style_config.fg {
+                parsed = parsed.fg(Theme::parse_color(fg.as_str())?);
+            }
+            if let Some(bg) = &style_config.bg {
+                parsed = parsed.bg(Theme::parse_color(bg.as_str())?);
+            }
+            for modifier in style_config.modifiers.iter() {
+                parsed = parsed.add_modifier(Theme::parse_modifier(modifier.as_str())?);
+            }
+
+            *target = parsed;
+        }
+
+        Ok(())
+    }
+
+    fn parse_color(data: &str) -> Result<style::Color, String> {
+        let error = || format!("{}: got \"{}\"", ERROR_COLOR_PARSE, data);
+
+        if let Some(hex) = data.strip_prefix('#') {
+            // Checked before slicing, as a non-ASCII char would split across channels
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(error());
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| error());
+            return Ok(style::Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+
+        if let Ok(index) = data.parse::<u8>() {
+            return Ok(style::Color::Indexed(index));
+        }
+
+        match data
+            .to_lowercase()
+            .replace(&['-', '_', ' '][..], "")
+            .as_str()
+        {
+            "reset" | "default" => Ok(style::Color::Reset),
+            "black" => Ok(style::Color::Black),
+            "red" => Ok(style::Color::Red),
+            "green" => Ok(style::Color::Green),
+            "yellow" => Ok(style::Color::Yellow),
+            "blue" => Ok(style::Color::Blue),
+            "magenta" => Ok(style::Color::Magenta),
+            "cyan" => Ok(style::Color::Cyan),
+            "gray" | "grey" => Ok(style::Color::Gray),
+            "darkgray" | "darkgrey" => Ok(style::Color::DarkGray),
+            "lightred" => Ok(style::Color::LightRed),
+            "lightgreen" => Ok(style::Color::LightGreen),
+            "lightyellow" => Ok(style::Color::LightYellow),
+            "lightblue" => Ok(style::Color::LightBlue),
+            "lightmagenta" => Ok(style::Color::LightMagenta),
+            "lightcyan" => Ok(style::Color::LightCyan),
+            "white" => Ok(style::Color::White),
+            _ => Err(error()),
+        }
+    }
+
+    fn parse_modifier(data: &str) -> Result<style::Modifier, String> {
+        match data.to_lowercase().as_str() {
+            "bold" => Ok(style::Modifier::BOLD),
+            "dim" => Ok(style::Modifier::DIM),
+            "italic" => Ok(style::Modifier::ITALIC),
+            "underlined" | "underline" => Ok(style::Modifier::UNDERLINED),
+            "slow_blink" | "blink" => Ok(style::Modifier::SLOW_BLINK),
+            "rapid_blink" => Ok(style::Modifier::RAPID_BLINK),
+            "reversed" | "reverse" => Ok(style::Modifier::REVERSED),
+            "hidden" => Ok(style::Modifier::HIDDEN),
+            "crossed_out" => Ok(style::Modifier::CROSSED_OUT),
+            _ => Err(format!("{}: got \"{}\"", ERROR_MODIFIER_PARSE, data)),
+        }
+    }
+
+    pub fn dungeon_fg(&self) -> style::Color {
+        self.dungeon.fg.unwrap_or(style::Color::Reset)
+    }
+
+    pub fn dungeon_bg(&self) -> style::Color {
+        self.dungeon.bg.unwrap_or(style::Color::Reset)
+    }
+
+    fn colored(fg: style::Color, bg: style::Color) -> style::Style {
+        style::Style::default().fg(fg).bg(bg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_colors_are_parsed() {
+        assert_eq!(
+            Theme::parse_color("#1a2B3c"),
+            Ok(style::Color::Rgb(0x1a, 0x2b, 0x3c))
+        );
+        for data in ["#aéaaa", "#12345", "#1234567", "#12345g", "#+12345"] {
+            assert_eq!(
+                Theme::parse_color(data),
+                Err(format!("{}: got \"{}\"", ERROR_COLOR_PARSE, data))
+            );
+        }
+    }
+}